@@ -0,0 +1,8 @@
+CREATE TABLE sessions(
+    session_id TEXT NOT NULL,
+    user_id uuid NOT NULL
+    REFERENCES users (user_id) ON DELETE CASCADE,
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL,
+    PRIMARY KEY (session_id)
+);
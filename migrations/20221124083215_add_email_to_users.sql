@@ -0,0 +1,3 @@
+-- Admins created before this migration have no email and cannot reset
+-- their password until one is set.
+ALTER TABLE users ADD COLUMN email TEXT NULL UNIQUE;
@@ -0,0 +1,11 @@
+CREATE TABLE password_reset_tokens(
+    -- Only a SHA-256 digest of the token is stored: a leaked table cannot be
+    -- used to reset anyone's password.
+    token_hash TEXT NOT NULL,
+    user_id uuid NOT NULL
+    REFERENCES users (user_id) ON DELETE CASCADE,
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL,
+    used_at timestamptz NULL,
+    PRIMARY KEY (token_hash)
+);
@@ -0,0 +1,8 @@
+BEGIN;
+-- Outstanding links keep working: they are looked up by the digest of the
+-- token they carry, which is exactly what we store here.
+UPDATE subscription_tokens
+SET subscription_token = encode(sha256(convert_to(subscription_token, 'UTF8')), 'hex');
+ALTER TABLE subscription_tokens
+    RENAME COLUMN subscription_token TO subscription_token_hash;
+COMMIT;
@@ -0,0 +1,15 @@
+CREATE TABLE email_events (
+    id uuid NOT NULL,
+    provider TEXT NOT NULL,
+    -- Providers deliver events at least once: the same event must only be
+    -- stored once.
+    provider_event_id TEXT NOT NULL,
+    event_type TEXT NOT NULL,
+    email TEXT NOT NULL,
+    occurred_at timestamptz NOT NULL,
+    payload jsonb NOT NULL,
+    received_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (id),
+    UNIQUE (provider, provider_event_id)
+);
+CREATE INDEX email_events_email_idx ON email_events (email);
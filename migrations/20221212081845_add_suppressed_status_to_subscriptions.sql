@@ -0,0 +1,4 @@
+ALTER TABLE subscriptions ADD COLUMN suppressed_at timestamptz NULL;
+ALTER TABLE subscriptions DROP CONSTRAINT subscriptions_status_check;
+ALTER TABLE subscriptions ADD CONSTRAINT subscriptions_status_check
+    CHECK (status IN ('pending_confirmation', 'confirmed', 'unsubscribed', 'suppressed'));
@@ -0,0 +1,16 @@
+CREATE TABLE suppressions (
+    email TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    -- `admin` for manual entries, otherwise the provider that reported the
+    -- address.
+    source TEXT NOT NULL,
+    created_at timestamptz NOT NULL,
+    PRIMARY KEY (email)
+);
+
+-- Addresses suppressed by the provider webhooks so far.
+INSERT INTO suppressions (email, reason, source, created_at)
+SELECT DISTINCT ON (email) email, event_type, provider, received_at
+FROM email_events
+WHERE event_type IN ('hard_bounce', 'spam_report')
+ORDER BY email, received_at;
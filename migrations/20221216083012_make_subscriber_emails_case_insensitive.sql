@@ -0,0 +1,45 @@
+-- Emails are only compared case-insensitively once they are written the way
+-- `SubscriberEmail` writes them: trimmed, with their domain lowercased and
+-- IDNA-encoded. `cargo run --bin canonicalize_emails` rewrites them.
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM subscriptions WHERE email !~ '^\S.*@[a-z0-9.-]+$')
+        OR EXISTS (SELECT 1 FROM suppressions WHERE email !~ '^\S.*@[a-z0-9.-]+$')
+    THEN
+        RAISE EXCEPTION 'Some emails are not in canonical form. Run `cargo run --bin canonicalize_emails`, then run the migration again.';
+    END IF;
+END $$;
+
+-- Emails that only differ by case belong to the same person, yet they were
+-- stored as different subscribers. Which one to keep is for a human to
+-- decide: list them all, and stop until they have been merged.
+DO $$
+DECLARE
+    collisions TEXT;
+BEGIN
+    SELECT string_agg(format('%s: %s', email, subscribers), E'\n' ORDER BY email)
+    INTO collisions
+    FROM (
+        SELECT
+            lower(email) AS email,
+            string_agg(
+                format('%s (%s, %s)', email, id, status), ', ' ORDER BY subscribed_at
+            ) AS subscribers
+        FROM subscriptions
+        GROUP BY lower(email)
+        HAVING count(*) > 1
+    ) c;
+    IF collisions IS NOT NULL THEN
+        RAISE EXCEPTION E'Some emails belong to several subscribers. Merge them, then run the migration again.\n%', collisions;
+    END IF;
+END $$;
+
+ALTER TABLE subscriptions DROP CONSTRAINT subscriptions_email_key;
+CREATE UNIQUE INDEX subscriptions_email_key ON subscriptions (lower(email));
+
+-- The same address must not be suppressed twice either: keep the first entry.
+DELETE FROM suppressions s
+USING suppressions earlier
+WHERE lower(s.email) = lower(earlier.email)
+    AND (earlier.created_at, earlier.email) < (s.created_at, s.email);
+CREATE UNIQUE INDEX suppressions_email_lower_key ON suppressions (lower(email));
@@ -0,0 +1,8 @@
+-- Mailgun signs every call with a new random token: the tokens seen so far
+-- tell a captured call replayed within the signature window apart. They are
+-- dropped once that window is over.
+CREATE TABLE mailgun_webhook_tokens (
+    token TEXT NOT NULL,
+    expires_at timestamptz NOT NULL,
+    PRIMARY KEY (token)
+);
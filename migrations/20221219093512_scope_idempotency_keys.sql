@@ -0,0 +1,11 @@
+-- Keys were global: a client reusing the key of another got its saved
+-- response instead of having its request processed. Keys now belong to a
+-- scope, the endpoint and the user for authenticated ones.
+--
+-- Saved responses are only kept for a day, and those saved so far cannot be
+-- told apart by endpoint: they are dropped.
+DELETE FROM idempotency;
+ALTER TABLE idempotency ADD COLUMN scope TEXT NOT NULL;
+ALTER TABLE idempotency DROP CONSTRAINT idempotency_pkey;
+ALTER TABLE idempotency ADD PRIMARY KEY (scope, idempotency_key);
+CREATE INDEX idempotency_created_at_idx ON idempotency (created_at);
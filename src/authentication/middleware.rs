@@ -0,0 +1,58 @@
+use std::ops::Deref;
+
+use axum::{
+    http::{Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+    Extension,
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::session_state::{get_session_user_id, SignedCookies};
+
+/// The id of the logged-in user, available to every handler behind
+/// [`reject_anonymous_users`].
+#[derive(Copy, Clone, Debug)]
+pub struct UserId(Uuid);
+
+impl std::fmt::Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Deref for UserId {
+    type Target = Uuid;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Redirect requests without a valid session to the login page.
+pub async fn reject_anonymous_users<B>(
+    cookies: SignedCookies,
+    Extension(pool): Extension<PgPool>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let user_id = match cookies.session_id() {
+        Some(session_id) => match get_session_user_id(&pool, &session_id).await {
+            Ok(user_id) => user_id,
+            Err(e) => {
+                tracing::error!(error.cause_chain = ?e, error.message = %e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        },
+        None => None,
+    };
+
+    match user_id {
+        Some(user_id) => {
+            request.extensions_mut().insert(UserId(user_id));
+            next.run(request).await
+        }
+        None => Redirect::to("/login").into_response(),
+    }
+}
@@ -0,0 +1,287 @@
+use anyhow::Context;
+use argon2::{
+    password_hash::SaltString, Algorithm, Argon2, Params, PasswordHash, PasswordHasher,
+    PasswordVerifier, Version,
+};
+use axum::http::{header, HeaderMap};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::{domain::SubscriberEmail, telemetry::spawn_blocking_with_tracing};
+
+const MIN_PASSWORD_LENGTH: usize = 12;
+const MAX_PASSWORD_LENGTH: usize = 128;
+
+/// A valid hash, computed with the current parameters, that we verify against
+/// when the username is unknown. It keeps the response time of a failed login
+/// independent of whether the user exists.
+const DUMMY_PASSWORD_HASH: &str = "$argon2id$v=19$m=15000,t=2,p=1$\
+    gZiV/M1gPc22ElAH/Jh1Hw$\
+    CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno";
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthError {
+    #[error("Invalid credentials.")]
+    InvalidCredentials(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+pub struct Credentials {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+/// Read the credentials of the `Authorization` header, for `Basic` auth.
+pub fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
+    // The header value, if present, must be a valid UTF8 string
+    let header_value = headers
+        .get(header::AUTHORIZATION)
+        .context("The 'Authorization' header was missing")?
+        .to_str()
+        .context("The 'Authorization' header was not a valid UTF8 string.")?;
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .context("The authorization scheme was not 'Basic'.")?;
+    let decoded_bytes = base64::decode_config(base64encoded_segment, base64::STANDARD)
+        .context("Failed to base64-decode 'Basic' credentials.")?;
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .context("The decoded credential string is not valid UTF8.")?;
+
+    // Split into two segments, using ':' as delimitator
+    let mut credentials = decoded_credentials.splitn(2, ':');
+    let username = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("A username must be provided in 'Basic' auth."))?
+        .to_string();
+    let password = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("A password must be provided in 'Basic' auth."))?
+        .to_string();
+
+    Ok(Credentials {
+        username,
+        password: Secret::new(password),
+    })
+}
+
+/// The parameters new hashes are computed with.
+///
+/// Hashes are stored as PHC strings, which embed the parameters they were
+/// computed with: bumping these upgrades every user on their next login.
+fn current_params() -> Params {
+    Params::new(15000, 2, 1, None).expect("Invalid Argon2 parameters")
+}
+
+/// Check a new password against the length policy, returning a message that
+/// can be shown to the user when it is rejected.
+pub fn check_password_policy(password: &Secret<String>) -> Result<(), String> {
+    let length = password.expose_secret().chars().count();
+    if length < MIN_PASSWORD_LENGTH {
+        return Err(format!(
+            "The new password must be at least {} characters long.",
+            MIN_PASSWORD_LENGTH
+        ));
+    }
+    if length > MAX_PASSWORD_LENGTH {
+        return Err(format!(
+            "The new password must be at most {} characters long.",
+            MAX_PASSWORD_LENGTH
+        ));
+    }
+    Ok(())
+}
+
+#[tracing::instrument(name = "Get stored credentials", skip(username, pool))]
+async fn get_stored_credentials(
+    username: &str,
+    pool: &PgPool,
+) -> Result<Option<(Uuid, Secret<String>)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT user_id, password_hash FROM users WHERE username = $1"#,
+        username,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve stored credentials.")?
+    .map(|row| (row.user_id, Secret::new(row.password_hash)));
+
+    Ok(row)
+}
+
+#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    pool: &PgPool,
+) -> Result<Uuid, AuthError> {
+    let mut user_id = None;
+    let mut expected_password_hash = Secret::new(DUMMY_PASSWORD_HASH.to_string());
+
+    if let Some((stored_user_id, stored_password_hash)) =
+        get_stored_credentials(&credentials.username, pool).await?
+    {
+        user_id = Some(stored_user_id);
+        expected_password_hash = stored_password_hash;
+    }
+
+    let password_candidate = credentials.password.clone();
+    let needs_rehash = spawn_blocking_with_tracing(move || {
+        verify_password_hash(expected_password_hash, password_candidate)
+    })
+    .await
+    .context("Failed to spawn blocking task.")??;
+
+    let user_id = user_id
+        .ok_or_else(|| anyhow::anyhow!("Unknown username."))
+        .map_err(AuthError::InvalidCredentials)?;
+
+    if needs_rehash {
+        if let Err(e) = change_password(user_id, credentials.password, pool).await {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                "Failed to upgrade the stored password hash"
+            );
+        }
+    }
+
+    Ok(user_id)
+}
+
+/// Returns whether the stored hash should be recomputed with the current
+/// parameters.
+#[tracing::instrument(
+    name = "Verify password hash",
+    skip(expected_password_hash, password_candidate)
+)]
+fn verify_password_hash(
+    expected_password_hash: Secret<String>,
+    password_candidate: Secret<String>,
+) -> Result<bool, AuthError> {
+    let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
+        .context("Failed to parse hash in PHC string format.")?;
+
+    Argon2::default()
+        .verify_password(
+            password_candidate.expose_secret().as_bytes(),
+            &expected_password_hash,
+        )
+        .context("Invalid password.")
+        .map_err(AuthError::InvalidCredentials)?;
+
+    Ok(!uses_current_parameters(&expected_password_hash))
+}
+
+fn uses_current_parameters(password_hash: &PasswordHash) -> bool {
+    password_hash.algorithm == Algorithm::Argon2id.ident()
+        && password_hash.version == Some(Version::V0x13 as u32)
+        && Params::try_from(password_hash)
+            .map(|params| {
+                let current = current_params();
+                params.m_cost() == current.m_cost()
+                    && params.t_cost() == current.t_cost()
+                    && params.p_cost() == current.p_cost()
+            })
+            .unwrap_or(false)
+}
+
+pub fn compute_password_hash(password: Secret<String>) -> Result<Secret<String>, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, current_params())
+        .hash_password(password.expose_secret().as_bytes(), &salt)?
+        .to_string();
+
+    Ok(Secret::new(password_hash))
+}
+
+#[tracing::instrument(name = "Change password", skip(password, executor))]
+pub async fn change_password<'c>(
+    user_id: Uuid,
+    password: Secret<String>,
+    executor: impl PgExecutor<'c>,
+) -> Result<(), anyhow::Error> {
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+        .await?
+        .context("Failed to hash password")?;
+
+    sqlx::query!(
+        r#"UPDATE users SET password_hash = $1 WHERE user_id = $2"#,
+        password_hash.expose_secret(),
+        user_id,
+    )
+    .execute(executor)
+    .await
+    .context("Failed to change user's password in the database.")?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Create user", skip(password, email, pool))]
+pub async fn create_user(
+    username: &str,
+    email: Option<&SubscriberEmail>,
+    password: Secret<String>,
+    pool: &PgPool,
+) -> Result<Uuid, anyhow::Error> {
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+        .await?
+        .context("Failed to hash password")?;
+
+    let user_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"INSERT INTO users (user_id, username, email, password_hash) VALUES ($1, $2, $3, $4)"#,
+        user_id,
+        username,
+        email.map(|e| e.as_ref()),
+        password_hash.expose_secret(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the new user in the database.")?;
+
+    Ok(user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use argon2::PasswordHash;
+    use secrecy::{ExposeSecret, Secret};
+
+    use super::{
+        check_password_policy, compute_password_hash, uses_current_parameters, DUMMY_PASSWORD_HASH,
+    };
+
+    #[test]
+    fn new_hashes_use_the_current_parameters() {
+        let hash = compute_password_hash(Secret::new("a password".to_string())).unwrap();
+        let hash = PasswordHash::new(hash.expose_secret()).unwrap();
+        assert!(uses_current_parameters(&hash));
+    }
+
+    #[test]
+    fn the_dummy_hash_uses_the_current_parameters() {
+        let hash = PasswordHash::new(DUMMY_PASSWORD_HASH).unwrap();
+        assert!(uses_current_parameters(&hash));
+    }
+
+    #[test]
+    fn hashes_with_outdated_parameters_are_detected() {
+        let hash = "$argon2id$v=19$m=4096,t=3,p=1$\
+            MTIzNDU2Nzg$\
+            kp0Ptz7QbaY9KScoJqumsfGFUl6QzddOBJN+4s6cBYs";
+        let hash = PasswordHash::new(hash).unwrap();
+        assert!(!uses_current_parameters(&hash));
+    }
+
+    #[test]
+    fn passwords_outside_the_length_policy_are_rejected() {
+        assert!(check_password_policy(&Secret::new("a".repeat(11))).is_err());
+        assert!(check_password_policy(&Secret::new("a".repeat(129))).is_err());
+    }
+
+    #[test]
+    fn the_length_policy_counts_characters_not_bytes() {
+        assert!(check_password_policy(&Secret::new("ü".repeat(12))).is_ok());
+        assert!(check_password_policy(&Secret::new("ü".repeat(128))).is_ok());
+    }
+}
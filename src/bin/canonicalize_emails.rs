@@ -0,0 +1,44 @@
+//! Rewrite the stored emails the way the application now writes them:
+//! trimmed, with their domain lowercased and IDNA-encoded.
+//!
+//! Usage: `cargo run --bin canonicalize_emails`
+//!
+//! Run it before the migration making emails case-insensitive. Subscribers
+//! (or admins) that would share an email are listed and left as they are:
+//! merge them, then run it again.
+
+use axum_zero2prod::{
+    canonical_emails::canonicalize_stored_emails, configurations::get_configuration,
+    startup::get_connection_pool,
+};
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    let configuration = get_configuration()?;
+    let pool = get_connection_pool(&configuration.database);
+    let report = canonicalize_stored_emails(&pool).await?;
+
+    println!("Rewrote {} emails", report.n_rewritten);
+    if report.n_duplicate_suppressions > 0 {
+        println!(
+            "Dropped {} suppressions of addresses already suppressed",
+            report.n_duplicate_suppressions
+        );
+    }
+    for email in &report.invalid {
+        println!("Left an invalid email as is: {}", email);
+    }
+    for group in &report.admin_collisions {
+        println!("Admins sharing an email: {}", group.join(", "));
+    }
+    for group in &report.subscriber_collisions {
+        println!("Subscribers sharing an email: {}", group.join(", "));
+    }
+    if !report.subscriber_collisions.is_empty() {
+        anyhow::bail!(
+            "Some emails belong to several subscribers. Merge them, then run this again."
+        );
+    }
+
+    Ok(())
+}
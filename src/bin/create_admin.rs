@@ -0,0 +1,48 @@
+//! Seed an admin account.
+//!
+//! Usage: `cargo run --bin create_admin -- <username> [email]`
+//!
+//! The email is where password reset links are sent.
+//!
+//! The password is read from the `ADMIN_PASSWORD` environment variable or, if
+//! unset, from the first line of stdin. It never appears in the shell history.
+
+use std::io::BufRead;
+
+use axum_zero2prod::{
+    authentication::{check_password_policy, create_user},
+    configurations::get_configuration,
+    domain::SubscriberEmail,
+    startup::get_connection_pool,
+};
+use secrecy::Secret;
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    let username = std::env::args()
+        .nth(1)
+        .ok_or_else(|| anyhow::anyhow!("Usage: create_admin <username> [email]"))?;
+    let email = std::env::args()
+        .nth(2)
+        .map(SubscriberEmail::parse)
+        .transpose()
+        .map_err(anyhow::Error::msg)?;
+
+    let password = match std::env::var("ADMIN_PASSWORD") {
+        Ok(password) => Secret::new(password),
+        Err(_) => {
+            eprintln!("Password for {}:", username);
+            let mut line = String::new();
+            std::io::stdin().lock().read_line(&mut line)?;
+            Secret::new(line.trim_end_matches(['\r', '\n']).to_string())
+        }
+    };
+    check_password_policy(&password).map_err(anyhow::Error::msg)?;
+
+    let configuration = get_configuration()?;
+    let pool = get_connection_pool(&configuration.database);
+    let user_id = create_user(&username, email.as_ref(), password, &pool).await?;
+    println!("Created admin {} with id {}", username, user_id);
+
+    Ok(())
+}
@@ -0,0 +1,224 @@
+//! Rewrite the emails stored before [`SubscriberEmail`] canonicalized them.
+
+use std::collections::HashSet;
+
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+
+use crate::domain::SubscriberEmail;
+
+/// What [`canonicalize_stored_emails`] did, and what it left to an admin.
+#[derive(Default)]
+pub struct CanonicalizationReport {
+    /// How many emails were rewritten.
+    pub n_rewritten: usize,
+    /// How many suppressions were dropped, the same address being already
+    /// suppressed.
+    pub n_duplicate_suppressions: usize,
+    /// The stored emails [`SubscriberEmail`] rejects, left as they are.
+    pub invalid: Vec<String>,
+    /// The subscribers that would share an email, one group per email. They
+    /// are left as they are: which one to keep is for a human to decide.
+    pub subscriber_collisions: Vec<Vec<String>>,
+    /// The same for admins.
+    pub admin_collisions: Vec<Vec<String>>,
+}
+
+/// Write the emails of subscribers, admins and suppressions the way
+/// [`SubscriberEmail::parse`] does, and their pending deliveries along.
+pub async fn canonicalize_stored_emails(
+    pool: &PgPool,
+) -> Result<CanonicalizationReport, anyhow::Error> {
+    let mut report = CanonicalizationReport::default();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    canonicalize_subscribers(&mut transaction, &mut report)
+        .await
+        .context("Failed to canonicalize the emails of subscribers")?;
+    canonicalize_admins(&mut transaction, &mut report)
+        .await
+        .context("Failed to canonicalize the emails of admins")?;
+    canonicalize_suppressions(&mut transaction, &mut report)
+        .await
+        .context("Failed to canonicalize the suppressed addresses")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to canonicalize emails")?;
+
+    Ok(report)
+}
+
+async fn canonicalize_subscribers(
+    transaction: &mut Transaction<'_, Postgres>,
+    report: &mut CanonicalizationReport,
+) -> Result<(), sqlx::Error> {
+    let subscribers = sqlx::query!(
+        r#"SELECT id, email, status FROM subscriptions ORDER BY subscribed_at FOR UPDATE"#
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    let canonical: Vec<String> = subscribers
+        .iter()
+        .map(|s| canonical_form(&s.email, report))
+        .collect();
+
+    let groups = colliding_groups(&mut *transaction, &canonical).await?;
+    report.subscriber_collisions = groups
+        .iter()
+        .map(|group| {
+            group
+                .iter()
+                .map(|&i| {
+                    let s = &subscribers[i];
+                    format!("{} ({}, {})", s.email, s.id, s.status)
+                })
+                .collect()
+        })
+        .collect();
+    let colliding: HashSet<usize> = groups.into_iter().flatten().collect();
+
+    for (i, subscriber) in subscribers.iter().enumerate() {
+        if colliding.contains(&i) || canonical[i] == subscriber.email {
+            continue;
+        }
+        sqlx::query!(
+            r#"UPDATE subscriptions SET email = $2 WHERE id = $1"#,
+            subscriber.id,
+            canonical[i],
+        )
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::query!(
+            r#"UPDATE issue_delivery_queue SET subscriber_email = $2 WHERE subscriber_email = $1"#,
+            subscriber.email,
+            canonical[i],
+        )
+        .execute(&mut *transaction)
+        .await?;
+        report.n_rewritten += 1;
+    }
+
+    Ok(())
+}
+
+async fn canonicalize_admins(
+    transaction: &mut Transaction<'_, Postgres>,
+    report: &mut CanonicalizationReport,
+) -> Result<(), sqlx::Error> {
+    let admins = sqlx::query!(
+        r#"SELECT user_id, username, email AS "email!"
+    FROM users WHERE email IS NOT NULL ORDER BY username FOR UPDATE"#
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    let canonical: Vec<String> = admins
+        .iter()
+        .map(|a| canonical_form(&a.email, report))
+        .collect();
+
+    let groups = colliding_groups(&mut *transaction, &canonical).await?;
+    report.admin_collisions = groups
+        .iter()
+        .map(|group| {
+            group
+                .iter()
+                .map(|&i| format!("{} ({})", admins[i].email, admins[i].username))
+                .collect()
+        })
+        .collect();
+    let colliding: HashSet<usize> = groups.into_iter().flatten().collect();
+
+    for (i, admin) in admins.iter().enumerate() {
+        if colliding.contains(&i) || canonical[i] == admin.email {
+            continue;
+        }
+        sqlx::query!(
+            r#"UPDATE users SET email = $2 WHERE user_id = $1"#,
+            admin.user_id,
+            canonical[i],
+        )
+        .execute(&mut *transaction)
+        .await?;
+        report.n_rewritten += 1;
+    }
+
+    Ok(())
+}
+
+/// Suppressions of the same address are merged into the first one.
+async fn canonicalize_suppressions(
+    transaction: &mut Transaction<'_, Postgres>,
+    report: &mut CanonicalizationReport,
+) -> Result<(), sqlx::Error> {
+    let emails: Vec<String> =
+        sqlx::query!(r#"SELECT email FROM suppressions ORDER BY created_at, email FOR UPDATE"#)
+            .fetch_all(&mut *transaction)
+            .await?
+            .into_iter()
+            .map(|r| r.email)
+            .collect();
+    let canonical: Vec<String> = emails.iter().map(|e| canonical_form(e, report)).collect();
+
+    let mut duplicates = HashSet::new();
+    for group in colliding_groups(&mut *transaction, &canonical).await? {
+        duplicates.extend(group.into_iter().skip(1));
+    }
+    for &i in &duplicates {
+        sqlx::query!(r#"DELETE FROM suppressions WHERE email = $1"#, emails[i])
+            .execute(&mut *transaction)
+            .await?;
+        report.n_duplicate_suppressions += 1;
+    }
+
+    for (i, email) in emails.iter().enumerate() {
+        if duplicates.contains(&i) || canonical[i] == *email {
+            continue;
+        }
+        sqlx::query!(
+            r#"UPDATE suppressions SET email = $2 WHERE email = $1"#,
+            email,
+            canonical[i],
+        )
+        .execute(&mut *transaction)
+        .await?;
+        report.n_rewritten += 1;
+    }
+
+    Ok(())
+}
+
+/// The canonical form of `email`, or `email` itself if it is invalid.
+fn canonical_form(email: &str, report: &mut CanonicalizationReport) -> String {
+    match SubscriberEmail::parse(email.to_owned()) {
+        Ok(parsed) => parsed.as_ref().to_owned(),
+        Err(_) => {
+            report.invalid.push(email.to_owned());
+            email.to_owned()
+        }
+    }
+}
+
+/// The indices of `emails` that are the same for Postgres' `lower()`, as the
+/// unique indices compare them, by group of two or more in ascending order.
+async fn colliding_groups(
+    transaction: &mut Transaction<'_, Postgres>,
+    emails: &[String],
+) -> Result<Vec<Vec<usize>>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"SELECT array_agg(e.position ORDER BY e.position) AS "positions!"
+    FROM unnest($1::TEXT[]) WITH ORDINALITY AS e (email, position)
+    GROUP BY lower(e.email)
+    HAVING count(*) > 1"#,
+        emails
+    )
+    .fetch_all(transaction)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|r| r.positions.into_iter().map(|p| p as usize - 1).collect())
+        .collect())
+}
@@ -2,30 +2,306 @@ use secrecy::{ExposeSecret, Secret};
 use serde_aux::field_attributes::deserialize_number_from_string;
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
 
-use crate::domain::SubscriberEmail;
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use crate::{
+    domain::{SubscriberEmail, SubscriberEmailError},
+    email_client::{
+        CircuitBreaker, EmailSender, FailoverSender, FileClient, MailgunClient, PostmarkClient,
+        RetryPolicy, SendGridClient, SmtpClient, Smtputf8Check,
+    },
+    email_events::{MailgunWebhook, PostmarkWebhook, SendGridWebhook},
+};
 
 #[derive(serde::Deserialize)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    #[serde(default)]
+    pub webhooks: WebhookSettings,
 }
 
 #[derive(serde::Deserialize)]
 pub struct EmailClientSettings {
+    #[serde(default)]
+    pub provider: EmailProvider,
+    /// API root of the provider. Unused by `smtp`.
     pub base_url: String,
     pub sender_email: String,
+    /// API key of the provider, or the SMTP password.
     pub authorization_token: Secret<String>,
     pub timeout_milliseconds: u64,
+    /// Used by the HTTP providers.
+    #[serde(default)]
+    pub retry: RetrySettings,
+    /// Required by the `smtp` provider.
+    pub smtp: Option<SmtpSettings>,
+    /// Required by the `file` provider.
+    pub file: Option<FileTransportSettings>,
+    /// Emails go through this provider while the primary one is failing.
+    pub secondary: Option<Box<EmailClientSettings>>,
+    /// When to give up on the primary provider. Unused without `secondary`.
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerSettings,
+    /// Whether the account of an HTTP provider delivers to mailboxes with a
+    /// UTF-8 local part. `smtp` negotiates it with the server instead.
+    #[serde(default)]
+    pub smtputf8: bool,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailProvider {
+    #[default]
+    SendGrid,
+    Postmark,
+    Mailgun,
+    Smtp,
+    /// Write emails to disk, for development.
+    File,
+}
+
+impl EmailProvider {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailProvider::SendGrid => "sendgrid",
+            EmailProvider::Postmark => "postmark",
+            EmailProvider::Mailgun => "mailgun",
+            EmailProvider::Smtp => "smtp",
+            EmailProvider::File => "file",
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(default)]
+pub struct RetrySettings {
+    /// Includes the first attempt, so 1 disables retries.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub initial_backoff_milliseconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_backoff_milliseconds: u64,
+}
+
+impl Default for RetrySettings {
+    fn default() -> Self {
+        let policy = RetryPolicy::default();
+        Self {
+            max_attempts: policy.max_attempts,
+            initial_backoff_milliseconds: policy.initial_backoff.as_millis() as u64,
+            max_backoff_milliseconds: policy.max_backoff.as_millis() as u64,
+        }
+    }
+}
+
+/// Credentials of the event webhooks of the providers. The webhook of a
+/// provider is only served once they are set.
+#[derive(serde::Deserialize, Default)]
+#[serde(default)]
+pub struct WebhookSettings {
+    /// The base64 public key shown in the settings of the SendGrid Event
+    /// Webhook.
+    pub sendgrid_verification_key: Option<String>,
+    pub mailgun_signing_key: Option<Secret<String>>,
+    /// Set as `Basic` auth credentials in the URL of the Postmark webhook.
+    pub postmark_username: Option<String>,
+    pub postmark_password: Option<Secret<String>>,
+}
+
+impl WebhookSettings {
+    pub fn sendgrid(&self) -> Option<SendGridWebhook> {
+        self.sendgrid_verification_key.as_ref().map(|key| {
+            SendGridWebhook::new(key).expect("Invalid SendGrid webhook verification key")
+        })
+    }
+
+    pub fn mailgun(&self) -> Option<MailgunWebhook> {
+        self.mailgun_signing_key.clone().map(MailgunWebhook::new)
+    }
+
+    pub fn postmark(&self) -> Option<PostmarkWebhook> {
+        match (&self.postmark_username, &self.postmark_password) {
+            (Some(username), Some(password)) => {
+                Some(PostmarkWebhook::new(username.clone(), password.clone()))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(default)]
+pub struct CircuitBreakerSettings {
+    /// Consecutive failures of the primary provider before failing over.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub failure_threshold: u32,
+    /// How long to fail over before probing the primary provider again.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub open_duration_seconds: u64,
+}
+
+impl Default for CircuitBreakerSettings {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            open_duration_seconds: 30,
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    #[serde(default)]
+    pub tls: SmtpTls,
+    /// Authenticate as this user, with `authorization_token` as password.
+    pub username: Option<String>,
+    /// Size of the connection pool. Defaults to 10.
+    pub max_connections: Option<u32>,
+    /// Sign outgoing emails when set.
+    pub dkim: Option<DkimSettings>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct FileTransportSettings {
+    pub directory: PathBuf,
+    /// Browse the emails at `/dev/mailbox`. Never enable it in production.
+    #[serde(default)]
+    pub serve_mailbox: bool,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Plain text, for local sinks such as mailpit.
+    None,
+    /// Upgrade the connection with `STARTTLS`, usually on port 587.
+    #[default]
+    StartTls,
+    /// Implicit TLS, usually on port 465.
+    Tls,
+}
+
+#[derive(serde::Deserialize)]
+pub struct DkimSettings {
+    pub domain: String,
+    pub selector: String,
+    /// PKCS#1 PEM for RSA, base64 of the 32 bytes seed for Ed25519.
+    pub private_key: Secret<String>,
+    #[serde(default)]
+    pub algorithm: DkimAlgorithm,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DkimAlgorithm {
+    #[default]
+    Rsa,
+    Ed25519,
 }
 
 impl EmailClientSettings {
-    pub fn sender(&self) -> Result<SubscriberEmail, String> {
+    /// The provider client, behind a circuit breaker when a `secondary`
+    /// provider is configured.
+    pub fn client(&self) -> Arc<dyn EmailSender> {
+        let primary = self.provider_client();
+        match &self.secondary {
+            Some(secondary) => Arc::new(FailoverSender::new(
+                primary,
+                secondary.provider_client(),
+                CircuitBreaker::new(
+                    self.provider.as_str().to_owned(),
+                    self.circuit_breaker.failure_threshold,
+                    Duration::from_secs(self.circuit_breaker.open_duration_seconds),
+                ),
+            )),
+            None => primary,
+        }
+    }
+
+    /// The client of `provider`, rejecting the UTF-8 mailboxes it cannot
+    /// deliver to.
+    fn provider_client(&self) -> Arc<dyn EmailSender> {
+        let smtputf8 = match self.provider {
+            EmailProvider::Smtp | EmailProvider::File => true,
+            _ => self.smtputf8,
+        };
+        Arc::new(Smtputf8Check::new(self.transport_client(), smtputf8))
+    }
+
+    fn transport_client(&self) -> Arc<dyn EmailSender> {
+        let sender = self.sender().expect("Invalid sender email address");
+        let base_url = self.base_url.clone();
+        let token = self.authorization_token.clone();
+        match self.provider {
+            EmailProvider::SendGrid => Arc::new(SendGridClient::new(
+                base_url,
+                sender,
+                token,
+                self.timeout(),
+                self.retry_policy(),
+            )),
+            EmailProvider::Postmark => Arc::new(PostmarkClient::new(
+                base_url,
+                sender,
+                token,
+                self.timeout(),
+                self.retry_policy(),
+            )),
+            EmailProvider::Mailgun => Arc::new(MailgunClient::new(
+                base_url,
+                sender,
+                token,
+                self.timeout(),
+                self.retry_policy(),
+            )),
+            EmailProvider::Smtp => {
+                let smtp = self
+                    .smtp
+                    .as_ref()
+                    .expect("The smtp provider needs `email_client.smtp` settings");
+                Arc::new(
+                    SmtpClient::new(smtp, token, sender, self.timeout())
+                        .expect("Invalid SMTP settings"),
+                )
+            }
+            EmailProvider::File => {
+                let file = self
+                    .file
+                    .as_ref()
+                    .expect("The file provider needs `email_client.file` settings");
+                Arc::new(FileClient::new(file.directory.clone(), sender))
+            }
+        }
+    }
+
+    /// Where `/dev/mailbox` reads emails from, when it is enabled.
+    pub fn dev_mailbox(&self) -> Option<PathBuf> {
+        match (&self.provider, &self.file) {
+            (EmailProvider::File, Some(file)) if file.serve_mailbox => Some(file.directory.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn sender(&self) -> Result<SubscriberEmail, SubscriberEmailError> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
 
-    pub fn timeout(&self) -> std::time::Duration {
-        std::time::Duration::from_millis(self.timeout_milliseconds)
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_milliseconds)
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.retry.max_attempts.max(1),
+            initial_backoff: Duration::from_millis(self.retry.initial_backoff_milliseconds),
+            max_backoff: Duration::from_millis(self.retry.max_backoff_milliseconds),
+        }
     }
 }
 
@@ -35,6 +311,20 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
     pub base_url: String,
+    /// Signs session and flash message cookies. Must be at least 64 bytes.
+    pub hmac_secret: Secret<String>,
+    /// How long the link of a confirmation email stays valid.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub subscription_token_ttl_hours: i64,
+    /// Where HTML form submissions to `/subscribe` are redirected to.
+    pub subscribe_success_url: String,
+    pub subscribe_error_url: String,
+}
+
+impl ApplicationSettings {
+    pub fn subscription_token_ttl(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.subscription_token_ttl_hours)
+    }
 }
 
 #[derive(serde::Deserialize)]
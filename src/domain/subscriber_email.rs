@@ -1,15 +1,131 @@
-use validator::validate_email;
+/// RFC 5321 caps paths at 256 octets, angle brackets included.
+const MAX_LENGTH: usize = 254;
+const MAX_LOCAL_PART_LENGTH: usize = 64;
+const MAX_LABEL_LENGTH: usize = 63;
+/// Allowed in atoms besides letters and digits (RFC 5322 `atext`).
+const ATOM_SPECIALS: &str = "!#$%&'*+-/=?^_`{|}~";
 
+/// A valid email, in canonical form: trimmed, with its domain lowercased and
+/// in ASCII (punycode for internationalized domains).
+///
+/// The local part keeps its case: it is up to the receiving server. It may
+/// contain UTF-8 (RFC 6531), in which case only transports supporting
+/// `SMTPUTF8` can deliver to it.
+///
+/// Quoted local parts and address literals, which RFC 5321 allows, are
+/// rejected: our transports cannot address them.
 pub struct SubscriberEmail(String);
 
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum SubscriberEmailError {
+    #[error("The email cannot be empty.")]
+    Empty,
+    #[error("The email cannot be longer than {} bytes.", MAX_LENGTH)]
+    TooLong,
+    #[error("The email must contain an `@`.")]
+    MissingAtSign,
+    #[error("The email needs a name before the `@`.")]
+    EmptyLocalPart,
+    #[error(
+        "The part before the `@` cannot be longer than {} bytes.",
+        MAX_LOCAL_PART_LENGTH
+    )]
+    LocalPartTooLong,
+    #[error("The part before the `@` cannot contain `{0}`.")]
+    InvalidCharacter(char),
+    #[error("The part before the `@` cannot start or end with a dot, or have two in a row.")]
+    MisplacedDot,
+    #[error("The part after the `@` is not a valid domain.")]
+    InvalidDomain,
+}
+
+impl SubscriberEmailError {
+    /// A stable identifier of the error, for clients to act upon.
+    pub fn code(&self) -> &'static str {
+        match self {
+            SubscriberEmailError::Empty => "email_empty",
+            SubscriberEmailError::TooLong => "email_too_long",
+            SubscriberEmailError::MissingAtSign => "missing_at_sign",
+            SubscriberEmailError::EmptyLocalPart => "local_part_empty",
+            SubscriberEmailError::LocalPartTooLong => "local_part_too_long",
+            SubscriberEmailError::InvalidCharacter(_) => "invalid_character",
+            SubscriberEmailError::MisplacedDot => "misplaced_dot",
+            SubscriberEmailError::InvalidDomain => "invalid_domain",
+        }
+    }
+}
+
 impl SubscriberEmail {
-    pub fn parse(s: String) -> Result<SubscriberEmail, String> {
-        if validate_email(s.as_str()) {
-            Ok(Self(s))
-        } else {
-            Err(format!("{} is not a valid subscriber email.", s))
+    /// Parse a `Mailbox` of RFC 5321, with the UTF-8 extensions of RFC 6531.
+    pub fn parse(s: String) -> Result<SubscriberEmail, SubscriberEmailError> {
+        let email = s.trim();
+        if email.is_empty() {
+            return Err(SubscriberEmailError::Empty);
+        }
+        let (local_part, domain) = email
+            .rsplit_once('@')
+            .ok_or(SubscriberEmailError::MissingAtSign)?;
+        check_local_part(local_part)?;
+        let domain = canonical_domain(domain)?;
+
+        let email = format!("{}@{}", local_part, domain);
+        if email.len() > MAX_LENGTH {
+            return Err(SubscriberEmailError::TooLong);
+        }
+        Ok(Self(email))
+    }
+
+    /// Whether the local part is UTF-8, so that delivering to it takes the
+    /// `SMTPUTF8` extension. The domain is always ASCII.
+    pub fn requires_smtputf8(&self) -> bool {
+        !self.0.is_ascii()
+    }
+}
+
+/// A `Dot-string`.
+fn check_local_part(local_part: &str) -> Result<(), SubscriberEmailError> {
+    if local_part.is_empty() {
+        return Err(SubscriberEmailError::EmptyLocalPart);
+    }
+    if local_part.len() > MAX_LOCAL_PART_LENGTH {
+        return Err(SubscriberEmailError::LocalPartTooLong);
+    }
+    for atom in local_part.split('.') {
+        if atom.is_empty() {
+            return Err(SubscriberEmailError::MisplacedDot);
         }
+        if let Some(c) = atom.chars().find(|c| !is_atext(*c)) {
+            return Err(SubscriberEmailError::InvalidCharacter(c));
+        }
+    }
+    Ok(())
+}
+
+fn is_atext(c: char) -> bool {
+    c.is_ascii_alphanumeric() || ATOM_SPECIALS.contains(c) || is_utf8_non_ascii(c)
+}
+
+/// RFC 6531 allows any non-ASCII character. Only letters and digits are kept,
+/// the others being as unlikely in a mailbox as they are unsupported by our
+/// transports.
+fn is_utf8_non_ascii(c: char) -> bool {
+    !c.is_ascii() && c.is_alphanumeric()
+}
+
+/// A fully qualified domain, in lowercase ASCII.
+fn canonical_domain(domain: &str) -> Result<String, SubscriberEmailError> {
+    let domain = idna::domain_to_ascii(domain).map_err(|_| SubscriberEmailError::InvalidDomain)?;
+    let labels: Vec<&str> = domain.split('.').collect();
+    let is_valid_label = |label: &&str| {
+        (1..=MAX_LABEL_LENGTH).contains(&label.len())
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+    };
+    if labels.len() < 2 || !labels.iter().all(is_valid_label) {
+        return Err(SubscriberEmailError::InvalidDomain);
     }
+    Ok(domain)
 }
 
 impl AsRef<String> for SubscriberEmail {
@@ -20,7 +136,7 @@ impl AsRef<String> for SubscriberEmail {
 
 #[cfg(test)]
 mod tests {
-    use super::SubscriberEmail;
+    use super::{SubscriberEmail, SubscriberEmailError};
     use fake::faker::internet::en::SafeEmail;
     use fake::Fake;
 
@@ -56,4 +172,141 @@ mod tests {
         let email = "@domain.com".to_string();
         assert!(SubscriberEmail::parse(email).is_err());
     }
+
+    fn parse(email: &str) -> Result<SubscriberEmail, SubscriberEmailError> {
+        SubscriberEmail::parse(email.to_string())
+    }
+
+    #[test]
+    fn misplaced_dots_are_rejected() {
+        for email in [
+            ".ursula@domain.com",
+            "ursula.@domain.com",
+            "ur..sula@domain.com",
+        ] {
+            assert_eq!(parse(email).err(), Some(SubscriberEmailError::MisplacedDot));
+        }
+    }
+
+    #[test]
+    fn specials_are_rejected() {
+        assert_eq!(
+            parse("ursula le guin@domain.com").err(),
+            Some(SubscriberEmailError::InvalidCharacter(' '))
+        );
+        assert_eq!(
+            parse("ursula<guin>@domain.com").err(),
+            Some(SubscriberEmailError::InvalidCharacter('<'))
+        );
+    }
+
+    #[test]
+    fn quoted_local_parts_are_rejected() {
+        assert_eq!(
+            parse(r#""ursula le guin"@domain.com"#).err(),
+            Some(SubscriberEmailError::InvalidCharacter('"'))
+        );
+    }
+
+    #[test]
+    fn a_64_byte_long_local_part_is_valid() {
+        assert!(parse(&format!("{}@domain.com", "a".repeat(64))).is_ok());
+    }
+
+    #[test]
+    fn a_local_part_longer_than_64_bytes_is_rejected() {
+        assert_eq!(
+            parse(&format!("{}@domain.com", "a".repeat(65))).err(),
+            Some(SubscriberEmailError::LocalPartTooLong)
+        );
+        // 22 characters, but 66 bytes.
+        assert_eq!(
+            parse(&format!("{}@domain.com", "ё".repeat(33))).err(),
+            Some(SubscriberEmailError::LocalPartTooLong)
+        );
+    }
+
+    #[test]
+    fn an_email_longer_than_254_bytes_is_rejected() {
+        let domain = vec!["a".repeat(63); 3].join(".");
+        assert_eq!(
+            parse(&format!("{}@{}.com", "a".repeat(64), domain)).err(),
+            Some(SubscriberEmailError::TooLong)
+        );
+    }
+
+    #[test]
+    fn invalid_domains_are_rejected() {
+        for email in [
+            "ursula@localhost",
+            "ursula@domain.com.",
+            "ursula@-domain.com",
+            "ursula@do_main.com",
+            "ursula@domain..com",
+            "ursula@[192.0.2.1]",
+            "ursula@[IPv6:2001:db8::1]",
+        ] {
+            assert_eq!(
+                parse(email).err(),
+                Some(SubscriberEmailError::InvalidDomain),
+                "{} was accepted",
+                email
+            );
+        }
+    }
+
+    #[test]
+    fn utf8_local_parts_require_smtputf8() {
+        let email = parse("用户@例子.广告").unwrap();
+        assert_eq!(email.as_ref(), "用户@xn--fsqu00a.xn--4rr70v");
+        assert!(email.requires_smtputf8());
+        assert!(!parse("ursula@Bücher.example").unwrap().requires_smtputf8());
+    }
+
+    #[test]
+    fn non_ascii_characters_other_than_letters_and_digits_are_rejected() {
+        assert_eq!(
+            parse("ursula\u{85}@domain.com").err(),
+            Some(SubscriberEmailError::InvalidCharacter('\u{85}'))
+        );
+        assert_eq!(
+            parse("urs\u{a0}ula@domain.com").err(),
+            Some(SubscriberEmailError::InvalidCharacter('\u{a0}'))
+        );
+        assert_eq!(
+            parse("jose\u{301}@domain.com").err(),
+            Some(SubscriberEmailError::InvalidCharacter('\u{301}'))
+        );
+    }
+
+    #[test]
+    fn surrounding_whitespace_is_trimmed() {
+        let email = SubscriberEmail::parse("  ursula@domain.com\n".to_string()).unwrap();
+        assert_eq!(email.as_ref(), "ursula@domain.com");
+    }
+
+    #[test]
+    fn the_domain_is_lowercased_but_not_the_local_part() {
+        let email = SubscriberEmail::parse("Ursula.Le.Guin@Domain.COM".to_string()).unwrap();
+        assert_eq!(email.as_ref(), "Ursula.Le.Guin@domain.com");
+    }
+
+    #[test]
+    fn internationalized_domains_are_stored_as_punycode() {
+        let email = SubscriberEmail::parse("ursula@Bücher.example".to_string()).unwrap();
+        assert_eq!(email.as_ref(), "ursula@xn--bcher-kva.example");
+    }
+
+    #[test]
+    fn invalid_internationalized_domains_are_rejected() {
+        let email = "ursula@xn--a.example".to_string();
+        assert!(SubscriberEmail::parse(email).is_err());
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn parsing_a_parsed_email_gives_it_back(valid_email: ValidEmailFixture) -> bool {
+        let email = SubscriberEmail::parse(valid_email.0).unwrap();
+        let reparsed = SubscriberEmail::parse(email.as_ref().clone()).unwrap();
+        email.as_ref() == reparsed.as_ref()
+    }
 }
@@ -1,22 +1,47 @@
 use unicode_segmentation::UnicodeSegmentation;
 
+const MAX_LENGTH: usize = 256;
+const FORBIDDEN_CHARACTERS: [char; 9] = ['/', '(', ')', '"', '<', '>', '\\', '{', '}'];
+
 pub struct SubscriberName(String);
 
-impl SubscriberName {
-    pub fn parse(s: String) -> Result<SubscriberName, String> {
-        let is_empty_or_whitespace = s.trim().is_empty();
-        let is_too_long = s.graphemes(true).count() > 256;
-        let forbidden_characters = ['/', '(', ')', '"', '<', '>', '\\', '{', '}'];
-        let contains_forbidden_characters = s.chars().any(|g| forbidden_characters.contains(&g));
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum SubscriberNameError {
+    #[error("The name cannot be empty.")]
+    Empty,
+    #[error("The name cannot be longer than {} characters.", MAX_LENGTH)]
+    TooLong,
+    #[error("The name cannot contain `{0}`.")]
+    ForbiddenCharacter(char),
+}
 
-        if is_empty_or_whitespace || is_too_long || contains_forbidden_characters {
-            Err(format!("{} is not a valid subscriber name.", s))
-        } else {
-            Ok(Self(s))
+impl SubscriberNameError {
+    /// A stable identifier of the error, for clients to act upon.
+    pub fn code(&self) -> &'static str {
+        match self {
+            SubscriberNameError::Empty => "name_empty",
+            SubscriberNameError::TooLong => "name_too_long",
+            SubscriberNameError::ForbiddenCharacter(_) => "forbidden_character",
         }
     }
 }
 
+impl SubscriberName {
+    pub fn parse(s: String) -> Result<SubscriberName, SubscriberNameError> {
+        if s.trim().is_empty() {
+            return Err(SubscriberNameError::Empty);
+        }
+        if s.graphemes(true).count() > MAX_LENGTH {
+            return Err(SubscriberNameError::TooLong);
+        }
+        if let Some(c) = s.chars().find(|c| FORBIDDEN_CHARACTERS.contains(c)) {
+            return Err(SubscriberNameError::ForbiddenCharacter(c));
+        }
+
+        Ok(Self(s))
+    }
+}
+
 impl AsRef<String> for SubscriberName {
     fn as_ref(&self) -> &String {
         &self.0
@@ -25,7 +50,7 @@ impl AsRef<String> for SubscriberName {
 
 #[cfg(test)]
 mod tests {
-    use crate::domain::SubscriberName;
+    use crate::domain::{SubscriberName, SubscriberNameError};
 
     #[test]
     fn a_256_grapheme_long_name_is_valid() {
@@ -36,13 +61,19 @@ mod tests {
     #[test]
     fn a_name_longer_than_256_graphemes_is_rejected() {
         let name = "a".repeat(257);
-        assert!(SubscriberName::parse(name).is_err());
+        assert_eq!(
+            SubscriberName::parse(name).err(),
+            Some(SubscriberNameError::TooLong)
+        );
     }
 
     #[test]
     fn whitespace_only_names_are_rejected() {
         let name = " ".to_string();
-        assert!(SubscriberName::parse(name).is_err());
+        assert_eq!(
+            SubscriberName::parse(name).err(),
+            Some(SubscriberNameError::Empty)
+        );
     }
 
     #[test]
@@ -52,9 +83,12 @@ mod tests {
     }
     #[test]
     fn names_containing_an_invalid_character_are_rejected() {
-        for name in &['/', '(', ')', '"', '<', '>', '\\', '{', '}'] {
-            let name = name.to_string();
-            assert!(SubscriberName::parse(name).is_err());
+        for c in &['/', '(', ')', '"', '<', '>', '\\', '{', '}'] {
+            let name = format!("Ursula {} Le Guin", c);
+            assert_eq!(
+                SubscriberName::parse(name).err(),
+                Some(SubscriberNameError::ForbiddenCharacter(*c))
+            );
         }
     }
     #[test]
@@ -1,202 +0,0 @@
-use reqwest::Client;
-use secrecy::{ExposeSecret, Secret};
-use serde_json::json;
-
-use crate::domain::SubscriberEmail;
-
-pub struct EmailClient {
-    sender: SubscriberEmail,
-    http_client: reqwest::Client,
-    base_url: String,
-    authorization_token: Secret<String>,
-}
-
-impl EmailClient {
-    pub fn new(
-        base_url: String,
-        sender: SubscriberEmail,
-        authorization_token: Secret<String>,
-        timeout: std::time::Duration,
-    ) -> Self {
-        let http_client = Client::builder().timeout(timeout).build().unwrap();
-
-        Self {
-            http_client,
-            sender,
-            base_url,
-            authorization_token,
-        }
-    }
-
-    pub async fn send_email(
-        &self,
-        recipient: SubscriberEmail,
-        subject: &str,
-        html_content: &str,
-        text_content: &str,
-    ) -> Result<(), reqwest::Error> {
-        let url = format!("{}/mail/send", self.base_url);
-        let request_body = json!({
-            "personalizations": [{"to": [{"email": recipient.as_ref()}]}],
-            "from": {"email": self.sender.as_ref()},
-            "subject": subject,
-            "content": [
-                {"type": "text/plain", "value": text_content},
-                {"type": "text/html", "value": html_content},
-            ],
-        });
-
-        self.http_client
-            .post(&url)
-            .json(&request_body)
-            .bearer_auth(self.authorization_token.expose_secret())
-            .send()
-            .await?
-            .error_for_status()?;
-
-        Ok(())
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use fake::{
-        faker::{
-            internet::en::SafeEmail,
-            lorem::en::{Paragraph, Sentence},
-        },
-        Fake, Faker,
-    };
-    use hyper::{header, Method};
-    use secrecy::Secret;
-    use wiremock::{
-        matchers::{any, header, header_exists, method, path},
-        Mock, MockServer, ResponseTemplate,
-    };
-
-    use crate::domain::SubscriberEmail;
-
-    use super::EmailClient;
-
-    struct SendEmailBodyMatcher;
-
-    impl wiremock::Match for SendEmailBodyMatcher {
-        fn matches(&self, request: &wiremock::Request) -> bool {
-            let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
-
-            if let Ok(body) = result {
-                body.get("personalizations").is_some()
-                    && body.get("from").is_some()
-                    && body.get("subject").is_some()
-                    && body.get("content").is_some()
-            } else {
-                false
-            }
-        }
-    }
-
-    /// Generate a random email subject
-    fn subject() -> String {
-        Sentence(1..2).fake()
-    }
-    /// Generate a random email content
-    fn content() -> String {
-        Paragraph(1..10).fake()
-    }
-    /// Generate a random subscriber email
-    fn email() -> SubscriberEmail {
-        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
-    }
-
-    /// Get a test instance of `EmailClient`.
-    fn email_client(base_url: String) -> EmailClient {
-        EmailClient::new(
-            base_url,
-            email(),
-            Secret::new(Faker.fake()),
-            std::time::Duration::from_millis(200),
-        )
-    }
-
-    #[tokio::test]
-    async fn send_email_sends_the_expected_request() {
-        let mock_server = MockServer::start().await;
-
-        Mock::given(header_exists(header::AUTHORIZATION))
-            .and(header(
-                header::CONTENT_TYPE,
-                mime::APPLICATION_JSON.as_ref(),
-            ))
-            .and(path("/mail/send"))
-            .and(method(Method::POST))
-            .and(SendEmailBodyMatcher)
-            .respond_with(ResponseTemplate::new(200))
-            .expect(1)
-            .mount(&mock_server)
-            .await;
-
-        let subscriber_email = SubscriberEmail::parse(SafeEmail().fake()).unwrap();
-
-        let _ = email_client(mock_server.uri())
-            .send_email(subscriber_email, &subject(), &content(), &content())
-            .await;
-    }
-
-    #[tokio::test]
-    async fn send_email_succeeds_if_the_server_returns_200() {
-        let mock_server = MockServer::start().await;
-        let subscriber_email = SubscriberEmail::parse(SafeEmail().fake()).unwrap();
-
-        Mock::given(any())
-            .respond_with(ResponseTemplate::new(200))
-            .expect(1)
-            .mount(&mock_server)
-            .await;
-
-        let outcome = email_client(mock_server.uri())
-            .send_email(subscriber_email, &subject(), &content(), &content())
-            .await;
-
-        assert!(outcome.is_ok());
-    }
-
-    #[tokio::test]
-    async fn send_email_fails_if_the_server_returns_500() {
-        // Arrange
-        let mock_server = MockServer::start().await;
-        let subscriber_email = SubscriberEmail::parse(SafeEmail().fake()).unwrap();
-        Mock::given(any())
-            // Not a 200 anymore!
-            .respond_with(ResponseTemplate::new(500))
-            .expect(1)
-            .mount(&mock_server)
-            .await;
-        // Act
-        let outcome = email_client(mock_server.uri())
-            .send_email(subscriber_email, &subject(), &content(), &content())
-            .await;
-        // Assert
-        assert!(outcome.is_err());
-    }
-
-    #[tokio::test]
-    async fn send_email_times_out_if_the_server_takes_too_long() {
-        // Arrange
-        let mock_server = MockServer::start().await;
-        let subscriber_email = SubscriberEmail::parse(SafeEmail().fake()).unwrap();
-
-        let response = ResponseTemplate::new(200) // 3 minutes!
-            .set_delay(std::time::Duration::from_secs(180));
-        Mock::given(any())
-            .respond_with(response)
-            .expect(1)
-            .mount(&mock_server)
-            .await;
-        // Act
-        let outcome = email_client(mock_server.uri())
-            .send_email(subscriber_email, &subject(), &content(), &content())
-            .await;
-        // Assert
-        assert!(outcome.is_err());
-    }
-}
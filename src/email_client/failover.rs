@@ -0,0 +1,697 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use super::{send_runs_around, BatchRecipient, ChunkOutcome, EmailError, EmailSender};
+use crate::domain::SubscriberEmail;
+
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    /// Calls go through.
+    Closed,
+    /// Calls are refused until the breaker lets a probe through.
+    Open,
+    /// A single probe is in flight; its outcome closes or reopens the breaker.
+    HalfOpen,
+}
+
+/// Stop calling a provider after `failure_threshold` consecutive failures,
+/// then probe it again every `open_duration`.
+pub struct CircuitBreaker {
+    name: String,
+    failure_threshold: u32,
+    open_duration: Duration,
+    state: Mutex<BreakerState>,
+}
+
+enum BreakerState {
+    Closed {
+        consecutive_failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// A probe that has not reported back after `open_duration` is taken as
+    /// abandoned, e.g. its future was dropped: another one goes through.
+    HalfOpen {
+        since: Instant,
+    },
+}
+
+impl CircuitBreaker {
+    pub fn new(name: String, failure_threshold: u32, open_duration: Duration) -> Self {
+        Self {
+            name,
+            failure_threshold: failure_threshold.max(1),
+            open_duration,
+            state: Mutex::new(BreakerState::Closed {
+                consecutive_failures: 0,
+            }),
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        match *self.state.lock().unwrap() {
+            BreakerState::Closed { .. } => CircuitState::Closed,
+            BreakerState::Open { .. } => CircuitState::Open,
+            BreakerState::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Whether a call may go through. Once `open_duration` is over, the first
+    /// caller gets to probe the provider and the others keep being refused.
+    fn allow_call(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        match *state {
+            BreakerState::Closed { .. } => true,
+            BreakerState::Open { until } if now >= until => {
+                *state = BreakerState::HalfOpen { since: now };
+                tracing::info!(provider = %self.name, circuit = "half_open", "Probing the email provider.");
+                true
+            }
+            BreakerState::HalfOpen { since } if now >= since + self.open_duration => {
+                *state = BreakerState::HalfOpen { since: now };
+                tracing::warn!(provider = %self.name, circuit = "half_open", "The probe never reported back. Probing the email provider again.");
+                true
+            }
+            BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => false,
+        }
+    }
+
+    fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if !matches!(*state, BreakerState::Closed { .. }) {
+            tracing::info!(provider = %self.name, circuit = "closed", "The email provider recovered.");
+        }
+        *state = BreakerState::Closed {
+            consecutive_failures: 0,
+        };
+    }
+
+    /// The call said nothing about the provider, e.g. the email was invalid.
+    /// If it was the probe, the next call probes the provider instead.
+    fn record_no_outcome(&self) {
+        let mut state = self.state.lock().unwrap();
+        if matches!(*state, BreakerState::HalfOpen { .. }) {
+            *state = BreakerState::Open {
+                until: Instant::now(),
+            };
+        }
+    }
+
+    fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        let consecutive_failures = match *state {
+            BreakerState::Closed {
+                consecutive_failures,
+            } => consecutive_failures + 1,
+            // A failed probe reopens the breaker straight away.
+            BreakerState::HalfOpen { .. } => self.failure_threshold,
+            BreakerState::Open { .. } => return,
+        };
+        if consecutive_failures >= self.failure_threshold {
+            tracing::warn!(
+                provider = %self.name,
+                circuit = "open",
+                consecutive_failures,
+                "The email provider keeps failing. Failing over for {:?}.",
+                self.open_duration,
+            );
+            *state = BreakerState::Open {
+                until: Instant::now() + self.open_duration,
+            };
+        } else {
+            *state = BreakerState::Closed {
+                consecutive_failures,
+            };
+        }
+    }
+}
+
+/// Send through `primary`, falling back to `secondary` when it fails or while
+/// its circuit breaker is open.
+///
+/// Mailboxes with a UTF-8 local part go to `secondary` if only it supports
+/// `SMTPUTF8`, straight away or once the server of `primary` turns out not to.
+/// That is no failure of `primary`.
+pub struct FailoverSender {
+    primary: Arc<dyn EmailSender>,
+    secondary: Arc<dyn EmailSender>,
+    breaker: CircuitBreaker,
+}
+
+impl FailoverSender {
+    pub fn new(
+        primary: Arc<dyn EmailSender>,
+        secondary: Arc<dyn EmailSender>,
+        breaker: CircuitBreaker,
+    ) -> Self {
+        Self {
+            primary,
+            secondary,
+            breaker,
+        }
+    }
+
+    fn routes_smtputf8_to_secondary(&self) -> bool {
+        !self.primary.supports_smtputf8() && self.secondary.supports_smtputf8()
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for FailoverSender {
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<(), EmailError> {
+        if recipient.requires_smtputf8() && self.routes_smtputf8_to_secondary() {
+            return self
+                .secondary
+                .send_email(recipient, subject, html_content, text_content, headers)
+                .await;
+        }
+        if self.breaker.allow_call() {
+            match self
+                .primary
+                .send_email(recipient, subject, html_content, text_content, headers)
+                .await
+            {
+                Ok(()) => {
+                    self.breaker.record_success();
+                    return Ok(());
+                }
+                Err(EmailError::Smtputf8Unsupported) if self.secondary.supports_smtputf8() => {
+                    self.breaker.record_no_outcome();
+                }
+                // Nobody can deliver this email, e.g. the provider rejected
+                // its payload.
+                Err(e) if !e.is_retryable() => {
+                    self.breaker.record_no_outcome();
+                    return Err(e);
+                }
+                Err(e) => {
+                    self.breaker.record_failure();
+                    tracing::warn!(
+                        error.cause_chain = ?e,
+                        "The primary email provider failed. Trying the secondary one.",
+                    );
+                }
+            }
+        }
+
+        self.secondary
+            .send_email(recipient, subject, html_content, text_content, headers)
+            .await
+    }
+
+    /// Chunks the primary provider fails to send are sent again through the
+    /// secondary one.
+    async fn send_batch(
+        &self,
+        recipients: &[BatchRecipient],
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Vec<ChunkOutcome> {
+        let routed: Vec<usize> = if self.routes_smtputf8_to_secondary() {
+            recipients
+                .iter()
+                .enumerate()
+                .filter(|(_, r)| r.email.requires_smtputf8())
+                .map(|(i, _)| i)
+                .collect()
+        } else {
+            Vec::new()
+        };
+        if !routed.is_empty() {
+            let mut outcomes = send_runs_around(
+                self,
+                recipients,
+                &routed,
+                subject,
+                html_content,
+                text_content,
+            )
+            .await;
+            for i in routed {
+                let sent = self
+                    .secondary
+                    .send_batch(&recipients[i..i + 1], subject, html_content, text_content)
+                    .await;
+                outcomes.extend(sent.into_iter().map(|o| ChunkOutcome {
+                    recipients: i..i + 1,
+                    result: o.result,
+                }));
+            }
+            return outcomes;
+        }
+
+        if !self.breaker.allow_call() {
+            return self
+                .secondary
+                .send_batch(recipients, subject, html_content, text_content)
+                .await;
+        }
+
+        let mut outcomes = Vec::new();
+        for outcome in self
+            .primary
+            .send_batch(recipients, subject, html_content, text_content)
+            .await
+        {
+            match &outcome.result {
+                Ok(()) => {
+                    self.breaker.record_success();
+                    outcomes.push(outcome);
+                    continue;
+                }
+                Err(EmailError::Smtputf8Unsupported) if self.secondary.supports_smtputf8() => {
+                    self.breaker.record_no_outcome();
+                }
+                Err(e) if !e.is_retryable() => {
+                    self.breaker.record_no_outcome();
+                    outcomes.push(outcome);
+                    continue;
+                }
+                Err(e) => {
+                    self.breaker.record_failure();
+                    tracing::warn!(
+                        error.cause_chain = ?e,
+                        n_recipients = outcome.recipients.len(),
+                        "The primary email provider failed. Trying the secondary one.",
+                    );
+                }
+            }
+            let offset = outcome.recipients.start;
+            let retried = self
+                .secondary
+                .send_batch(
+                    &recipients[outcome.recipients],
+                    subject,
+                    html_content,
+                    text_content,
+                )
+                .await;
+            outcomes.extend(retried.into_iter().map(|o| ChunkOutcome {
+                recipients: o.recipients.start + offset..o.recipients.end + offset,
+                result: o.result,
+            }));
+        }
+        outcomes
+    }
+
+    fn circuit_state(&self) -> Option<CircuitState> {
+        Some(self.breaker.state())
+    }
+
+    /// Chunks the primary provider fails to send must fit the secondary one.
+    fn max_batch_size(&self) -> usize {
+        self.primary
+            .max_batch_size()
+            .min(self.secondary.max_batch_size())
+    }
+
+    fn supports_smtputf8(&self) -> bool {
+        self.primary.supports_smtputf8() || self.secondary.supports_smtputf8()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use crate::{
+        domain::SubscriberEmail,
+        email_client::{BatchRecipient, EmailError, EmailSender, Smtputf8Check},
+    };
+
+    use super::{CircuitBreaker, CircuitState, FailoverSender};
+
+    /// Counts its calls, and fails them while `failing` is set. `rejecting`
+    /// fails them all with an error that is the fault of the email.
+    #[derive(Default)]
+    struct FakeSender {
+        calls: AtomicUsize,
+        failing: AtomicBool,
+        rejecting: Option<fn() -> EmailError>,
+        smtputf8: bool,
+    }
+
+    impl FakeSender {
+        fn failing() -> Arc<Self> {
+            let sender = Self::default();
+            sender.failing.store(true, Ordering::SeqCst);
+            Arc::new(sender)
+        }
+
+        fn with_smtputf8() -> Arc<Self> {
+            Arc::new(Self {
+                smtputf8: true,
+                ..Default::default()
+            })
+        }
+
+        fn calls(&self) -> usize {
+            self.calls.load(Ordering::SeqCst)
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl EmailSender for FakeSender {
+        async fn send_email(
+            &self,
+            _recipient: &SubscriberEmail,
+            _subject: &str,
+            _html_content: &str,
+            _text_content: &str,
+            _headers: &[(&str, &str)],
+        ) -> Result<(), EmailError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if let Some(rejection) = self.rejecting {
+                return Err(rejection());
+            }
+            if self.failing.load(Ordering::SeqCst) {
+                let error = std::io::Error::other("Provider down");
+                return Err(error.into());
+            }
+            Ok(())
+        }
+
+        fn supports_smtputf8(&self) -> bool {
+            self.smtputf8
+        }
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse("subscriber@example.com".into()).unwrap()
+    }
+
+    fn utf8_email() -> SubscriberEmail {
+        SubscriberEmail::parse("абонент@example.com".into()).unwrap()
+    }
+
+    fn recipient(email: SubscriberEmail) -> BatchRecipient {
+        BatchRecipient {
+            email,
+            substitutions: vec![],
+            headers: vec![],
+        }
+    }
+
+    fn failover(
+        primary: Arc<FakeSender>,
+        secondary: Arc<FakeSender>,
+        open_duration: Duration,
+    ) -> FailoverSender {
+        FailoverSender::new(
+            primary,
+            secondary,
+            CircuitBreaker::new("primary".into(), 2, open_duration),
+        )
+    }
+
+    async fn send(sender: &FailoverSender) -> Result<(), EmailError> {
+        sender
+            .send_email(&email(), "Subject", "<p>Hi</p>", "Hi", &[])
+            .await
+    }
+
+    #[tokio::test]
+    async fn the_secondary_provider_takes_over_when_the_primary_fails() {
+        let (primary, secondary) = (FakeSender::failing(), Arc::new(FakeSender::default()));
+        let sender = failover(primary.clone(), secondary.clone(), Duration::from_secs(60));
+
+        assert!(send(&sender).await.is_ok());
+
+        assert_eq!(primary.calls(), 1);
+        assert_eq!(secondary.calls(), 1);
+        assert_eq!(sender.circuit_state(), Some(CircuitState::Closed));
+    }
+
+    #[tokio::test]
+    async fn the_breaker_opens_after_consecutive_failures() {
+        let (primary, secondary) = (FakeSender::failing(), Arc::new(FakeSender::default()));
+        let sender = failover(primary.clone(), secondary.clone(), Duration::from_secs(60));
+
+        for _ in 0..4 {
+            send(&sender).await.unwrap();
+        }
+
+        assert_eq!(primary.calls(), 2);
+        assert_eq!(secondary.calls(), 4);
+        assert_eq!(sender.circuit_state(), Some(CircuitState::Open));
+    }
+
+    #[tokio::test]
+    async fn a_successful_probe_closes_the_breaker() {
+        let (primary, secondary) = (FakeSender::failing(), Arc::new(FakeSender::default()));
+        let sender = failover(
+            primary.clone(),
+            secondary.clone(),
+            Duration::from_millis(10),
+        );
+        send(&sender).await.unwrap();
+        send(&sender).await.unwrap();
+        assert_eq!(sender.circuit_state(), Some(CircuitState::Open));
+
+        primary.failing.store(false, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        send(&sender).await.unwrap();
+
+        assert_eq!(primary.calls(), 3);
+        assert_eq!(secondary.calls(), 2);
+        assert_eq!(sender.circuit_state(), Some(CircuitState::Closed));
+    }
+
+    #[tokio::test]
+    async fn a_failed_probe_reopens_the_breaker() {
+        let (primary, secondary) = (FakeSender::failing(), Arc::new(FakeSender::default()));
+        let sender = failover(
+            primary.clone(),
+            secondary.clone(),
+            Duration::from_millis(10),
+        );
+        send(&sender).await.unwrap();
+        send(&sender).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        send(&sender).await.unwrap();
+
+        assert_eq!(primary.calls(), 3);
+        assert_eq!(sender.circuit_state(), Some(CircuitState::Open));
+    }
+
+    #[tokio::test]
+    async fn only_one_probe_goes_through_while_half_open() {
+        let breaker = CircuitBreaker::new("primary".into(), 1, Duration::from_millis(10));
+        breaker.record_failure();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        assert!(breaker.allow_call());
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(!breaker.allow_call());
+    }
+
+    #[tokio::test]
+    async fn a_probe_that_never_reports_back_is_replaced() {
+        let breaker = CircuitBreaker::new("primary".into(), 1, Duration::from_millis(10));
+        breaker.record_failure();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(breaker.allow_call());
+
+        // The probe is dropped before recording its outcome.
+        assert!(!breaker.allow_call());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        assert!(breaker.allow_call());
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+    }
+
+    #[tokio::test]
+    async fn failed_chunks_of_a_batch_are_sent_through_the_secondary_provider() {
+        let (primary, secondary) = (FakeSender::failing(), Arc::new(FakeSender::default()));
+        let sender = failover(primary.clone(), secondary.clone(), Duration::from_secs(60));
+        let recipients: Vec<_> = (0..2).map(|_| recipient(email())).collect();
+
+        let outcomes = sender
+            .send_batch(&recipients, "Subject", "<p>Hi</p>", "Hi")
+            .await;
+
+        assert_eq!(outcomes.len(), 2);
+        assert_eq!(outcomes[1].recipients, 1..2);
+        assert!(outcomes.iter().all(|o| o.result.is_ok()));
+        assert_eq!(secondary.calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn utf8_mailboxes_go_to_the_secondary_provider_if_only_it_supports_them() {
+        let (primary, secondary) = (Arc::new(FakeSender::default()), FakeSender::with_smtputf8());
+        let sender = failover(primary.clone(), secondary.clone(), Duration::from_secs(60));
+
+        sender
+            .send_email(&utf8_email(), "Subject", "<p>Hi</p>", "Hi", &[])
+            .await
+            .unwrap();
+        let outcomes = sender
+            .send_batch(
+                &[
+                    recipient(email()),
+                    recipient(utf8_email()),
+                    recipient(email()),
+                ],
+                "Subject",
+                "<p>Hi</p>",
+                "Hi",
+            )
+            .await;
+
+        let mut sent: Vec<_> = outcomes.iter().map(|o| o.recipients.clone()).collect();
+        sent.sort_by_key(|r| r.start);
+        assert_eq!(sent, [0..1, 1..2, 2..3]);
+        assert!(outcomes.iter().all(|o| o.result.is_ok()));
+        assert_eq!(primary.calls(), 2);
+        assert_eq!(secondary.calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn undeliverable_utf8_mailboxes_do_not_open_the_breaker() {
+        let primary = Arc::new(Smtputf8Check::new(Arc::new(FakeSender::default()), false));
+        let secondary = Arc::new(FakeSender::default());
+        let sender = FailoverSender::new(
+            primary,
+            secondary.clone(),
+            CircuitBreaker::new("primary".into(), 1, Duration::from_secs(60)),
+        );
+
+        let result = sender
+            .send_email(&utf8_email(), "Subject", "<p>Hi</p>", "Hi", &[])
+            .await;
+
+        assert!(matches!(result, Err(EmailError::Smtputf8Unsupported)));
+        assert_eq!(secondary.calls(), 0);
+        assert_eq!(sender.circuit_state(), Some(CircuitState::Closed));
+    }
+
+    #[tokio::test]
+    async fn emails_the_primary_provider_rejects_are_not_failed_over() {
+        let primary = Arc::new(FakeSender {
+            rejecting: Some(|| {
+                let error = std::io::Error::other("Invalid header");
+                EmailError::InvalidMessage(error.into())
+            }),
+            ..Default::default()
+        });
+        let secondary = Arc::new(FakeSender::default());
+        let sender = FailoverSender::new(
+            primary,
+            secondary.clone(),
+            CircuitBreaker::new("primary".into(), 1, Duration::from_secs(60)),
+        );
+
+        let result = send(&sender).await;
+        let outcomes = sender
+            .send_batch(&[recipient(email())], "Subject", "<p>Hi</p>", "Hi")
+            .await;
+
+        assert!(matches!(result, Err(EmailError::InvalidMessage(_))));
+        assert!(matches!(
+            outcomes[0].result,
+            Err(EmailError::InvalidMessage(_))
+        ));
+        assert_eq!(secondary.calls(), 0);
+        assert_eq!(sender.circuit_state(), Some(CircuitState::Closed));
+    }
+
+    #[tokio::test]
+    async fn a_primary_provider_refusing_its_credentials_is_failed_over() {
+        let primary = Arc::new(FakeSender {
+            rejecting: Some(|| {
+                let response = axum::http::Response::builder()
+                    .status(401)
+                    .body("")
+                    .unwrap();
+                let error = reqwest::Response::from(response)
+                    .error_for_status()
+                    .unwrap_err();
+                EmailError::Http(error)
+            }),
+            ..Default::default()
+        });
+        let secondary = Arc::new(FakeSender::default());
+        let sender = FailoverSender::new(
+            primary,
+            secondary.clone(),
+            CircuitBreaker::new("primary".into(), 1, Duration::from_secs(60)),
+        );
+
+        send(&sender).await.unwrap();
+
+        assert_eq!(secondary.calls(), 1);
+        assert_eq!(sender.circuit_state(), Some(CircuitState::Open));
+    }
+
+    #[tokio::test]
+    async fn a_probe_to_an_undeliverable_mailbox_leaves_the_next_call_to_probe() {
+        let inner = FakeSender::failing();
+        let primary = Arc::new(Smtputf8Check::new(inner.clone(), false));
+        let sender = FailoverSender::new(
+            primary,
+            Arc::new(FakeSender::default()),
+            CircuitBreaker::new("primary".into(), 1, Duration::from_millis(10)),
+        );
+        send(&sender).await.unwrap();
+        assert_eq!(sender.circuit_state(), Some(CircuitState::Open));
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let result = sender
+            .send_email(&utf8_email(), "Subject", "<p>Hi</p>", "Hi", &[])
+            .await;
+        assert!(matches!(result, Err(EmailError::Smtputf8Unsupported)));
+        assert_eq!(sender.circuit_state(), Some(CircuitState::Open));
+
+        inner.failing.store(false, Ordering::SeqCst);
+        send(&sender).await.unwrap();
+        assert_eq!(inner.calls(), 2);
+        assert_eq!(sender.circuit_state(), Some(CircuitState::Closed));
+    }
+
+    #[tokio::test]
+    async fn utf8_mailboxes_the_primary_server_turns_down_go_to_the_secondary_provider() {
+        let primary = Arc::new(FakeSender {
+            rejecting: Some(|| EmailError::Smtputf8Unsupported),
+            smtputf8: true,
+            ..Default::default()
+        });
+        let secondary = FakeSender::with_smtputf8();
+        let sender = FailoverSender::new(
+            primary,
+            secondary.clone(),
+            CircuitBreaker::new("primary".into(), 1, Duration::from_secs(60)),
+        );
+
+        sender
+            .send_email(&utf8_email(), "Subject", "<p>Hi</p>", "Hi", &[])
+            .await
+            .unwrap();
+        let outcomes = sender
+            .send_batch(&[recipient(utf8_email())], "Subject", "<p>Hi</p>", "Hi")
+            .await;
+
+        assert!(outcomes[0].result.is_ok());
+        assert_eq!(secondary.calls(), 2);
+        assert_eq!(sender.circuit_state(), Some(CircuitState::Closed));
+    }
+}
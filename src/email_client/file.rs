@@ -0,0 +1,156 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use super::{smtp::build_message, EmailError, EmailSender};
+use crate::domain::SubscriberEmail;
+
+const INDEX_FILE: &str = "index.json";
+
+/// Writes emails to a directory instead of sending them, for development.
+///
+/// Every email becomes `<id>.eml`, with its HTML body next to it in
+/// `<id>.html`, and an entry in `index.json`.
+pub struct FileClient {
+    sender: SubscriberEmail,
+    directory: PathBuf,
+    /// Serializes the read-modify-write of the index.
+    index_lock: Mutex<()>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MailboxEntry {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub recipient: String,
+    pub subject: String,
+}
+
+impl FileClient {
+    pub fn new(directory: PathBuf, sender: SubscriberEmail) -> Self {
+        Self {
+            sender,
+            directory,
+            index_lock: Mutex::new(()),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for FileClient {
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<(), EmailError> {
+        let message = build_message(
+            &self.sender,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            headers,
+        )
+        .map_err(EmailError::InvalidMessage)?;
+        let entry = MailboxEntry {
+            id: Uuid::new_v4(),
+            created_at: Utc::now(),
+            recipient: recipient.as_ref().to_owned(),
+            subject: subject.to_owned(),
+        };
+
+        tokio::fs::create_dir_all(&self.directory).await?;
+        tokio::fs::write(
+            self.directory.join(format!("{}.eml", entry.id)),
+            message.formatted(),
+        )
+        .await?;
+        tokio::fs::write(
+            self.directory.join(format!("{}.html", entry.id)),
+            html_content,
+        )
+        .await?;
+
+        let _guard = self.index_lock.lock().await;
+        let mut index = read_index(&self.directory).await?;
+        index.push(entry);
+        let index = serde_json::to_vec_pretty(&index)
+            .map_err(|e| EmailError::InvalidMessage(Box::new(e)))?;
+        tokio::fs::write(self.directory.join(INDEX_FILE), index).await?;
+
+        Ok(())
+    }
+}
+
+/// The emails written to `directory`, oldest first.
+pub async fn read_index(directory: &Path) -> Result<Vec<MailboxEntry>, std::io::Error> {
+    match tokio::fs::read(directory.join(INDEX_FILE)).await {
+        Ok(index) => Ok(serde_json::from_slice(&index)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// The HTML body of the email `id`, if it was written to `directory`.
+pub async fn read_html(directory: &Path, id: Uuid) -> Result<Option<String>, std::io::Error> {
+    match tokio::fs::read_to_string(directory.join(format!("{}.html", id))).await {
+        Ok(html) => Ok(Some(html)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{domain::SubscriberEmail, email_client::EmailSender};
+
+    use super::{read_html, read_index, FileClient};
+
+    fn email(address: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(address.into()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_email_writes_the_message_and_indexes_it() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let client = FileClient::new(directory.clone(), email("sender@example.com"));
+
+        for subject in ["First", "Second"] {
+            client
+                .send_email(
+                    &email("recipient@example.com"),
+                    subject,
+                    "<p>Hello</p>",
+                    "Hello",
+                    &[],
+                )
+                .await
+                .unwrap();
+        }
+
+        let index = read_index(&directory).await.unwrap();
+        assert_eq!(index.len(), 2);
+        assert_eq!(index[0].subject, "First");
+        assert_eq!(index[1].subject, "Second");
+        assert_eq!(index[0].recipient, "recipient@example.com");
+
+        let eml = std::fs::read_to_string(directory.join(format!("{}.eml", index[0].id))).unwrap();
+        assert!(eml.contains("Subject: First"));
+        let html = read_html(&directory, index[0].id).await.unwrap();
+        assert_eq!(html.as_deref(), Some("<p>Hello</p>"));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn an_empty_directory_has_an_empty_index() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+
+        assert!(read_index(&directory).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,158 @@
+use reqwest::Client;
+use secrecy::{ExposeSecret, Secret};
+
+use super::{retry::send_with_retry, EmailError, EmailSender, RetryPolicy};
+use crate::domain::SubscriberEmail;
+
+/// Sends emails through the `/messages` API of Mailgun.
+///
+/// `base_url` includes the sending domain, e.g.
+/// `https://api.mailgun.net/v3/mg.example.com`.
+pub struct MailgunClient {
+    sender: SubscriberEmail,
+    http_client: reqwest::Client,
+    base_url: String,
+    api_key: Secret<String>,
+    retry_policy: RetryPolicy,
+}
+
+impl MailgunClient {
+    pub fn new(
+        base_url: String,
+        sender: SubscriberEmail,
+        api_key: Secret<String>,
+        timeout: std::time::Duration,
+        retry_policy: RetryPolicy,
+    ) -> Self {
+        let http_client = Client::builder().timeout(timeout).build().unwrap();
+
+        Self {
+            http_client,
+            sender,
+            base_url,
+            api_key,
+            retry_policy,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for MailgunClient {
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<(), EmailError> {
+        let url = format!("{}/messages", self.base_url);
+        let mut form: Vec<(String, &str)> = vec![
+            ("from".into(), self.sender.as_ref()),
+            ("to".into(), recipient.as_ref()),
+            ("subject".into(), subject),
+            ("html".into(), html_content),
+            ("text".into(), text_content),
+        ];
+        // Mailgun takes custom headers as `h:`-prefixed parameters.
+        form.extend(
+            headers
+                .iter()
+                .map(|(name, value)| (format!("h:{}", name), *value)),
+        );
+
+        send_with_retry(&self.retry_policy, || {
+            self.http_client
+                .post(&url)
+                .basic_auth("api", Some(self.api_key.expose_secret()))
+                .form(&form)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fake::{faker::internet::en::SafeEmail, Fake};
+    use secrecy::Secret;
+    use wiremock::{
+        matchers::{any, basic_auth, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use crate::{
+        domain::SubscriberEmail,
+        email_client::{EmailSender, RetryPolicy},
+    };
+
+    use super::MailgunClient;
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn email_client(base_url: String) -> MailgunClient {
+        MailgunClient::new(
+            base_url,
+            email(),
+            Secret::new("api-key".into()),
+            std::time::Duration::from_millis(200),
+            RetryPolicy {
+                max_attempts: 1,
+                initial_backoff: std::time::Duration::from_millis(1),
+                max_backoff: std::time::Duration::from_millis(10),
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_the_expected_request() {
+        let mock_server = MockServer::start().await;
+        let recipient = email();
+
+        Mock::given(path("/messages"))
+            .and(method("POST"))
+            .and(basic_auth("api", "api-key"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        email_client(mock_server.uri())
+            .send_email(
+                &recipient,
+                "Subject",
+                "<p>Hello</p>",
+                "Hello",
+                &[("List-Unsubscribe-Post", "List-Unsubscribe=One-Click")],
+            )
+            .await
+            .unwrap();
+
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let form: Vec<(String, String)> = serde_urlencoded::from_bytes(&request.body).unwrap();
+        assert!(form.contains(&("to".into(), recipient.as_ref().into())));
+        assert!(form.contains(&("html".into(), "<p>Hello</p>".into())));
+        assert!(form.contains(&(
+            "h:List-Unsubscribe-Post".into(),
+            "List-Unsubscribe=One-Click".into()
+        )));
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_returns_500() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client(mock_server.uri())
+            .send_email(&email(), "Subject", "<p>Hello</p>", "Hello", &[])
+            .await;
+
+        assert!(outcome.is_err());
+    }
+}
@@ -0,0 +1,194 @@
+mod failover;
+mod file;
+mod mailgun;
+mod postmark;
+mod retry;
+mod sendgrid;
+mod smtp;
+mod smtputf8;
+mod suppression;
+
+pub use failover::{CircuitBreaker, CircuitState, FailoverSender};
+pub use file::{read_html, read_index, FileClient, MailboxEntry};
+pub use mailgun::MailgunClient;
+pub use postmark::PostmarkClient;
+pub use retry::RetryPolicy;
+pub use sendgrid::SendGridClient;
+pub use smtp::SmtpClient;
+pub use smtputf8::Smtputf8Check;
+pub use suppression::SuppressionCheck;
+
+use std::ops::Range;
+
+use reqwest::StatusCode;
+
+use crate::{domain::SubscriberEmail, utils::error_chain_fmt};
+
+/// Delivers emails on behalf of the application, whatever the vendor.
+#[async_trait::async_trait]
+pub trait EmailSender: Send + Sync {
+    /// `headers` are added to the email as is, e.g. `List-Unsubscribe`.
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<(), EmailError>;
+
+    /// Send the same email to many recipients, reporting how each chunk of
+    /// `recipients` fared.
+    ///
+    /// The default makes one call per recipient. Providers with a bulk API
+    /// override it.
+    async fn send_batch(
+        &self,
+        recipients: &[BatchRecipient],
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Vec<ChunkOutcome> {
+        let mut outcomes = Vec::with_capacity(recipients.len());
+        for (i, recipient) in recipients.iter().enumerate() {
+            let headers: Vec<_> = recipient
+                .headers
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str()))
+                .collect();
+            let result = self
+                .send_email(
+                    &recipient.email,
+                    &recipient.substitute(subject),
+                    &recipient.substitute(html_content),
+                    &recipient.substitute(text_content),
+                    &headers,
+                )
+                .await;
+            outcomes.push(ChunkOutcome {
+                recipients: i..i + 1,
+                result,
+            });
+        }
+        outcomes
+    }
+
+    /// The state of the circuit breaker guarding the provider, for senders
+    /// that have one.
+    fn circuit_state(&self) -> Option<CircuitState> {
+        None
+    }
+
+    /// How many recipients one call of the provider reaches, at most. The
+    /// default `send_batch` makes one call per recipient.
+    fn max_batch_size(&self) -> usize {
+        1
+    }
+
+    /// Whether the provider delivers to mailboxes with a UTF-8 local part
+    /// (RFC 6531).
+    fn supports_smtputf8(&self) -> bool {
+        false
+    }
+}
+
+/// Send `recipients` through `sender`, except those at the `skipped` indices,
+/// in ascending order. The runs between them are sent in separate batches, so
+/// that outcomes keep pointing at contiguous recipients.
+///
+/// There is no outcome for the skipped recipients.
+async fn send_runs_around(
+    sender: &dyn EmailSender,
+    recipients: &[BatchRecipient],
+    skipped: &[usize],
+    subject: &str,
+    html_content: &str,
+    text_content: &str,
+) -> Vec<ChunkOutcome> {
+    let mut outcomes = Vec::new();
+    let mut run_start = 0;
+    let run_ends = skipped.iter().copied().chain([recipients.len()]);
+    for run_end in run_ends {
+        if run_start < run_end {
+            let sent = sender
+                .send_batch(
+                    &recipients[run_start..run_end],
+                    subject,
+                    html_content,
+                    text_content,
+                )
+                .await;
+            outcomes.extend(sent.into_iter().map(|o| ChunkOutcome {
+                recipients: o.recipients.start + run_start..o.recipients.end + run_start,
+                result: o.result,
+            }));
+        }
+        run_start = run_end + 1;
+    }
+    outcomes
+}
+
+/// A recipient of [`EmailSender::send_batch`].
+pub struct BatchRecipient {
+    pub email: SubscriberEmail,
+    /// Replaced in the subject and the contents for this recipient only, e.g.
+    /// `("{{unsubscribe_link}}", "https://...")`.
+    pub substitutions: Vec<(String, String)>,
+    pub headers: Vec<(String, String)>,
+}
+
+impl BatchRecipient {
+    pub fn substitute(&self, content: &str) -> String {
+        self.substitutions
+            .iter()
+            .fold(content.to_owned(), |content, (key, value)| {
+                content.replace(key, value)
+            })
+    }
+}
+
+/// How the call for `recipients[self.recipients]` went.
+#[derive(Debug)]
+pub struct ChunkOutcome {
+    pub recipients: Range<usize>,
+    pub result: Result<(), EmailError>,
+}
+
+#[derive(thiserror::Error)]
+pub enum EmailError {
+    #[error("Failed to call the API of the email provider.")]
+    Http(#[from] reqwest::Error),
+    #[error("The SMTP server failed to accept the email.")]
+    Smtp(#[from] lettre::transport::smtp::Error),
+    #[error("Failed to write the email to disk.")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to build the email.")]
+    InvalidMessage(#[source] Box<dyn std::error::Error + Send + Sync>),
+    #[error("Failed to check the suppression list.")]
+    Database(#[from] sqlx::Error),
+    #[error("The email provider cannot deliver to a UTF-8 mailbox.")]
+    Smtputf8Unsupported,
+}
+
+impl EmailError {
+    /// Whether sending the email again, later or through another provider,
+    /// may succeed. Otherwise the email itself is at fault, not the provider.
+    ///
+    /// Providers answer 400 or 422 to a payload they reject. Any other status,
+    /// e.g. a 401 for a revoked API key, is a failure of the provider.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            EmailError::Http(error) => !error.status().is_some_and(|s| {
+                s == StatusCode::BAD_REQUEST || s == StatusCode::UNPROCESSABLE_ENTITY
+            }),
+            EmailError::InvalidMessage(_) | EmailError::Smtputf8Unsupported => false,
+            EmailError::Smtp(_) | EmailError::Io(_) | EmailError::Database(_) => true,
+        }
+    }
+}
+
+impl std::fmt::Debug for EmailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
@@ -0,0 +1,186 @@
+use reqwest::Client;
+use secrecy::{ExposeSecret, Secret};
+use serde_json::json;
+
+use super::{retry::send_with_retry, EmailError, EmailSender, RetryPolicy};
+use crate::domain::SubscriberEmail;
+
+/// Sends emails through the `/email` API of Postmark.
+pub struct PostmarkClient {
+    sender: SubscriberEmail,
+    http_client: reqwest::Client,
+    base_url: String,
+    server_token: Secret<String>,
+    retry_policy: RetryPolicy,
+}
+
+impl PostmarkClient {
+    pub fn new(
+        base_url: String,
+        sender: SubscriberEmail,
+        server_token: Secret<String>,
+        timeout: std::time::Duration,
+        retry_policy: RetryPolicy,
+    ) -> Self {
+        let http_client = Client::builder().timeout(timeout).build().unwrap();
+
+        Self {
+            http_client,
+            sender,
+            base_url,
+            server_token,
+            retry_policy,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for PostmarkClient {
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<(), EmailError> {
+        let url = format!("{}/email", self.base_url);
+        let headers: Vec<_> = headers
+            .iter()
+            .map(|(name, value)| json!({"Name": name, "Value": value}))
+            .collect();
+        let request_body = json!({
+            "From": self.sender.as_ref(),
+            "To": recipient.as_ref(),
+            "Subject": subject,
+            "HtmlBody": html_content,
+            "TextBody": text_content,
+            "Headers": headers,
+        });
+
+        send_with_retry(&self.retry_policy, || {
+            self.http_client
+                .post(&url)
+                .header("X-Postmark-Server-Token", self.server_token.expose_secret())
+                .json(&request_body)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fake::{faker::internet::en::SafeEmail, Fake};
+    use secrecy::Secret;
+    use wiremock::{
+        matchers::{any, header, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use crate::{
+        domain::SubscriberEmail,
+        email_client::{BatchRecipient, EmailSender, RetryPolicy},
+    };
+
+    use super::PostmarkClient;
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn email_client(base_url: String) -> PostmarkClient {
+        PostmarkClient::new(
+            base_url,
+            email(),
+            Secret::new("server-token".into()),
+            std::time::Duration::from_millis(200),
+            RetryPolicy {
+                max_attempts: 1,
+                initial_backoff: std::time::Duration::from_millis(1),
+                max_backoff: std::time::Duration::from_millis(10),
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_the_expected_request() {
+        let mock_server = MockServer::start().await;
+        let recipient = email();
+
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .and(header("X-Postmark-Server-Token", "server-token"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        email_client(mock_server.uri())
+            .send_email(
+                &recipient,
+                "Subject",
+                "<p>Hello</p>",
+                "Hello",
+                &[("List-Unsubscribe-Post", "List-Unsubscribe=One-Click")],
+            )
+            .await
+            .unwrap();
+
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body["To"], recipient.as_ref().as_str());
+        assert_eq!(body["HtmlBody"], "<p>Hello</p>");
+        assert_eq!(body["TextBody"], "Hello");
+        assert_eq!(body["Headers"][0]["Name"], "List-Unsubscribe-Post");
+        assert_eq!(body["Headers"][0]["Value"], "List-Unsubscribe=One-Click");
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_returns_500() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client(mock_server.uri())
+            .send_email(&email(), "Subject", "<p>Hello</p>", "Hello", &[])
+            .await;
+
+        assert!(outcome.is_err());
+    }
+
+    #[tokio::test]
+    async fn send_batch_defaults_to_one_call_per_recipient() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        let recipients: Vec<_> = ["Ann", "Bob"]
+            .iter()
+            .map(|name| BatchRecipient {
+                email: email(),
+                substitutions: vec![("{{name}}".into(), name.to_string())],
+                headers: vec![("X-Name".into(), name.to_string())],
+            })
+            .collect();
+        let outcomes = email_client(mock_server.uri())
+            .send_batch(&recipients, "Hi {{name}}", "<p>Hi {{name}}</p>", "Hi")
+            .await;
+
+        assert_eq!(outcomes.len(), 2);
+        assert_eq!(outcomes[1].recipients, 1..2);
+        assert!(outcomes.iter().all(|o| o.result.is_ok()));
+        let requests = mock_server.received_requests().await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&requests[1].body).unwrap();
+        assert_eq!(body["Subject"], "Hi Bob");
+        assert_eq!(body["HtmlBody"], "<p>Hi Bob</p>");
+        assert_eq!(body["Headers"][0]["Value"], "Bob");
+    }
+}
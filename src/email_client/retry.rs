@@ -0,0 +1,271 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use reqwest::{header::RETRY_AFTER, RequestBuilder, Response};
+
+use super::EmailError;
+
+/// How the HTTP providers retry failed calls.
+///
+/// A call is retried when [`EmailError::is_retryable`] says so, as the
+/// delivery queue and the failover do: a payload rejected with a 400 or 422
+/// would fail the same way the next time.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Includes the first attempt, so 1 disables retries.
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    /// Caps the backoff. A `Retry-After` above it is not waited for.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Exponential backoff with jitter: a random delay between half and all
+    /// of `initial_backoff * 2^(attempt - 1)`.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt - 1))
+            .min(self.max_backoff);
+        let jitter = rand::thread_rng().gen_range(0.5..=1.0);
+        backoff.mul_f64(jitter)
+    }
+}
+
+/// Send the request built by `request` until it succeeds, fails for good,
+/// or `policy` runs out of attempts.
+///
+/// Every attempt is recorded as an event of the current span.
+pub(super) async fn send_with_retry(
+    policy: &RetryPolicy,
+    request: impl Fn() -> RequestBuilder,
+) -> Result<(), EmailError> {
+    let mut attempt = 1;
+    loop {
+        let (error, retry_after) = match request().send().await {
+            Ok(response) => {
+                let retry_after = retry_after(&response);
+                match response.error_for_status() {
+                    Ok(_) => {
+                        tracing::info!(attempt, "The email provider accepted the email.");
+                        return Ok(());
+                    }
+                    Err(error) => (EmailError::from(error), retry_after),
+                }
+            }
+            Err(error) => (EmailError::from(error), None),
+        };
+
+        if !error.is_retryable() {
+            tracing::warn!(
+                attempt,
+                error.cause_chain = ?error,
+                "The email provider rejected the email. Not retrying.",
+            );
+            return Err(error);
+        }
+        if attempt >= policy.max_attempts {
+            tracing::warn!(
+                attempt,
+                error.cause_chain = ?error,
+                "Failed to send the email. Out of attempts.",
+            );
+            return Err(error);
+        }
+        let delay = match retry_after {
+            Some(retry_after) if retry_after > policy.max_backoff => {
+                tracing::warn!(
+                    attempt,
+                    error.cause_chain = ?error,
+                    retry_after_ms = retry_after.as_millis() as u64,
+                    "Failed to send the email. The provider asks to wait too long.",
+                );
+                return Err(error);
+            }
+            Some(retry_after) => retry_after,
+            None => policy.backoff(attempt),
+        };
+        tracing::info!(
+            attempt,
+            error.cause_chain = ?error,
+            delay_ms = delay.as_millis() as u64,
+            "Failed to send the email. Retrying.",
+        );
+
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+/// `Retry-After` is either a number of seconds or an HTTP date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    // A date in the past means "now".
+    Some(
+        (date.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use wiremock::{matchers::any, Mock, MockServer, ResponseTemplate};
+
+    use super::{send_with_retry, RetryPolicy};
+    use crate::email_client::EmailError;
+
+    fn policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+
+    async fn send(mock_server: &MockServer, policy: RetryPolicy) -> Result<(), EmailError> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(200))
+            .build()
+            .unwrap();
+        send_with_retry(&policy, || client.post(mock_server.uri())).await
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried_until_success() {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        assert!(send(&mock_server, policy(3)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn retries_stop_after_max_attempts() {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        let error = send(&mock_server, policy(3)).await.unwrap_err();
+        assert!(error.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(400))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let error = send(&mock_server, policy(3)).await.unwrap_err();
+        assert!(!error.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn unauthorized_calls_are_retried_as_the_provider_is_at_fault() {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(401))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        let error = send(&mock_server, policy(3)).await.unwrap_err();
+        assert!(error.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn timeouts_are_retried() {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(1)))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        assert!(send(&mock_server, policy(2)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn retry_after_is_honoured() {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "1"))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let start = std::time::Instant::now();
+        assert!(send(&mock_server, policy(2)).await.is_ok());
+        assert!(start.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn a_retry_after_above_the_max_backoff_is_not_waited_for() {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "3600"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        assert!(send(&mock_server, policy(3)).await.is_err());
+    }
+
+    #[test]
+    fn backoff_grows_exponentially_up_to_the_cap() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(1000),
+        };
+
+        let third = policy.backoff(3);
+        assert!(third >= Duration::from_millis(200) && third <= Duration::from_millis(400));
+        let tenth = policy.backoff(10);
+        assert!(tenth >= Duration::from_millis(500) && tenth <= Duration::from_millis(1000));
+    }
+}
@@ -0,0 +1,416 @@
+use reqwest::Client;
+use secrecy::{ExposeSecret, Secret};
+use serde_json::json;
+
+use super::{
+    retry::send_with_retry, BatchRecipient, ChunkOutcome, EmailError, EmailSender, RetryPolicy,
+};
+use crate::domain::SubscriberEmail;
+
+/// The most `personalizations` SendGrid accepts in a single call.
+const MAX_PERSONALIZATIONS: usize = 1000;
+
+/// Sends emails through the v3 `/mail/send` API of SendGrid.
+pub struct SendGridClient {
+    sender: SubscriberEmail,
+    http_client: reqwest::Client,
+    base_url: String,
+    authorization_token: Secret<String>,
+    retry_policy: RetryPolicy,
+}
+
+impl SendGridClient {
+    pub fn new(
+        base_url: String,
+        sender: SubscriberEmail,
+        authorization_token: Secret<String>,
+        timeout: std::time::Duration,
+        retry_policy: RetryPolicy,
+    ) -> Self {
+        let http_client = Client::builder().timeout(timeout).build().unwrap();
+
+        Self {
+            http_client,
+            sender,
+            base_url,
+            authorization_token,
+            retry_policy,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for SendGridClient {
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<(), EmailError> {
+        let mut request_body = self.request_body(
+            vec![json!({"to": [{"email": recipient.as_ref()}]})],
+            subject,
+            html_content,
+            text_content,
+        );
+        if !headers.is_empty() {
+            request_body["headers"] = headers_object(headers.iter().copied());
+        }
+
+        self.send(&request_body).await
+    }
+
+    /// One call per chunk of 1000 recipients, each with their own
+    /// substitutions and headers.
+    async fn send_batch(
+        &self,
+        recipients: &[BatchRecipient],
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Vec<ChunkOutcome> {
+        let mut outcomes = Vec::new();
+        for (i, chunk) in recipients.chunks(MAX_PERSONALIZATIONS).enumerate() {
+            let personalizations = chunk.iter().map(personalization).collect();
+            let request_body =
+                self.request_body(personalizations, subject, html_content, text_content);
+            let start = i * MAX_PERSONALIZATIONS;
+            outcomes.push(ChunkOutcome {
+                recipients: start..start + chunk.len(),
+                result: self.send(&request_body).await,
+            });
+        }
+        outcomes
+    }
+
+    fn max_batch_size(&self) -> usize {
+        MAX_PERSONALIZATIONS
+    }
+}
+
+impl SendGridClient {
+    fn request_body(
+        &self,
+        personalizations: Vec<serde_json::Value>,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> serde_json::Value {
+        json!({
+            "personalizations": personalizations,
+            "from": {"email": self.sender.as_ref()},
+            "subject": subject,
+            "content": [
+                {"type": "text/plain", "value": text_content},
+                {"type": "text/html", "value": html_content},
+            ],
+        })
+    }
+
+    async fn send(&self, request_body: &serde_json::Value) -> Result<(), EmailError> {
+        let url = format!("{}/mail/send", self.base_url);
+        send_with_retry(&self.retry_policy, || {
+            self.http_client
+                .post(&url)
+                .json(request_body)
+                .bearer_auth(self.authorization_token.expose_secret())
+        })
+        .await
+    }
+}
+
+fn personalization(recipient: &BatchRecipient) -> serde_json::Value {
+    let mut personalization = json!({"to": [{"email": recipient.email.as_ref()}]});
+    if !recipient.substitutions.is_empty() {
+        personalization["substitutions"] = recipient
+            .substitutions
+            .iter()
+            .map(|(key, value)| (key.clone(), json!(value)))
+            .collect::<serde_json::Map<_, _>>()
+            .into();
+    }
+    if !recipient.headers.is_empty() {
+        personalization["headers"] = headers_object(
+            recipient
+                .headers
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str())),
+        );
+    }
+    personalization
+}
+
+fn headers_object<'a>(headers: impl Iterator<Item = (&'a str, &'a str)>) -> serde_json::Value {
+    headers
+        .map(|(name, value)| (name.to_string(), json!(value)))
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use fake::{
+        faker::{
+            internet::en::SafeEmail,
+            lorem::en::{Paragraph, Sentence},
+        },
+        Fake, Faker,
+    };
+    use hyper::{header, Method};
+    use secrecy::Secret;
+    use wiremock::{
+        matchers::{any, header, header_exists, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use crate::{
+        domain::SubscriberEmail,
+        email_client::{BatchRecipient, EmailSender, RetryPolicy},
+    };
+
+    use super::SendGridClient;
+
+    struct SendEmailBodyMatcher;
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &wiremock::Request) -> bool {
+            let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+
+            if let Ok(body) = result {
+                body.get("personalizations").is_some()
+                    && body.get("from").is_some()
+                    && body.get("subject").is_some()
+                    && body.get("content").is_some()
+            } else {
+                false
+            }
+        }
+    }
+
+    /// Generate a random email subject
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+    /// Generate a random email content
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+    /// Generate a random subscriber email
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    /// Get a test instance of `SendGridClient`.
+    fn email_client(base_url: String) -> SendGridClient {
+        SendGridClient::new(
+            base_url,
+            email(),
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(200),
+            RetryPolicy {
+                max_attempts: 1,
+                initial_backoff: std::time::Duration::from_millis(1),
+                max_backoff: std::time::Duration::from_millis(10),
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_the_expected_request() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(header_exists(header::AUTHORIZATION))
+            .and(header(
+                header::CONTENT_TYPE,
+                mime::APPLICATION_JSON.as_ref(),
+            ))
+            .and(path("/mail/send"))
+            .and(method(Method::POST))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let subscriber_email = SubscriberEmail::parse(SafeEmail().fake()).unwrap();
+
+        let _ = email_client(mock_server.uri())
+            .send_email(&subscriber_email, &subject(), &content(), &content(), &[])
+            .await;
+    }
+
+    #[tokio::test]
+    async fn send_email_includes_custom_headers() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        email_client(mock_server.uri())
+            .send_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                &[("List-Unsubscribe-Post", "List-Unsubscribe=One-Click")],
+            )
+            .await
+            .unwrap();
+
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(
+            body["headers"]["List-Unsubscribe-Post"],
+            "List-Unsubscribe=One-Click"
+        );
+    }
+
+    #[tokio::test]
+    async fn send_email_omits_headers_when_there_are_none() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        email_client(mock_server.uri())
+            .send_email(&email(), &subject(), &content(), &content(), &[])
+            .await
+            .unwrap();
+
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert!(body.get("headers").is_none());
+    }
+
+    #[tokio::test]
+    async fn send_email_succeeds_if_the_server_returns_200() {
+        let mock_server = MockServer::start().await;
+        let subscriber_email = SubscriberEmail::parse(SafeEmail().fake()).unwrap();
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client(mock_server.uri())
+            .send_email(&subscriber_email, &subject(), &content(), &content(), &[])
+            .await;
+
+        assert!(outcome.is_ok());
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_returns_500() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let subscriber_email = SubscriberEmail::parse(SafeEmail().fake()).unwrap();
+        Mock::given(any())
+            // Not a 200 anymore!
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        // Act
+        let outcome = email_client(mock_server.uri())
+            .send_email(&subscriber_email, &subject(), &content(), &content(), &[])
+            .await;
+        // Assert
+        assert!(outcome.is_err());
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out_if_the_server_takes_too_long() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let subscriber_email = SubscriberEmail::parse(SafeEmail().fake()).unwrap();
+
+        let response = ResponseTemplate::new(200) // 3 minutes!
+            .set_delay(std::time::Duration::from_secs(180));
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        // Act
+        let outcome = email_client(mock_server.uri())
+            .send_email(&subscriber_email, &subject(), &content(), &content(), &[])
+            .await;
+        // Assert
+        assert!(outcome.is_err());
+    }
+
+    fn batch_recipient(i: usize) -> BatchRecipient {
+        BatchRecipient {
+            email: SubscriberEmail::parse(format!("subscriber{}@example.com", i)).unwrap(),
+            substitutions: vec![("{{name}}".into(), format!("Subscriber {}", i))],
+            headers: vec![("List-Unsubscribe".into(), format!("<https://unsub/{}>", i))],
+        }
+    }
+
+    #[tokio::test]
+    async fn send_batch_sends_one_personalization_per_recipient() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(path("/mail/send"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let recipients: Vec<_> = (0..3).map(batch_recipient).collect();
+        let outcomes = email_client(mock_server.uri())
+            .send_batch(&recipients, "Hi {{name}}", "<p>Hi</p>", "Hi")
+            .await;
+
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(outcomes[0].recipients, 0..3);
+        assert!(outcomes[0].result.is_ok());
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        let personalization = &body["personalizations"][2];
+        assert_eq!(personalization["to"][0]["email"], "subscriber2@example.com");
+        assert_eq!(personalization["substitutions"]["{{name}}"], "Subscriber 2");
+        assert_eq!(
+            personalization["headers"]["List-Unsubscribe"],
+            "<https://unsub/2>"
+        );
+        assert_eq!(body["subject"], "Hi {{name}}");
+    }
+
+    #[tokio::test]
+    async fn send_batch_reports_the_outcome_of_each_chunk_of_1000() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let recipients: Vec<_> = (0..1001).map(batch_recipient).collect();
+        let outcomes = email_client(mock_server.uri())
+            .send_batch(&recipients, &subject(), &content(), &content())
+            .await;
+
+        assert_eq!(outcomes.len(), 2);
+        assert_eq!(outcomes[0].recipients, 0..1000);
+        assert!(outcomes[0].result.is_ok());
+        assert_eq!(outcomes[1].recipients, 1000..1001);
+        assert!(outcomes[1].result.is_err());
+    }
+}
@@ -0,0 +1,530 @@
+use std::sync::OnceLock;
+
+use anyhow::Context;
+use lettre::{
+    message::{
+        dkim::{
+            DkimCanonicalization, DkimCanonicalizationType, DkimConfig, DkimSigningAlgorithm,
+            DkimSigningKey,
+        },
+        header::{HeaderName, HeaderValue},
+        Mailbox, MultiPart,
+    },
+    transport::smtp::{
+        authentication::Credentials,
+        client::{AsyncSmtpConnection, Tls, TlsParameters},
+        extension::{ClientId, Extension},
+        PoolConfig,
+    },
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use secrecy::{ExposeSecret, Secret};
+
+use super::{EmailError, EmailSender};
+use crate::{
+    configurations::{DkimAlgorithm, DkimSettings, SmtpSettings, SmtpTls},
+    domain::SubscriberEmail,
+};
+
+/// Headers covered by the DKIM signature, on top of the custom ones.
+const SIGNED_HEADERS: [&str; 6] = [
+    "From",
+    "To",
+    "Subject",
+    "Date",
+    "MIME-Version",
+    "Content-Type",
+];
+
+/// Sends emails to an SMTP relay over a pool of connections, signing them
+/// with DKIM when a key is configured.
+pub struct SmtpClient {
+    sender: SubscriberEmail,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    dkim: Option<DkimConfig>,
+    relay: Relay,
+    /// Whether the relay advertises SMTPUTF8, once it has been asked.
+    smtputf8: OnceLock<bool>,
+}
+
+/// Where to open a connection of our own, to read the relay's capabilities.
+struct Relay {
+    host: String,
+    port: u16,
+    tls: SmtpTls,
+    timeout: std::time::Duration,
+}
+
+impl SmtpClient {
+    /// `password` is only sent if `settings.username` is set.
+    pub fn new(
+        settings: &SmtpSettings,
+        password: Secret<String>,
+        sender: SubscriberEmail,
+        timeout: std::time::Duration,
+    ) -> Result<Self, anyhow::Error> {
+        let tls = match settings.tls {
+            SmtpTls::None => Tls::None,
+            SmtpTls::StartTls => Tls::Required(TlsParameters::new(settings.host.clone())?),
+            SmtpTls::Tls => Tls::Wrapper(TlsParameters::new(settings.host.clone())?),
+        };
+        let mut pool = PoolConfig::new();
+        if let Some(max_connections) = settings.max_connections {
+            pool = pool.max_size(max_connections);
+        }
+        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&settings.host)
+            .port(settings.port)
+            .tls(tls)
+            .pool_config(pool)
+            .timeout(Some(timeout));
+        if let Some(username) = &settings.username {
+            builder = builder.credentials(Credentials::new(
+                username.clone(),
+                password.expose_secret().to_owned(),
+            ));
+        }
+        let dkim = settings
+            .dkim
+            .as_ref()
+            .map(dkim_config)
+            .transpose()
+            .context("Invalid DKIM settings")?;
+
+        Ok(Self {
+            sender,
+            transport: builder.build(),
+            dkim,
+            relay: Relay {
+                host: settings.host.clone(),
+                port: settings.port,
+                tls: settings.tls,
+                timeout,
+            },
+            smtputf8: OnceLock::new(),
+        })
+    }
+
+    /// Ask the relay whether it advertises SMTPUTF8 the first time a UTF-8
+    /// mailbox is sent to, and remember its answer.
+    async fn relay_supports_smtputf8(&self) -> Result<bool, EmailError> {
+        if let Some(supported) = self.smtputf8.get() {
+            return Ok(*supported);
+        }
+        let supported = self.relay.probe_smtputf8().await?;
+        Ok(*self.smtputf8.get_or_init(|| supported))
+    }
+}
+
+impl Relay {
+    /// Read the extensions the relay lists in its reply to EHLO, after
+    /// STARTTLS if it is used: they may differ before. A UTF-8 mailbox also
+    /// makes the headers 8-bit, so 8BITMIME is needed along with SMTPUTF8.
+    async fn probe_smtputf8(&self) -> Result<bool, lettre::transport::smtp::Error> {
+        let hello_name = ClientId::default();
+        let tls_parameters = || TlsParameters::new(self.host.clone());
+        let mut connection = AsyncSmtpConnection::connect_tokio1(
+            (self.host.as_str(), self.port),
+            Some(self.timeout),
+            &hello_name,
+            match self.tls {
+                SmtpTls::Tls => Some(tls_parameters()?),
+                SmtpTls::None | SmtpTls::StartTls => None,
+            },
+            None,
+        )
+        .await?;
+        if let SmtpTls::StartTls = self.tls {
+            connection.starttls(tls_parameters()?, &hello_name).await?;
+        }
+        let server_info = connection.server_info();
+        let supported = server_info.supports_feature(Extension::SmtpUtfEight)
+            && server_info.supports_feature(Extension::EightBitMime);
+        connection.quit().await?;
+
+        Ok(supported)
+    }
+}
+
+fn dkim_config(settings: &DkimSettings) -> Result<DkimConfig, anyhow::Error> {
+    let algorithm = match settings.algorithm {
+        DkimAlgorithm::Rsa => DkimSigningAlgorithm::Rsa,
+        DkimAlgorithm::Ed25519 => DkimSigningAlgorithm::Ed25519,
+    };
+    let private_key = DkimSigningKey::new(settings.private_key.expose_secret(), algorithm)
+        .map_err(|e| anyhow::anyhow!("Failed to parse the DKIM private key: {:?}", e))?;
+    let headers = SIGNED_HEADERS
+        .iter()
+        .chain(["List-Unsubscribe", "List-Unsubscribe-Post"].iter())
+        .map(|name| HeaderName::new_from_ascii(name.to_string()))
+        .collect::<Result<_, _>>()?;
+
+    Ok(DkimConfig::new(
+        settings.selector.clone(),
+        settings.domain.clone(),
+        private_key,
+        headers,
+        DkimCanonicalization {
+            header: DkimCanonicalizationType::Relaxed,
+            body: DkimCanonicalizationType::Relaxed,
+        },
+    ))
+}
+
+#[async_trait::async_trait]
+impl EmailSender for SmtpClient {
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<(), EmailError> {
+        let mut message = build_message(
+            &self.sender,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            headers,
+        )
+        .map_err(EmailError::InvalidMessage)?;
+        if recipient.requires_smtputf8() && !self.relay_supports_smtputf8().await? {
+            return Err(EmailError::Smtputf8Unsupported);
+        }
+        if let Some(dkim) = &self.dkim {
+            message.sign(dkim);
+        }
+
+        self.transport.send(message).await?;
+
+        Ok(())
+    }
+
+    /// Until the relay has been asked, UTF-8 mailboxes are handed to it: it
+    /// turns them down with [`EmailError::Smtputf8Unsupported`] if need be.
+    fn supports_smtputf8(&self) -> bool {
+        self.smtputf8.get().copied().unwrap_or(true)
+    }
+}
+
+pub(super) fn build_message(
+    sender: &SubscriberEmail,
+    recipient: &SubscriberEmail,
+    subject: &str,
+    html_content: &str,
+    text_content: &str,
+    headers: &[(&str, &str)],
+) -> Result<Message, Box<dyn std::error::Error + Send + Sync>> {
+    let mut message = Message::builder()
+        .from(sender.as_ref().parse::<Mailbox>()?)
+        .to(recipient.as_ref().parse::<Mailbox>()?)
+        .subject(subject)
+        .multipart(MultiPart::alternative_plain_html(
+            text_content.to_owned(),
+            html_content.to_owned(),
+        ))?;
+    for (name, value) in headers {
+        let name = HeaderName::new_from_ascii(name.to_string())?;
+        message
+            .headers_mut()
+            .insert_raw(HeaderValue::new(name, value.to_string()));
+    }
+
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use secrecy::Secret;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    use crate::{
+        configurations::{DkimAlgorithm, DkimSettings, SmtpSettings, SmtpTls},
+        domain::SubscriberEmail,
+        email_client::{EmailError, EmailSender},
+    };
+
+    use super::SmtpClient;
+
+    /// What a client told the SMTP sink.
+    #[derive(Default)]
+    struct Received {
+        auth: Vec<String>,
+        data: Vec<String>,
+    }
+
+    /// Accept every email on an ephemeral port, as `mailpit` would.
+    async fn spawn_smtp_sink() -> (u16, Arc<Mutex<Received>>) {
+        spawn_smtp_sink_with(b"250-sink\r\n250 AUTH PLAIN\r\n").await
+    }
+
+    /// The same, replying `ehlo` to EHLO.
+    async fn spawn_smtp_sink_with(ehlo: &'static [u8]) -> (u16, Arc<Mutex<Received>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let received = Arc::new(Mutex::new(Received::default()));
+
+        let sink = received.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let sink = sink.clone();
+                tokio::spawn(async move {
+                    let (reader, mut writer) = stream.into_split();
+                    let mut lines = BufReader::new(reader).lines();
+                    writer.write_all(b"220 sink ESMTP\r\n").await.unwrap();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        let reply: &[u8] = match line.to_ascii_uppercase() {
+                            l if l.starts_with("EHLO") => ehlo,
+                            l if l.starts_with("AUTH") => {
+                                sink.lock().unwrap().auth.push(line);
+                                b"235 Authenticated\r\n"
+                            }
+                            l if l.starts_with("DATA") => {
+                                writer.write_all(b"354 Go ahead\r\n").await.unwrap();
+                                let mut data = String::new();
+                                while let Ok(Some(line)) = lines.next_line().await {
+                                    if line == "." {
+                                        break;
+                                    }
+                                    data.push_str(&line);
+                                    data.push_str("\r\n");
+                                }
+                                sink.lock().unwrap().data.push(data);
+                                b"250 Queued\r\n"
+                            }
+                            l if l.starts_with("QUIT") => {
+                                writer.write_all(b"221 Bye\r\n").await.unwrap();
+                                break;
+                            }
+                            _ => b"250 OK\r\n",
+                        };
+                        writer.write_all(reply).await.unwrap();
+                    }
+                });
+            }
+        });
+
+        (port, received)
+    }
+
+    fn email(address: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(address.into()).unwrap()
+    }
+
+    fn settings(port: u16) -> SmtpSettings {
+        SmtpSettings {
+            host: "127.0.0.1".into(),
+            port,
+            tls: SmtpTls::None,
+            username: None,
+            max_connections: None,
+            dkim: None,
+        }
+    }
+
+    async fn send(client: &SmtpClient) {
+        client
+            .send_email(
+                &email("recipient@example.com"),
+                "Subject",
+                "<p>Hello</p>",
+                "Hello",
+                &[("List-Unsubscribe-Post", "List-Unsubscribe=One-Click")],
+            )
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn send_email_delivers_both_bodies_and_the_custom_headers() {
+        let (port, received) = spawn_smtp_sink().await;
+        let client = SmtpClient::new(
+            &settings(port),
+            Secret::new("password".into()),
+            email("sender@example.com"),
+            std::time::Duration::from_secs(1),
+        )
+        .unwrap();
+
+        send(&client).await;
+
+        let received = received.lock().unwrap();
+        assert!(received.auth.is_empty());
+        let data = &received.data[0];
+        assert!(data.contains("To: recipient@example.com"));
+        assert!(data.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"));
+        assert!(data.contains("Content-Type: text/plain"));
+        assert!(data.contains("Content-Type: text/html"));
+        assert!(!data.contains("DKIM-Signature"));
+    }
+
+    #[tokio::test]
+    async fn send_email_authenticates_when_a_username_is_set() {
+        let (port, received) = spawn_smtp_sink().await;
+        let client = SmtpClient::new(
+            &SmtpSettings {
+                username: Some("user".into()),
+                ..settings(port)
+            },
+            Secret::new("password".into()),
+            email("sender@example.com"),
+            std::time::Duration::from_secs(1),
+        )
+        .unwrap();
+
+        send(&client).await;
+
+        let received = received.lock().unwrap();
+        // Pooled connections authenticate as they are opened.
+        // base64 of "\0user\0password"
+        assert!(!received.auth.is_empty());
+        assert!(received
+            .auth
+            .iter()
+            .all(|line| line == "AUTH PLAIN AHVzZXIAcGFzc3dvcmQ="));
+    }
+
+    #[tokio::test]
+    async fn send_email_signs_messages_with_dkim() {
+        let (port, received) = spawn_smtp_sink().await;
+        let client = SmtpClient::new(
+            &SmtpSettings {
+                dkim: Some(DkimSettings {
+                    domain: "example.com".into(),
+                    selector: "newsletter".into(),
+                    // Any 32 bytes make an Ed25519 key.
+                    private_key: Secret::new(base64::encode([7u8; 32])),
+                    algorithm: DkimAlgorithm::Ed25519,
+                }),
+                ..settings(port)
+            },
+            Secret::new("password".into()),
+            email("sender@example.com"),
+            std::time::Duration::from_secs(1),
+        )
+        .unwrap();
+
+        send(&client).await;
+
+        let received = received.lock().unwrap();
+        let data = &received.data[0];
+        let signature = data
+            .split("\r\n")
+            .skip_while(|line| !line.starts_with("DKIM-Signature"))
+            .take_while(|line| line.starts_with("DKIM-Signature") || line.starts_with(' '))
+            .collect::<String>();
+        assert!(signature.contains("d=example.com"));
+        assert!(signature.contains("s=newsletter"));
+        assert!(signature.contains("list-unsubscribe-post"));
+    }
+
+    #[tokio::test]
+    async fn utf8_mailboxes_are_turned_down_without_smtputf8() {
+        // The sink does not advertise SMTPUTF8.
+        let (port, received) = spawn_smtp_sink().await;
+        let client = SmtpClient::new(
+            &settings(port),
+            Secret::new("password".into()),
+            email("sender@example.com"),
+            std::time::Duration::from_secs(5),
+        )
+        .unwrap();
+
+        let result = client
+            .send_email(
+                &email("абонент@example.com"),
+                "Subject",
+                "<p>Hi</p>",
+                "Hi",
+                &[],
+            )
+            .await;
+
+        assert!(matches!(result, Err(EmailError::Smtputf8Unsupported)));
+        assert!(received.lock().unwrap().data.is_empty());
+        assert!(!client.supports_smtputf8());
+    }
+
+    #[tokio::test]
+    async fn utf8_mailboxes_are_delivered_when_the_relay_advertises_smtputf8() {
+        let (port, received) =
+            spawn_smtp_sink_with(b"250-sink\r\n250-8BITMIME\r\n250-SMTPUTF8\r\n250 AUTH PLAIN\r\n")
+                .await;
+        let client = SmtpClient::new(
+            &settings(port),
+            Secret::new("password".into()),
+            email("sender@example.com"),
+            std::time::Duration::from_secs(5),
+        )
+        .unwrap();
+
+        client
+            .send_email(
+                &email("абонент@example.com"),
+                "Subject",
+                "<p>Hi</p>",
+                "Hi",
+                &[],
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(received.lock().unwrap().data.len(), 1);
+        assert!(client.supports_smtputf8());
+    }
+
+    #[test]
+    fn invalid_dkim_keys_are_rejected() {
+        let outcome = SmtpClient::new(
+            &SmtpSettings {
+                dkim: Some(DkimSettings {
+                    domain: "example.com".into(),
+                    selector: "newsletter".into(),
+                    private_key: Secret::new("not a key".into()),
+                    algorithm: DkimAlgorithm::Rsa,
+                }),
+                ..settings(25)
+            },
+            Secret::new("password".into()),
+            email("sender@example.com"),
+            std::time::Duration::from_secs(1),
+        );
+
+        assert!(outcome.is_err());
+    }
+
+    #[test]
+    fn every_valid_email_makes_a_message() {
+        for address in [
+            "ursula@example.com",
+            "ursula.le-guin+news@sub.example.com",
+            "!#$%&'*+-/=?^_`{|}~@example.com",
+            "абонент@пример.рф",
+            "用户@例子.广告",
+            "दीपक@example.com",
+            "josé@example.com",
+        ] {
+            let outcome = super::build_message(
+                &email("sender@example.com"),
+                &email(address),
+                "Subject",
+                "<p>Hi</p>",
+                "Hi",
+                &[],
+            );
+            assert!(
+                outcome.is_ok(),
+                "{} was rejected: {:?}",
+                address,
+                outcome.err()
+            );
+        }
+    }
+}
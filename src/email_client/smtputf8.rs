@@ -0,0 +1,92 @@
+use std::sync::Arc;
+
+use super::{
+    send_runs_around, BatchRecipient, ChunkOutcome, CircuitState, EmailError, EmailSender,
+};
+use crate::domain::SubscriberEmail;
+
+/// Reject the mailboxes with a UTF-8 local part up front when the provider
+/// cannot deliver to them, instead of letting it bounce the email.
+///
+/// The rejection is final: sending again would not help.
+pub struct Smtputf8Check {
+    inner: Arc<dyn EmailSender>,
+    supported: bool,
+}
+
+impl Smtputf8Check {
+    pub fn new(inner: Arc<dyn EmailSender>, supported: bool) -> Self {
+        Self { inner, supported }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for Smtputf8Check {
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<(), EmailError> {
+        if !self.supported && recipient.requires_smtputf8() {
+            return Err(EmailError::Smtputf8Unsupported);
+        }
+        self.inner
+            .send_email(recipient, subject, html_content, text_content, headers)
+            .await
+    }
+
+    async fn send_batch(
+        &self,
+        recipients: &[BatchRecipient],
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Vec<ChunkOutcome> {
+        let rejected: Vec<usize> = if self.supported {
+            Vec::new()
+        } else {
+            recipients
+                .iter()
+                .enumerate()
+                .filter(|(_, r)| r.email.requires_smtputf8())
+                .map(|(i, _)| i)
+                .collect()
+        };
+        if rejected.is_empty() {
+            return self
+                .inner
+                .send_batch(recipients, subject, html_content, text_content)
+                .await;
+        }
+
+        let mut outcomes = send_runs_around(
+            self.inner.as_ref(),
+            recipients,
+            &rejected,
+            subject,
+            html_content,
+            text_content,
+        )
+        .await;
+        outcomes.extend(rejected.into_iter().map(|i| ChunkOutcome {
+            recipients: i..i + 1,
+            result: Err(EmailError::Smtputf8Unsupported),
+        }));
+        outcomes
+    }
+
+    fn circuit_state(&self) -> Option<CircuitState> {
+        self.inner.circuit_state()
+    }
+
+    fn max_batch_size(&self) -> usize {
+        self.inner.max_batch_size()
+    }
+
+    fn supports_smtputf8(&self) -> bool {
+        self.supported
+    }
+}
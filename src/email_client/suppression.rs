@@ -0,0 +1,102 @@
+use std::sync::Arc;
+
+use sqlx::PgPool;
+
+use super::{
+    send_runs_around, BatchRecipient, ChunkOutcome, CircuitState, EmailError, EmailSender,
+};
+use crate::{domain::SubscriberEmail, suppressions::suppressed_among};
+
+/// Skip the addresses of the suppression list, whatever the email.
+///
+/// Skipped emails count as sent: they must not be retried.
+pub struct SuppressionCheck {
+    inner: Arc<dyn EmailSender>,
+    pool: PgPool,
+}
+
+impl SuppressionCheck {
+    pub fn new(inner: Arc<dyn EmailSender>, pool: PgPool) -> Self {
+        Self { inner, pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for SuppressionCheck {
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<(), EmailError> {
+        let suppressed = suppressed_among(&self.pool, &[recipient.as_ref().to_owned()]).await?;
+        if !suppressed.is_empty() {
+            tracing::info!("Not sending an email to a suppressed address.");
+            return Ok(());
+        }
+        self.inner
+            .send_email(recipient, subject, html_content, text_content, headers)
+            .await
+    }
+
+    async fn send_batch(
+        &self,
+        recipients: &[BatchRecipient],
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Vec<ChunkOutcome> {
+        let emails: Vec<String> = recipients
+            .iter()
+            .map(|r| r.email.as_ref().to_owned())
+            .collect();
+        let skipped = match suppressed_among(&self.pool, &emails).await {
+            Ok(skipped) => skipped,
+            Err(e) => {
+                return vec![ChunkOutcome {
+                    recipients: 0..recipients.len(),
+                    result: Err(e.into()),
+                }]
+            }
+        };
+        if skipped.is_empty() {
+            return self
+                .inner
+                .send_batch(recipients, subject, html_content, text_content)
+                .await;
+        }
+        tracing::info!(
+            n_suppressed = skipped.len(),
+            "Not sending an email to suppressed addresses."
+        );
+
+        let mut outcomes = send_runs_around(
+            self.inner.as_ref(),
+            recipients,
+            &skipped,
+            subject,
+            html_content,
+            text_content,
+        )
+        .await;
+        outcomes.extend(skipped.into_iter().map(|i| ChunkOutcome {
+            recipients: i..i + 1,
+            result: Ok(()),
+        }));
+        outcomes
+    }
+
+    fn circuit_state(&self) -> Option<CircuitState> {
+        self.inner.circuit_state()
+    }
+
+    fn max_batch_size(&self) -> usize {
+        self.inner.max_batch_size()
+    }
+
+    fn supports_smtputf8(&self) -> bool {
+        self.inner.supports_smtputf8()
+    }
+}
@@ -0,0 +1,256 @@
+use anyhow::Context;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use sqlx::{Postgres, Transaction};
+
+use super::{check_signature_age, EmailEvent, EmailEventKind, MAX_SIGNATURE_AGE_SECONDS};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Verifies and parses the calls of Mailgun webhooks.
+///
+/// Mailgun signs the timestamp followed by a random token with HMAC-SHA256,
+/// keyed with the webhook signing key, and sends both in the body.
+#[derive(Clone)]
+pub struct MailgunWebhook {
+    signing_key: Secret<String>,
+}
+
+/// The token of a verified call. A call must only be accepted once: see
+/// [`MailgunToken::claim`].
+pub struct MailgunToken {
+    token: String,
+    /// When the signature stops being accepted anyway.
+    expires_at: DateTime<Utc>,
+}
+
+impl MailgunToken {
+    /// Record the token as used, forgetting those that expired. Returns
+    /// whether it was not used yet.
+    #[tracing::instrument(name = "Claim a Mailgun webhook token", skip_all)]
+    pub async fn claim(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+    ) -> Result<bool, sqlx::Error> {
+        sqlx::query!(r#"DELETE FROM mailgun_webhook_tokens WHERE expires_at < now()"#)
+            .execute(&mut *transaction)
+            .await?;
+        let inserted = sqlx::query!(
+            r#"INSERT INTO mailgun_webhook_tokens (token, expires_at) VALUES ($1, $2)
+    ON CONFLICT (token) DO NOTHING"#,
+            self.token,
+            self.expires_at,
+        )
+        .execute(transaction)
+        .await?
+        .rows_affected()
+            == 1;
+
+        Ok(inserted)
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct Body {
+    signature: Signature,
+    #[serde(rename = "event-data")]
+    event_data: serde_json::Value,
+}
+
+#[derive(serde::Deserialize)]
+struct Signature {
+    timestamp: String,
+    token: String,
+    signature: String,
+}
+
+#[derive(serde::Deserialize)]
+struct Event {
+    id: String,
+    event: String,
+    recipient: String,
+    timestamp: f64,
+    /// `permanent` or `temporary`, for failures.
+    severity: Option<String>,
+    reason: Option<String>,
+}
+
+impl MailgunWebhook {
+    pub fn new(signing_key: Secret<String>) -> Self {
+        Self { signing_key }
+    }
+
+    pub fn verify(&self, body: &[u8]) -> Result<MailgunToken, anyhow::Error> {
+        let Body { signature, .. } = serde_json::from_slice(body).context("Invalid body")?;
+        let expected = hex_decode(&signature.signature).context("Invalid signature")?;
+        let mut mac = HmacSha256::new_from_slice(self.signing_key.expose_secret().as_bytes())
+            .expect("HMAC can take a key of any size");
+        mac.update(signature.timestamp.as_bytes());
+        mac.update(signature.token.as_bytes());
+        mac.verify_slice(&expected).context("Invalid signature")?;
+        let timestamp = signature.timestamp.parse().context("Invalid timestamp")?;
+        check_signature_age(timestamp)?;
+
+        Ok(MailgunToken {
+            token: signature.token,
+            expires_at: Utc
+                .timestamp_opt(timestamp, 0)
+                .single()
+                .context("Invalid timestamp")?
+                + Duration::seconds(MAX_SIGNATURE_AGE_SECONDS),
+        })
+    }
+
+    /// Mailgun sends one event per call. Returns nothing for events we don't
+    /// track, e.g. opens.
+    pub fn parse(body: &[u8]) -> Result<Vec<EmailEvent>, anyhow::Error> {
+        let Body { event_data, .. } = serde_json::from_slice(body)?;
+        let event: Event = serde_json::from_value(event_data.clone())?;
+        let kind = match (event.event.as_str(), event.severity.as_deref()) {
+            ("delivered", _) => EmailEventKind::Delivered,
+            // Mailgun fails right away for the addresses it suppressed.
+            ("failed", _)
+                if event
+                    .reason
+                    .as_deref()
+                    .is_some_and(|r| r.starts_with("suppress-")) =>
+            {
+                EmailEventKind::Dropped
+            }
+            ("failed", Some("permanent")) => EmailEventKind::HardBounce,
+            ("failed", _) => EmailEventKind::SoftBounce,
+            ("complained", _) => EmailEventKind::SpamReport,
+            _ => return Ok(vec![]),
+        };
+        let occurred_at = Utc
+            .timestamp_millis_opt((event.timestamp * 1000.0) as i64)
+            .single()
+            .context("Invalid timestamp")?;
+
+        Ok(vec![EmailEvent {
+            provider_event_id: event.id,
+            kind,
+            email: event.recipient,
+            occurred_at,
+            payload: event_data,
+        }])
+    }
+}
+
+fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair).ok().filter(|p| p.len() == 2)?;
+            u8::from_str_radix(pair, 16).ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use hmac::Mac;
+    use secrecy::Secret;
+    use serde_json::{json, Value};
+
+    use super::{HmacSha256, MailgunWebhook};
+    use crate::email_events::EmailEventKind;
+
+    const SIGNING_KEY: &str = "mailgun-signing-key";
+
+    fn signed_body(key: &str, timestamp: i64, event_data: Value) -> Vec<u8> {
+        let timestamp = timestamp.to_string();
+        let token = "a8ce0edb2dd8301dee6c2405235584e45aa91d1e9f979f3de0";
+        let mut mac = HmacSha256::new_from_slice(key.as_bytes()).unwrap();
+        mac.update(timestamp.as_bytes());
+        mac.update(token.as_bytes());
+        let signature: String = mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+
+        json!({
+            "signature": {"timestamp": timestamp, "token": token, "signature": signature},
+            "event-data": event_data,
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    fn webhook() -> MailgunWebhook {
+        MailgunWebhook::new(Secret::new(SIGNING_KEY.into()))
+    }
+
+    fn event(event: &str, severity: Option<&str>, reason: Option<&str>) -> Value {
+        json!({
+            "id": "CPgfbmQMTCKtHW6uIWtuVe",
+            "event": event,
+            "recipient": "subscriber@example.com",
+            "timestamp": 1670832000.5,
+            "severity": severity,
+            "reason": reason,
+        })
+    }
+
+    #[test]
+    fn a_signed_body_is_verified() {
+        let body = signed_body(SIGNING_KEY, Utc::now().timestamp(), json!({}));
+
+        assert!(webhook().verify(&body).is_ok());
+    }
+
+    #[test]
+    fn a_body_signed_with_another_key_is_rejected() {
+        let body = signed_body("another-key", Utc::now().timestamp(), json!({}));
+
+        assert!(webhook().verify(&body).is_err());
+    }
+
+    #[test]
+    fn an_old_signature_is_rejected() {
+        let body = signed_body(SIGNING_KEY, Utc::now().timestamp() - 3600, json!({}));
+
+        assert!(webhook().verify(&body).is_err());
+    }
+
+    #[test]
+    fn events_are_parsed() {
+        for (event_data, kind) in [
+            (event("delivered", None, None), EmailEventKind::Delivered),
+            (
+                event("failed", Some("permanent"), Some("bounce")),
+                EmailEventKind::HardBounce,
+            ),
+            (
+                event("failed", Some("temporary"), Some("generic")),
+                EmailEventKind::SoftBounce,
+            ),
+            (
+                event("failed", Some("permanent"), Some("suppress-bounce")),
+                EmailEventKind::Dropped,
+            ),
+            (event("complained", None, None), EmailEventKind::SpamReport),
+        ] {
+            let body = signed_body(SIGNING_KEY, 0, event_data);
+
+            let events = MailgunWebhook::parse(&body).unwrap();
+
+            assert_eq!(events.len(), 1);
+            assert_eq!(events[0].kind, kind);
+            assert_eq!(events[0].email, "subscriber@example.com");
+            assert_eq!(events[0].occurred_at.timestamp_millis(), 1670832000500);
+        }
+    }
+
+    #[test]
+    fn untracked_events_are_skipped() {
+        let body = signed_body(SIGNING_KEY, 0, event("opened", None, None));
+
+        assert!(MailgunWebhook::parse(&body).unwrap().is_empty());
+    }
+}
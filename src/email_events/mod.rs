@@ -0,0 +1,142 @@
+mod mailgun;
+mod postmark;
+mod sendgrid;
+
+pub use mailgun::{MailgunToken, MailgunWebhook};
+pub use postmark::PostmarkWebhook;
+pub use sendgrid::SendGridWebhook;
+
+use anyhow::Context;
+use chrono::{DateTime, TimeZone, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{configurations::EmailProvider, suppressions::suppress};
+
+/// Signed webhook calls older (or newer) than this are rejected as replays.
+const MAX_SIGNATURE_AGE_SECONDS: i64 = 10 * 60;
+
+/// What a provider reported about an email it sent.
+#[derive(Debug)]
+pub struct EmailEvent {
+    /// The ID the provider gave the event, to ignore redeliveries.
+    pub provider_event_id: String,
+    pub kind: EmailEventKind,
+    pub email: String,
+    pub occurred_at: DateTime<Utc>,
+    /// The event as the provider sent it.
+    pub payload: serde_json::Value,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmailEventKind {
+    Delivered,
+    /// The address does not exist: sending again will fail the same way.
+    HardBounce,
+    /// E.g. a full mailbox or a blocked IP, which may go away.
+    SoftBounce,
+    /// The provider did not even try, e.g. it knows the address bounces.
+    Dropped,
+    /// The recipient flagged the email as spam.
+    SpamReport,
+}
+
+impl EmailEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailEventKind::Delivered => "delivered",
+            EmailEventKind::HardBounce => "hard_bounce",
+            EmailEventKind::SoftBounce => "soft_bounce",
+            EmailEventKind::Dropped => "dropped",
+            EmailEventKind::SpamReport => "spam_report",
+        }
+    }
+
+    /// Whether we must stop emailing the address.
+    pub fn suppresses(&self) -> bool {
+        matches!(
+            self,
+            EmailEventKind::HardBounce | EmailEventKind::SpamReport
+        )
+    }
+}
+
+/// Store `events`, skipping those already stored, and suppress the
+/// addresses that hard bounced or complained.
+#[tracing::instrument(name = "Record email events", skip(pool, events), fields(n_events = events.len()))]
+pub async fn record_events(
+    pool: &PgPool,
+    provider: EmailProvider,
+    events: &[EmailEvent],
+) -> Result<(), anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    store_events(&mut transaction, provider, events).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store email events")?;
+
+    Ok(())
+}
+
+/// [`record_events`] within a transaction of the caller.
+pub async fn store_events(
+    transaction: &mut Transaction<'_, Postgres>,
+    provider: EmailProvider,
+    events: &[EmailEvent],
+) -> Result<(), anyhow::Error> {
+    for event in events {
+        let inserted = sqlx::query!(
+            r#"INSERT INTO email_events
+    (id, provider, provider_event_id, event_type, email, occurred_at, payload)
+    VALUES ($1, $2, $3, $4, $5, $6, $7)
+    ON CONFLICT (provider, provider_event_id) DO NOTHING"#,
+            Uuid::new_v4(),
+            provider.as_str(),
+            event.provider_event_id,
+            event.kind.as_str(),
+            event.email,
+            event.occurred_at,
+            event.payload,
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to store an email event")?
+        .rows_affected()
+            == 1;
+        if !inserted || !event.kind.suppresses() {
+            continue;
+        }
+
+        let newly_suppressed = suppress(
+            transaction,
+            &event.email,
+            event.kind.as_str(),
+            provider.as_str(),
+        )
+        .await
+        .context("Failed to suppress an address")?;
+        if newly_suppressed {
+            tracing::info!(
+                event_type = event.kind.as_str(),
+                "Suppressed an address the provider reported."
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn check_signature_age(timestamp: i64) -> Result<(), anyhow::Error> {
+    let signed_at = Utc
+        .timestamp_opt(timestamp, 0)
+        .single()
+        .context("Invalid timestamp")?;
+    if (Utc::now() - signed_at).num_seconds().abs() > MAX_SIGNATURE_AGE_SECONDS {
+        anyhow::bail!("The signature is too old");
+    }
+    Ok(())
+}
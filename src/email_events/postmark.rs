@@ -0,0 +1,187 @@
+use anyhow::Context;
+use axum::http::HeaderMap;
+use chrono::{DateTime, Utc};
+use secrecy::{ExposeSecret, Secret};
+use subtle::ConstantTimeEq;
+
+use super::{EmailEvent, EmailEventKind};
+use crate::authentication::basic_authentication;
+
+/// Verifies and parses the calls of Postmark webhooks.
+///
+/// Postmark does not sign its calls: the webhook URL carries `Basic` auth
+/// credentials instead.
+#[derive(Clone)]
+pub struct PostmarkWebhook {
+    username: String,
+    password: Secret<String>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Event {
+    record_type: String,
+    /// The bounce or complaint ID. Deliveries only have a `MessageID`.
+    #[serde(rename = "ID")]
+    id: Option<i64>,
+    #[serde(rename = "MessageID")]
+    message_id: String,
+    /// Bounces and complaints.
+    email: Option<String>,
+    /// Deliveries.
+    recipient: Option<String>,
+    /// `HardBounce`, `SoftBounce`, ... for bounces.
+    #[serde(rename = "Type")]
+    bounce_type: Option<String>,
+    bounced_at: Option<DateTime<Utc>>,
+    delivered_at: Option<DateTime<Utc>>,
+}
+
+impl PostmarkWebhook {
+    pub fn new(username: String, password: Secret<String>) -> Self {
+        Self { username, password }
+    }
+
+    pub fn verify(&self, headers: &HeaderMap) -> Result<(), anyhow::Error> {
+        let credentials = basic_authentication(headers)?;
+        let username_matches = credentials
+            .username
+            .as_bytes()
+            .ct_eq(self.username.as_bytes());
+        let password_matches = credentials
+            .password
+            .expose_secret()
+            .as_bytes()
+            .ct_eq(self.password.expose_secret().as_bytes());
+        if !bool::from(username_matches & password_matches) {
+            anyhow::bail!("Invalid credentials");
+        }
+        Ok(())
+    }
+
+    /// Postmark sends one event per call. Returns nothing for events we don't
+    /// track, e.g. opens.
+    pub fn parse(body: &[u8]) -> Result<Vec<EmailEvent>, anyhow::Error> {
+        let payload: serde_json::Value = serde_json::from_slice(body)?;
+        let event: Event = serde_json::from_value(payload.clone())?;
+        let kind = match (event.record_type.as_str(), event.bounce_type.as_deref()) {
+            ("Delivery", _) => EmailEventKind::Delivered,
+            ("Bounce", Some("HardBounce")) => EmailEventKind::HardBounce,
+            ("Bounce", _) => EmailEventKind::SoftBounce,
+            ("SpamComplaint", _) => EmailEventKind::SpamReport,
+            _ => return Ok(vec![]),
+        };
+
+        Ok(vec![EmailEvent {
+            provider_event_id: match event.id {
+                Some(id) => format!("{}-{}", event.record_type, id),
+                None => format!("{}-{}", event.record_type, event.message_id),
+            },
+            kind,
+            email: event
+                .email
+                .or(event.recipient)
+                .context("The event has no recipient")?,
+            occurred_at: event
+                .bounced_at
+                .or(event.delivered_at)
+                .context("The event has no date")?,
+            payload,
+        }])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header, HeaderMap};
+    use secrecy::Secret;
+    use serde_json::json;
+
+    use super::PostmarkWebhook;
+    use crate::email_events::EmailEventKind;
+
+    fn webhook() -> PostmarkWebhook {
+        PostmarkWebhook::new("postmark".into(), Secret::new("webhook-password".into()))
+    }
+
+    fn basic_auth(username: &str, password: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let credentials = base64::encode(format!("{}:{}", username, password));
+        headers.insert(
+            header::AUTHORIZATION,
+            format!("Basic {}", credentials).parse().unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn the_configured_credentials_are_accepted() {
+        assert!(webhook()
+            .verify(&basic_auth("postmark", "webhook-password"))
+            .is_ok());
+    }
+
+    #[test]
+    fn other_credentials_are_rejected() {
+        assert!(webhook()
+            .verify(&basic_auth("postmark", "wrong-password"))
+            .is_err());
+        assert!(webhook().verify(&HeaderMap::new()).is_err());
+    }
+
+    #[test]
+    fn events_are_parsed() {
+        let bounce = |bounce_type: &str| {
+            json!({
+                "RecordType": "Bounce",
+                "ID": 4323372036854775807_i64,
+                "Type": bounce_type,
+                "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+                "Email": "subscriber@example.com",
+                "BouncedAt": "2022-12-12T08:00:00.1234567Z",
+            })
+        };
+        for (payload, kind) in [
+            (bounce("HardBounce"), EmailEventKind::HardBounce),
+            (bounce("SoftBounce"), EmailEventKind::SoftBounce),
+            (
+                json!({
+                    "RecordType": "SpamComplaint",
+                    "ID": 42,
+                    "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+                    "Email": "subscriber@example.com",
+                    "BouncedAt": "2022-12-12T08:00:00Z",
+                }),
+                EmailEventKind::SpamReport,
+            ),
+            (
+                json!({
+                    "RecordType": "Delivery",
+                    "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+                    "Recipient": "subscriber@example.com",
+                    "DeliveredAt": "2022-12-12T08:00:00Z",
+                }),
+                EmailEventKind::Delivered,
+            ),
+        ] {
+            let events = PostmarkWebhook::parse(payload.to_string().as_bytes()).unwrap();
+
+            assert_eq!(events.len(), 1);
+            assert_eq!(events[0].kind, kind);
+            assert_eq!(events[0].email, "subscriber@example.com");
+        }
+    }
+
+    #[test]
+    fn untracked_events_are_skipped() {
+        let payload = json!({
+            "RecordType": "Open",
+            "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+            "Recipient": "subscriber@example.com",
+        });
+
+        assert!(PostmarkWebhook::parse(payload.to_string().as_bytes())
+            .unwrap()
+            .is_empty());
+    }
+}
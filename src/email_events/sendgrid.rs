@@ -0,0 +1,200 @@
+use anyhow::Context;
+use axum::http::HeaderMap;
+use chrono::{TimeZone, Utc};
+use p256::{
+    ecdsa::{signature::Verifier, Signature, VerifyingKey},
+    pkcs8::DecodePublicKey,
+};
+
+use super::{check_signature_age, EmailEvent, EmailEventKind};
+
+const SIGNATURE_HEADER: &str = "X-Twilio-Email-Event-Webhook-Signature";
+const TIMESTAMP_HEADER: &str = "X-Twilio-Email-Event-Webhook-Timestamp";
+
+/// Verifies and parses the calls of the SendGrid Event Webhook.
+///
+/// SendGrid signs the timestamp followed by the body with ECDSA over P-256.
+#[derive(Clone)]
+pub struct SendGridWebhook {
+    verification_key: VerifyingKey,
+}
+
+#[derive(serde::Deserialize)]
+struct Event {
+    email: String,
+    timestamp: i64,
+    event: String,
+    sg_event_id: String,
+    /// `bounce` or `blocked`, for bounces.
+    #[serde(rename = "type")]
+    bounce_type: Option<String>,
+}
+
+impl SendGridWebhook {
+    /// `verification_key` is the base64 public key shown in the settings of
+    /// the webhook.
+    pub fn new(verification_key: &str) -> Result<Self, anyhow::Error> {
+        let der = base64::decode(verification_key.trim())
+            .context("The verification key is not valid base64")?;
+        let verification_key = VerifyingKey::from_public_key_der(&der)
+            .map_err(|e| anyhow::anyhow!("Invalid verification key: {}", e))?;
+        Ok(Self { verification_key })
+    }
+
+    pub fn verify(&self, headers: &HeaderMap, body: &[u8]) -> Result<(), anyhow::Error> {
+        let header = |name| {
+            headers
+                .get(name)
+                .with_context(|| format!("Missing {}", name))?
+                .to_str()
+                .with_context(|| format!("Invalid {}", name))
+        };
+        let timestamp = header(TIMESTAMP_HEADER)?;
+        let signature = base64::decode(header(SIGNATURE_HEADER)?)
+            .context("The signature is not valid base64")?;
+        let signature = Signature::from_der(&signature).context("Invalid signature")?;
+
+        let mut payload = timestamp.as_bytes().to_vec();
+        payload.extend_from_slice(body);
+        self.verification_key
+            .verify(&payload, &signature)
+            .context("Invalid signature")?;
+        check_signature_age(timestamp.parse().context("Invalid timestamp")?)
+    }
+
+    /// SendGrid sends a batch of events. Those we don't track, e.g. opens,
+    /// are left out.
+    pub fn parse(body: &[u8]) -> Result<Vec<EmailEvent>, anyhow::Error> {
+        let payloads: Vec<serde_json::Value> = serde_json::from_slice(body)?;
+        let mut events = Vec::new();
+        for payload in payloads {
+            let event: Event = serde_json::from_value(payload.clone())?;
+            let kind = match (event.event.as_str(), event.bounce_type.as_deref()) {
+                ("delivered", _) => EmailEventKind::Delivered,
+                ("bounce", Some("blocked")) => EmailEventKind::SoftBounce,
+                ("bounce", _) => EmailEventKind::HardBounce,
+                ("dropped", _) => EmailEventKind::Dropped,
+                ("spamreport", _) => EmailEventKind::SpamReport,
+                _ => continue,
+            };
+            events.push(EmailEvent {
+                provider_event_id: event.sg_event_id,
+                kind,
+                email: event.email,
+                occurred_at: Utc
+                    .timestamp_opt(event.timestamp, 0)
+                    .single()
+                    .context("Invalid timestamp")?,
+                payload,
+            });
+        }
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderMap;
+    use chrono::Utc;
+    use p256::{
+        ecdsa::{signature::Signer, Signature, SigningKey},
+        pkcs8::EncodePublicKey,
+    };
+    use serde_json::json;
+
+    use super::{SendGridWebhook, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+    use crate::email_events::EmailEventKind;
+
+    fn signing_key() -> SigningKey {
+        SigningKey::from_bytes(&[7u8; 32].into()).unwrap()
+    }
+
+    fn webhook() -> SendGridWebhook {
+        let der = signing_key().verifying_key().to_public_key_der().unwrap();
+        SendGridWebhook::new(&base64::encode(der.as_bytes())).unwrap()
+    }
+
+    fn signed_headers(timestamp: i64, body: &[u8]) -> HeaderMap {
+        let timestamp = timestamp.to_string();
+        let signature: Signature = signing_key().sign(&[timestamp.as_bytes(), body].concat());
+        let mut headers = HeaderMap::new();
+        headers.insert(TIMESTAMP_HEADER, timestamp.parse().unwrap());
+        headers.insert(
+            SIGNATURE_HEADER,
+            base64::encode(signature.to_der()).parse().unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn a_signed_body_is_verified() {
+        let body = br#"[{"event": "delivered"}]"#;
+
+        let headers = signed_headers(Utc::now().timestamp(), body);
+
+        assert!(webhook().verify(&headers, body).is_ok());
+    }
+
+    #[test]
+    fn a_tampered_body_is_rejected() {
+        let headers = signed_headers(Utc::now().timestamp(), br#"[{"event": "delivered"}]"#);
+
+        assert!(webhook()
+            .verify(&headers, br#"[{"event": "bounce"}]"#)
+            .is_err());
+    }
+
+    #[test]
+    fn an_old_signature_is_rejected() {
+        let body = br#"[{"event": "delivered"}]"#;
+
+        let headers = signed_headers(Utc::now().timestamp() - 3600, body);
+
+        assert!(webhook().verify(&headers, body).is_err());
+    }
+
+    #[test]
+    fn a_missing_signature_is_rejected() {
+        assert!(webhook().verify(&HeaderMap::new(), b"[]").is_err());
+    }
+
+    #[test]
+    fn events_are_parsed_and_untracked_ones_skipped() {
+        let event = |event: &str, bounce_type: Option<&str>| {
+            json!({
+                "email": "subscriber@example.com",
+                "timestamp": 1670832000,
+                "event": event,
+                "type": bounce_type,
+                "sg_event_id": format!("{}-{:?}", event, bounce_type),
+            })
+        };
+        let body = json!([
+            event("processed", None),
+            event("delivered", None),
+            event("bounce", Some("bounce")),
+            event("bounce", Some("blocked")),
+            event("dropped", None),
+            event("spamreport", None),
+            event("open", None),
+        ]);
+
+        let events = SendGridWebhook::parse(body.to_string().as_bytes()).unwrap();
+
+        let kinds: Vec<_> = events.iter().map(|e| e.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                EmailEventKind::Delivered,
+                EmailEventKind::HardBounce,
+                EmailEventKind::SoftBounce,
+                EmailEventKind::Dropped,
+                EmailEventKind::SpamReport,
+            ]
+        );
+        assert_eq!(events[1].email, "subscriber@example.com");
+        assert_eq!(events[1].provider_event_id, "bounce-Some(\"bounce\")");
+        assert_eq!(events[1].occurred_at.timestamp(), 1670832000);
+        assert_eq!(events[1].payload["type"], "bounce");
+    }
+}
@@ -0,0 +1,184 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::{
+    domain::SubscriberEmail,
+    email_client::EmailSender,
+    issue_delivery_worker::{retry_delay, ExecutionOutcome},
+};
+
+/// How many times a failed email is retried before it is dropped.
+const MAX_RETRIES: i16 = 8;
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+struct OutboxEmail {
+    id: Uuid,
+    recipient: String,
+    subject: String,
+    html_content: String,
+    text_content: String,
+    n_retries: i16,
+}
+
+/// Record an email in the outbox as part of `transaction`.
+///
+/// Nothing is sent until the transaction commits and a dispatcher picks the
+/// row up, so the email and the data it refers to are persisted atomically.
+#[tracing::instrument(
+    name = "Store email in the outbox",
+    skip_all,
+    fields(recipient = %recipient.as_ref())
+)]
+pub async fn enqueue_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    recipient: &SubscriberEmail,
+    subject: &str,
+    html_content: &str,
+    text_content: &str,
+) -> Result<Uuid, sqlx::Error> {
+    let id = Uuid::new_v4();
+    sqlx::query!(
+        r#"INSERT INTO email_outbox (id, recipient, subject, html_content, text_content, created_at)
+    VALUES ($1, $2, $3, $4, $5, $6)"#,
+        id,
+        recipient.as_ref(),
+        subject,
+        html_content,
+        text_content,
+        Utc::now(),
+    )
+    .execute(transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    Ok(id)
+}
+
+pub async fn run_dispatcher_until_stopped(pool: PgPool, email_sender: Arc<dyn EmailSender>) {
+    loop {
+        match try_dispatch_email(&pool, email_sender.as_ref()).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(
+        email_id = tracing::field::Empty,
+        recipient = tracing::field::Empty
+    ),
+    err
+)]
+pub async fn try_dispatch_email(
+    pool: &PgPool,
+    email_sender: &dyn EmailSender,
+) -> Result<ExecutionOutcome, sqlx::Error> {
+    let (transaction, email) = match dequeue_email(pool).await? {
+        Some(email) => email,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+    Span::current()
+        .record("email_id", display(email.id))
+        .record("recipient", display(&email.recipient));
+
+    match SubscriberEmail::parse(email.recipient.clone()) {
+        Ok(recipient) => {
+            if let Err(e) = email_sender
+                .send_email(
+                    &recipient,
+                    &email.subject,
+                    &email.html_content,
+                    &email.text_content,
+                    &[],
+                )
+                .await
+            {
+                if e.is_retryable() && email.n_retries < MAX_RETRIES {
+                    tracing::warn!(
+                        error.cause_chain = ?e,
+                        "Failed to send an email from the outbox. Retrying later.",
+                    );
+                    schedule_retry(transaction, &email).await?;
+                    return Ok(ExecutionOutcome::TaskCompleted);
+                }
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    "Failed to send an email from the outbox. Giving up.",
+                );
+            }
+        }
+        Err(error) => {
+            tracing::warn!(
+                error.message = %error,
+                "Dropping an email from the outbox. Its recipient is invalid",
+            );
+        }
+    }
+
+    delete_email(transaction, email.id).await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_email(pool: &PgPool) -> Result<Option<(PgTransaction, OutboxEmail)>, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let email = sqlx::query_as!(
+        OutboxEmail,
+        r#"SELECT id, recipient, subject, html_content, text_content, n_retries
+    FROM email_outbox
+    WHERE execute_after <= now()
+    ORDER BY created_at
+    FOR UPDATE
+    SKIP LOCKED
+    LIMIT 1"#,
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+
+    Ok(email.map(|email| (transaction, email)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_email(mut transaction: PgTransaction, id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query!(r#"DELETE FROM email_outbox WHERE id = $1"#, id)
+        .execute(&mut transaction)
+        .await?;
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn schedule_retry(
+    mut transaction: PgTransaction,
+    email: &OutboxEmail,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE email_outbox
+    SET n_retries = n_retries + 1, execute_after = $2
+    WHERE id = $1"#,
+        email.id,
+        Utc::now() + retry_delay(email.n_retries),
+    )
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+
+    Ok(())
+}
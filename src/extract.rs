@@ -0,0 +1,56 @@
+use axum::{
+    async_trait,
+    body::HttpBody,
+    extract::FromRequest,
+    http::{header, Request},
+    response::{IntoResponse, Response},
+    BoxError, Form, Json,
+};
+use serde::de::DeserializeOwned;
+
+/// How the body of a request was encoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BodyFormat {
+    Json,
+    /// `application/x-www-form-urlencoded`, as sent by HTML forms.
+    Form,
+}
+
+/// Deserialize the body as JSON or as a form, depending on `Content-Type`.
+///
+/// Anything that is not a form is treated as JSON, so API clients keep
+/// getting the same rejections as with [`Json`].
+pub struct JsonOrForm<T>(pub T, pub BodyFormat);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for JsonOrForm<T>
+where
+    T: DeserializeOwned,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let is_form = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.starts_with(mime::APPLICATION_WWW_FORM_URLENCODED.as_ref()))
+            .unwrap_or(false);
+
+        if is_form {
+            let Form(value) = Form::<T>::from_request(req, state)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            Ok(Self(value, BodyFormat::Form))
+        } else {
+            let Json(value) = Json::<T>::from_request(req, state)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            Ok(Self(value, BodyFormat::Json))
+        }
+    }
+}
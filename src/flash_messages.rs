@@ -0,0 +1,81 @@
+use cookie::Cookie;
+
+use crate::session_state::SignedCookies;
+
+const FLASH_COOKIE_NAME: &str = "_flash";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Level {
+    Info,
+    Error,
+}
+
+/// A message shown once, on the next page the user visits.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct FlashMessage {
+    level: Level,
+    content: String,
+}
+
+impl FlashMessage {
+    pub fn info(content: impl Into<String>) -> Self {
+        Self {
+            level: Level::Info,
+            content: content.into(),
+        }
+    }
+
+    pub fn error(content: impl Into<String>) -> Self {
+        Self {
+            level: Level::Error,
+            content: content.into(),
+        }
+    }
+
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+}
+
+impl SignedCookies {
+    fn flash_messages(&self) -> Vec<FlashMessage> {
+        self.get(FLASH_COOKIE_NAME)
+            .and_then(|cookie| serde_json::from_str(cookie.value()).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn with_flash(self, message: FlashMessage) -> Self {
+        let mut messages = self.flash_messages();
+        messages.push(message);
+        let value = serde_json::to_string(&messages).expect("Failed to serialize flash messages");
+        self.with_cookie(Cookie::new(FLASH_COOKIE_NAME, value))
+    }
+
+    /// Read the pending flash messages and clear them, so they are only shown
+    /// once.
+    pub fn take_flash(self) -> (Self, Vec<FlashMessage>) {
+        let messages = self.flash_messages();
+        if messages.is_empty() {
+            (self, messages)
+        } else {
+            (self.without_cookie(FLASH_COOKIE_NAME), messages)
+        }
+    }
+}
+
+/// Render flash messages as HTML paragraphs, escaping their content.
+pub fn render_flash_messages(messages: &[FlashMessage]) -> String {
+    messages
+        .iter()
+        .map(|m| {
+            format!(
+                "<p><i>{}</i></p>\n",
+                htmlescape::encode_minimal(m.content())
+            )
+        })
+        .collect()
+}
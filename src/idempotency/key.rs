@@ -0,0 +1,66 @@
+use axum::http::HeaderMap;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+#[derive(Debug)]
+pub struct IdempotencyKey(String);
+
+impl IdempotencyKey {
+    /// Extract the key sent in the `Idempotency-Key` header, if any.
+    pub fn from_headers(headers: &HeaderMap) -> Result<Option<Self>, String> {
+        match headers.get(IDEMPOTENCY_KEY_HEADER) {
+            None => Ok(None),
+            Some(value) => {
+                let value = value
+                    .to_str()
+                    .map_err(|_| "The idempotency key must be a valid string.".to_string())?;
+                Self::try_from(value.to_string()).map(Some)
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for IdempotencyKey {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        if s.is_empty() {
+            return Err("The idempotency key cannot be empty.".into());
+        }
+        let max_length = 50;
+        if s.len() >= max_length {
+            return Err(format!(
+                "The idempotency key must be shorter than {} characters.",
+                max_length
+            ));
+        }
+        Ok(Self(s))
+    }
+}
+
+impl AsRef<str> for IdempotencyKey {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IdempotencyKey;
+
+    #[test]
+    fn empty_keys_are_rejected() {
+        assert!(IdempotencyKey::try_from("".to_string()).is_err());
+    }
+
+    #[test]
+    fn keys_of_50_characters_or_more_are_rejected() {
+        assert!(IdempotencyKey::try_from("a".repeat(50)).is_err());
+    }
+
+    #[test]
+    fn a_uuid_is_a_valid_key() {
+        let key = uuid::Uuid::new_v4().to_string();
+        assert!(IdempotencyKey::try_from(key).is_ok());
+    }
+}
@@ -0,0 +1,5 @@
+mod key;
+mod persistence;
+
+pub use key::*;
+pub use persistence::*;
@@ -0,0 +1,150 @@
+use axum::{
+    body::{boxed, Full},
+    http::StatusCode,
+    response::Response,
+};
+use chrono::{Duration, Utc};
+use sqlx::{postgres::PgHasArrayType, PgPool, Postgres, Transaction};
+
+use super::IdempotencyKey;
+
+/// How long a saved response is replayed for. The same key can be used again
+/// afterwards.
+const IDEMPOTENCY_KEY_TTL_HOURS: i64 = 24;
+
+#[derive(Debug, sqlx::Type)]
+#[sqlx(type_name = "header_pair")]
+struct HeaderPairRecord {
+    name: String,
+    value: Vec<u8>,
+}
+
+impl PgHasArrayType for HeaderPairRecord {
+    fn array_type_info() -> sqlx::postgres::PgTypeInfo {
+        sqlx::postgres::PgTypeInfo::with_name("_header_pair")
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
+pub enum NextAction {
+    /// The key was not seen before: do the work inside this transaction and
+    /// hand it back to [`save_response`].
+    StartProcessing(Transaction<'static, Postgres>),
+    ReturnSavedResponse(Response),
+}
+
+/// Claim `idempotency_key` within `scope` for the current request. The
+/// scope names the endpoint, and the user for authenticated ones: keys of
+/// different scopes never meet.
+///
+/// A concurrent request holding the same key blocks on the `INSERT` until the
+/// first one commits (we then replay its response) or rolls back (we then do
+/// the work ourselves).
+#[tracing::instrument(name = "Claim idempotency key", skip(pool))]
+pub async fn try_processing(
+    pool: &PgPool,
+    scope: &str,
+    idempotency_key: &IdempotencyKey,
+) -> Result<NextAction, anyhow::Error> {
+    sqlx::query!(
+        r#"DELETE FROM idempotency WHERE created_at < $1"#,
+        Utc::now() - Duration::hours(IDEMPOTENCY_KEY_TTL_HOURS),
+    )
+    .execute(pool)
+    .await?;
+
+    let mut transaction = pool.begin().await?;
+    let n_inserted_rows = sqlx::query!(
+        r#"INSERT INTO idempotency (scope, idempotency_key, created_at)
+    VALUES ($1, $2, $3)
+    ON CONFLICT DO NOTHING"#,
+        scope,
+        idempotency_key.as_ref(),
+        Utc::now(),
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+
+    if n_inserted_rows > 0 {
+        Ok(NextAction::StartProcessing(transaction))
+    } else {
+        let saved_response = get_saved_response(pool, scope, idempotency_key)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("We expected a saved response, we didn't find it"))?;
+        Ok(NextAction::ReturnSavedResponse(saved_response))
+    }
+}
+
+#[tracing::instrument(name = "Get saved response", skip(pool))]
+pub async fn get_saved_response(
+    pool: &PgPool,
+    scope: &str,
+    idempotency_key: &IdempotencyKey,
+) -> Result<Option<Response>, anyhow::Error> {
+    let saved_response = sqlx::query!(
+        r#"SELECT
+        response_status_code as "response_status_code!",
+        response_headers as "response_headers!: Vec<HeaderPairRecord>",
+        response_body as "response_body!"
+    FROM idempotency
+    WHERE scope = $1 AND idempotency_key = $2"#,
+        scope,
+        idempotency_key.as_ref(),
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    if let Some(r) = saved_response {
+        let status_code = StatusCode::from_u16(r.response_status_code.try_into()?)?;
+        let mut response = Response::builder().status(status_code);
+        for HeaderPairRecord { name, value } in r.response_headers {
+            response = response.header(name, value);
+        }
+        Ok(Some(response.body(boxed(Full::from(r.response_body)))?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Persist `http_response` against `idempotency_key` within `scope` and
+/// commit the transaction obtained from [`try_processing`].
+#[tracing::instrument(name = "Save response", skip(transaction, http_response))]
+pub async fn save_response(
+    mut transaction: Transaction<'static, Postgres>,
+    scope: &str,
+    idempotency_key: &IdempotencyKey,
+    http_response: Response,
+) -> Result<Response, anyhow::Error> {
+    let (response_head, body) = http_response.into_parts();
+    let body = hyper::body::to_bytes(body).await?;
+    let status_code = response_head.status.as_u16() as i16;
+    let headers = response_head
+        .headers
+        .iter()
+        .map(|(name, value)| HeaderPairRecord {
+            name: name.as_str().to_owned(),
+            value: value.as_bytes().to_owned(),
+        })
+        .collect::<Vec<_>>();
+
+    sqlx::query_unchecked!(
+        r#"UPDATE idempotency
+    SET
+        response_status_code = $2,
+        response_headers = $3,
+        response_body = $4
+    WHERE scope = $5 AND idempotency_key = $1"#,
+        idempotency_key.as_ref(),
+        status_code,
+        headers,
+        body.as_ref(),
+        scope,
+    )
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+
+    let http_response = Response::from_parts(response_head, boxed(Full::from(body)));
+    Ok(http_response)
+}
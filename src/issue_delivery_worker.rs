@@ -0,0 +1,301 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::Utc;
+use sqlx::PgPool;
+use tracing::Span;
+use uuid::Uuid;
+
+use crate::{
+    domain::SubscriberEmail,
+    email_client::{BatchRecipient, EmailSender},
+    unsubscribe::UnsubscribeLinks,
+};
+
+/// How many times a failed delivery is retried before the task is dropped.
+const MAX_RETRIES: i16 = 5;
+
+/// How long claimed tasks are hidden from other workers. Those of a worker
+/// that died before recording their outcome are delivered again afterwards.
+const CLAIM_DURATION_MINUTES: i64 = 10;
+
+/// Stands for the unsubscribe link of each subscriber in the issue's content.
+const UNSUBSCRIBE_LINK: &str = "{{unsubscribe_link}}";
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+struct DeliveryTask {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_retries: i16,
+    /// `None` once the subscriber is no longer confirmed.
+    subscriber_id: Option<Uuid>,
+}
+
+struct NewsletterIssue {
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+/// Keep draining `issue_delivery_queue`. Every application instance runs one of
+/// these: tasks are claimed with `FOR UPDATE SKIP LOCKED` and hidden until
+/// their outcome is recorded, so workers never step on each other.
+pub async fn run_worker_until_stopped(
+    pool: PgPool,
+    email_sender: Arc<dyn EmailSender>,
+    unsubscribe_links: UnsubscribeLinks,
+) {
+    loop {
+        match try_execute_task(&pool, email_sender.as_ref(), &unsubscribe_links).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+#[tracing::instrument(skip_all, fields(n_tasks = tracing::field::Empty), err)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_sender: &dyn EmailSender,
+    unsubscribe_links: &UnsubscribeLinks,
+) -> Result<ExecutionOutcome, sqlx::Error> {
+    // No transaction is held while the provider is called: the outcome of
+    // each of its calls is recorded on its own, and a later failure cannot
+    // bring back what was already sent.
+    let tasks = claim_tasks(pool, email_sender.max_batch_size()).await?;
+    let issue_id = match tasks.first() {
+        Some(task) => task.newsletter_issue_id,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+    Span::current().record("n_tasks", tasks.len());
+
+    deliver_issue(pool, email_sender, unsubscribe_links, issue_id, tasks).await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+/// Send an issue to the subscribers of `tasks` in as few calls as the email
+/// provider allows.
+#[tracing::instrument(skip_all, fields(newsletter_issue_id = %issue_id))]
+async fn deliver_issue(
+    pool: &PgPool,
+    email_sender: &dyn EmailSender,
+    unsubscribe_links: &UnsubscribeLinks,
+    issue_id: Uuid,
+    tasks: Vec<DeliveryTask>,
+) -> Result<(), sqlx::Error> {
+    let mut recipients = Vec::new();
+    let mut recipient_tasks = Vec::new();
+    for task in tasks {
+        let subscriber_id = match task.subscriber_id {
+            Some(subscriber_id) => subscriber_id,
+            None => {
+                tracing::info!(
+                    subscriber_email = %task.subscriber_email,
+                    "Skipping a subscriber who is no longer confirmed.",
+                );
+                delete_task(pool, &task).await?;
+                continue;
+            }
+        };
+        match SubscriberEmail::parse(task.subscriber_email.clone()) {
+            Ok(email) => {
+                recipients.push(BatchRecipient {
+                    email,
+                    substitutions: vec![(
+                        UNSUBSCRIBE_LINK.into(),
+                        unsubscribe_links.link_for(subscriber_id),
+                    )],
+                    // RFC 8058: mailbox providers show their own unsubscribe
+                    // button, which POSTs to this URL.
+                    headers: vec![
+                        (
+                            "List-Unsubscribe".into(),
+                            format!("<{}>", unsubscribe_links.one_click_link_for(subscriber_id)),
+                        ),
+                        (
+                            "List-Unsubscribe-Post".into(),
+                            "List-Unsubscribe=One-Click".into(),
+                        ),
+                    ],
+                });
+                recipient_tasks.push(task);
+            }
+            Err(error) => {
+                tracing::warn!(
+                    subscriber_email = %task.subscriber_email,
+                    error.message = %error,
+                    "Skipping a confirmed subscriber. Their stored contact details are invalid",
+                );
+                delete_task(pool, &task).await?;
+            }
+        }
+    }
+    if recipients.is_empty() {
+        return Ok(());
+    }
+
+    let issue = get_issue(pool, issue_id).await?;
+    let outcomes = email_sender
+        .send_batch(
+            &recipients,
+            &issue.title,
+            &html_with_footer(&issue.html_content, UNSUBSCRIBE_LINK),
+            &text_with_footer(&issue.text_content, UNSUBSCRIBE_LINK),
+        )
+        .await;
+
+    for outcome in outcomes {
+        let tasks = &recipient_tasks[outcome.recipients];
+        match outcome.result {
+            Ok(()) => {
+                for task in tasks {
+                    delete_task(pool, task).await?;
+                }
+            }
+            Err(e) => {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    n_subscribers = tasks.len(),
+                    "Failed to deliver issue to confirmed subscribers.",
+                );
+                for task in tasks {
+                    if e.is_retryable() && task.n_retries < MAX_RETRIES {
+                        schedule_retry(pool, task).await?;
+                    } else {
+                        tracing::error!(
+                            subscriber_email = %task.subscriber_email,
+                            "Failed to deliver issue to a confirmed subscriber. Giving up.",
+                        );
+                        delete_task(pool, task).await?;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Claim up to `limit` due tasks of a single issue, for
+/// [`CLAIM_DURATION_MINUTES`].
+#[tracing::instrument(skip_all)]
+async fn claim_tasks(pool: &PgPool, limit: usize) -> Result<Vec<DeliveryTask>, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let issue = sqlx::query!(
+        r#"SELECT newsletter_issue_id
+    FROM issue_delivery_queue
+    WHERE execute_after <= now()
+    FOR UPDATE
+    SKIP LOCKED
+    LIMIT 1"#,
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+    let issue = match issue {
+        Some(issue) => issue,
+        None => return Ok(Vec::new()),
+    };
+    let tasks = sqlx::query_as!(
+        DeliveryTask,
+        r#"SELECT q.newsletter_issue_id, q.subscriber_email, q.n_retries,
+        s.id AS "subscriber_id?"
+    FROM issue_delivery_queue q
+    LEFT JOIN subscriptions s
+        ON s.email = q.subscriber_email AND s.status = 'confirmed'
+    WHERE q.newsletter_issue_id = $1 AND q.execute_after <= now()
+    FOR UPDATE OF q
+    SKIP LOCKED
+    LIMIT $2"#,
+        issue.newsletter_issue_id,
+        limit as i64,
+    )
+    .fetch_all(&mut transaction)
+    .await?;
+    let subscriber_emails: Vec<String> = tasks.iter().map(|t| t.subscriber_email.clone()).collect();
+    sqlx::query!(
+        r#"UPDATE issue_delivery_queue
+    SET execute_after = $3
+    WHERE newsletter_issue_id = $1 AND subscriber_email = ANY($2)"#,
+        issue.newsletter_issue_id,
+        &subscriber_emails,
+        Utc::now() + chrono::Duration::minutes(CLAIM_DURATION_MINUTES),
+    )
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+
+    Ok(tasks)
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(pool: &PgPool, task: &DeliveryTask) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"DELETE FROM issue_delivery_queue
+    WHERE newsletter_issue_id = $1 AND subscriber_email = $2"#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn schedule_retry(pool: &PgPool, task: &DeliveryTask) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE issue_delivery_queue
+    SET n_retries = n_retries + 1, execute_after = $3
+    WHERE newsletter_issue_id = $1 AND subscriber_email = $2"#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        Utc::now() + retry_delay(task.n_retries),
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+fn html_with_footer(html_content: &str, unsubscribe_link: &str) -> String {
+    format!(
+        "{}<p><a href=\"{}\">Unsubscribe</a> from this newsletter.</p>",
+        html_content, unsubscribe_link
+    )
+}
+
+fn text_with_footer(text_content: &str, unsubscribe_link: &str) -> String {
+    format!(
+        "{}\n\n--\nUnsubscribe from this newsletter: {}",
+        text_content, unsubscribe_link
+    )
+}
+
+/// Back off exponentially: 30s, 1m, 2m, 4m, ...
+pub(crate) fn retry_delay(n_retries: i16) -> chrono::Duration {
+    chrono::Duration::seconds(30 * 2i64.pow(n_retries as u32))
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, sqlx::Error> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"SELECT title, text_content, html_content
+    FROM newsletter_issues
+    WHERE newsletter_issue_id = $1"#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(issue)
+}
@@ -1,6 +1,19 @@
+pub mod authentication;
+pub mod canonical_emails;
 pub mod configurations;
 pub mod domain;
 pub mod email_client;
+pub mod email_events;
+pub mod email_outbox;
+pub mod extract;
+pub mod flash_messages;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod problem_details;
 pub mod routes;
+pub mod session_state;
 pub mod startup;
+pub mod suppressions;
 pub mod telemetry;
+pub mod unsubscribe;
+pub mod utils;
@@ -1,12 +1,14 @@
-use std::net::SocketAddr;
+use std::{net::SocketAddr, sync::Arc};
 
 use axum_zero2prod::{
     configurations::get_configuration,
-    email_client::EmailClient,
-    startup::get_app,
+    email_client::{EmailSender, SuppressionCheck},
+    email_outbox::run_dispatcher_until_stopped,
+    issue_delivery_worker::run_worker_until_stopped,
+    startup::{get_app, get_connection_pool},
     telemetry::{get_subscriber, init_subscriber},
+    unsubscribe::UnsubscribeLinks,
 };
-use sqlx::postgres::PgPoolOptions;
 
 #[tokio::main]
 async fn main() {
@@ -14,9 +16,7 @@ async fn main() {
     init_subscriber(subscriber);
 
     let configuration = get_configuration().expect("Failed to read configuration.");
-    let connection_pool = PgPoolOptions::new()
-        .acquire_timeout(std::time::Duration::from_secs(2))
-        .connect_lazy_with(configuration.database.with_db());
+    let connection_pool = get_connection_pool(&configuration.database);
 
     let address = format!(
         "{}:{}",
@@ -24,26 +24,47 @@ async fn main() {
     );
     let address: SocketAddr = address.parse().expect("Failed to parse address.");
 
-    let sender = configuration
-        .email_client
-        .sender()
-        .expect("Invalid sender email address");
-    let timeout = configuration.email_client.timeout();
-    let email_client = EmailClient::new(
-        configuration.email_client.base_url,
-        sender,
-        configuration.email_client.authorization_token,
-        timeout,
+    let unsubscribe_links = UnsubscribeLinks::new(
+        configuration.application.base_url.clone(),
+        configuration.application.hmac_secret.clone(),
     );
 
+    // Shared so that the circuit breaker sees every call to the provider.
+    let email_sender: Arc<dyn EmailSender> = Arc::new(SuppressionCheck::new(
+        configuration.email_client.client(),
+        connection_pool.clone(),
+    ));
+
     let app = get_app(
-        connection_pool,
-        email_client,
-        configuration.application.base_url,
+        connection_pool.clone(),
+        &configuration,
+        email_sender.clone(),
     );
+    let server = tokio::spawn(axum::Server::bind(&address).serve(app.into_make_service()));
+    let worker = tokio::spawn(run_worker_until_stopped(
+        connection_pool.clone(),
+        email_sender.clone(),
+        unsubscribe_links,
+    ));
+    let dispatcher = tokio::spawn(run_dispatcher_until_stopped(connection_pool, email_sender));
 
-    axum::Server::bind(&address)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+    tokio::select! {
+        outcome = server => {
+            match outcome {
+                Ok(Ok(())) => tracing::info!("API has exited"),
+                Ok(Err(e)) => tracing::error!(error.cause_chain = ?e, "API failed"),
+                Err(e) => tracing::error!(error.cause_chain = ?e, "API task failed to complete"),
+            }
+        }
+        outcome = worker => {
+            if let Err(e) = outcome {
+                tracing::error!(error.cause_chain = ?e, "Background worker task failed to complete");
+            }
+        }
+        outcome = dispatcher => {
+            if let Err(e) = outcome {
+                tracing::error!(error.cause_chain = ?e, "Email dispatcher task failed to complete");
+            }
+        }
+    };
 }
@@ -0,0 +1,67 @@
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+
+const PROBLEM_JSON: &str = "application/problem+json";
+
+/// An RFC 7807 error body.
+#[derive(Debug, serde::Serialize)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    title: &'static str,
+    #[serde(serialize_with = "serialize_status")]
+    status: StatusCode,
+    #[serde(rename = "invalid-params", skip_serializing_if = "Vec::is_empty")]
+    invalid_params: Vec<InvalidParam>,
+}
+
+/// A field of the request that failed validation.
+#[derive(Debug, serde::Serialize)]
+pub struct InvalidParam {
+    pub name: &'static str,
+    /// Machine-readable, e.g. `name_too_long`.
+    pub code: &'static str,
+    /// Human-readable.
+    pub reason: String,
+}
+
+impl InvalidParam {
+    pub fn missing(name: &'static str) -> Self {
+        Self {
+            name,
+            code: "missing",
+            reason: format!("The {} is missing.", name),
+        }
+    }
+}
+
+impl ProblemDetails {
+    pub fn validation_error(invalid_params: Vec<InvalidParam>) -> Self {
+        Self {
+            kind: "/problems/validation-error",
+            title: "Your request parameters didn't validate.",
+            status: StatusCode::UNPROCESSABLE_ENTITY,
+            invalid_params,
+        }
+    }
+}
+
+impl IntoResponse for ProblemDetails {
+    fn into_response(self) -> Response {
+        let mut response = (self.status, Json(&self)).into_response();
+        response
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+        response
+    }
+}
+
+fn serialize_status<S: serde::Serializer>(
+    status: &StatusCode,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u16(status.as_u16())
+}
@@ -0,0 +1,60 @@
+use anyhow::Context;
+use axum::{http::StatusCode, response::Html, Extension};
+use axum_macros::debug_handler;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    authentication::UserId, flash_messages::render_flash_messages, session_state::SignedCookies,
+};
+
+#[debug_handler]
+pub async fn admin_dashboard(
+    Extension(pool): Extension<PgPool>,
+    Extension(user_id): Extension<UserId>,
+    cookies: SignedCookies,
+) -> Result<(SignedCookies, Html<String>), StatusCode> {
+    let username = get_username(*user_id, &pool).await.map_err(|e| {
+        tracing::error!(error.cause_chain = ?e, error.message = %e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let (cookies, flash_messages) = cookies.take_flash();
+    let msg_html = render_flash_messages(&flash_messages);
+
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Admin dashboard</title>
+</head>
+<body>
+    {msg_html}
+    <p>Welcome {}!</p>
+    <p>Available actions:</p>
+    <ol>
+        <li><a href="/admin/password">Change password</a></li>
+        <li><a href="/admin/suppressions">Suppressed addresses</a></li>
+        <li>
+            <form name="logoutForm" action="/admin/logout" method="post">
+                <input type="submit" value="Logout">
+            </form>
+        </li>
+    </ol>
+</body>
+</html>"#,
+        htmlescape::encode_minimal(&username),
+    );
+
+    Ok((cookies, Html(body)))
+}
+
+#[tracing::instrument(name = "Get username", skip(pool))]
+pub async fn get_username(user_id: Uuid, pool: &PgPool) -> Result<String, anyhow::Error> {
+    let row = sqlx::query!(r#"SELECT username FROM users WHERE user_id = $1"#, user_id,)
+        .fetch_one(pool)
+        .await
+        .context("Failed to perform a query to retrieve a username.")?;
+
+    Ok(row.username)
+}
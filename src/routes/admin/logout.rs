@@ -0,0 +1,30 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Redirect, Response},
+    Extension,
+};
+use axum_macros::debug_handler;
+use sqlx::PgPool;
+
+use crate::{
+    flash_messages::FlashMessage,
+    session_state::{delete_session, SignedCookies},
+};
+
+#[debug_handler]
+pub async fn log_out(Extension(pool): Extension<PgPool>, cookies: SignedCookies) -> Response {
+    if let Some(session_id) = cookies.session_id() {
+        if let Err(e) = delete_session(&pool, &session_id).await {
+            tracing::error!(error.cause_chain = ?e, error.message = %e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    (
+        cookies
+            .without_session_id()
+            .with_flash(FlashMessage::info("You have successfully logged out.")),
+        Redirect::to("/login"),
+    )
+        .into_response()
+}
@@ -0,0 +1,9 @@
+pub use dashboard::*;
+pub use logout::*;
+pub use password::*;
+pub use suppressions::*;
+
+mod dashboard;
+mod logout;
+mod password;
+mod suppressions;
@@ -0,0 +1,132 @@
+use anyhow::Context;
+use axum::{
+    http::StatusCode,
+    response::{Html, IntoResponse, Redirect, Response},
+    Extension, Form,
+};
+use axum_macros::debug_handler;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+use crate::{
+    authentication::{
+        self, check_password_policy, validate_credentials, AuthError, Credentials, UserId,
+    },
+    flash_messages::{render_flash_messages, FlashMessage},
+    routes::get_username,
+    session_state::SignedCookies,
+    utils::error_chain_fmt,
+};
+
+#[derive(serde::Deserialize)]
+pub struct ChangePasswordFormData {
+    current_password: Secret<String>,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum ChangePasswordError {
+    #[error("The current password is incorrect.")]
+    WrongPassword(#[source] anyhow::Error),
+    #[error("{0}")]
+    InvalidNewPassword(String),
+    #[error("Something went wrong")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ChangePasswordError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+#[debug_handler]
+pub async fn change_password_form(cookies: SignedCookies) -> (SignedCookies, Html<String>) {
+    let (cookies, flash_messages) = cookies.take_flash();
+    let msg_html = render_flash_messages(&flash_messages);
+
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Change Password</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/admin/password" method="post">
+        <label>Current password
+            <input type="password" placeholder="Enter current password" name="current_password">
+        </label>
+        <br>
+        <label>New password
+            <input type="password" placeholder="Enter new password" name="new_password">
+        </label>
+        <br>
+        <label>Confirm new password
+            <input type="password" placeholder="Type the new password again" name="new_password_check">
+        </label>
+        <br>
+        <button type="submit">Change password</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+    );
+
+    (cookies, Html(body))
+}
+
+#[debug_handler]
+#[tracing::instrument(name = "Change password", skip(form, pool, cookies))]
+pub async fn change_password(
+    Extension(pool): Extension<PgPool>,
+    Extension(user_id): Extension<UserId>,
+    cookies: SignedCookies,
+    Form(form): Form<ChangePasswordFormData>,
+) -> Response {
+    let flash = match try_change_password(&pool, user_id, form).await {
+        Ok(()) => FlashMessage::info("Your password has been changed."),
+        Err(e @ ChangePasswordError::UnexpectedError(_)) => {
+            tracing::error!(error.cause_chain = ?e, error.message = %e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        Err(e) => {
+            tracing::warn!(error.cause_chain = ?e, error.message = %e);
+            FlashMessage::error(e.to_string())
+        }
+    };
+
+    (cookies.with_flash(flash), Redirect::to("/admin/password")).into_response()
+}
+
+async fn try_change_password(
+    pool: &PgPool,
+    user_id: UserId,
+    form: ChangePasswordFormData,
+) -> Result<(), ChangePasswordError> {
+    if form.new_password.expose_secret() != form.new_password_check.expose_secret() {
+        return Err(ChangePasswordError::InvalidNewPassword(
+            "You entered two different new passwords - the field values must match.".into(),
+        ));
+    }
+    check_password_policy(&form.new_password).map_err(ChangePasswordError::InvalidNewPassword)?;
+
+    let credentials = Credentials {
+        username: get_username(*user_id, pool).await?,
+        password: form.current_password,
+    };
+    validate_credentials(credentials, pool)
+        .await
+        .map_err(|e| match e {
+            AuthError::InvalidCredentials(_) => ChangePasswordError::WrongPassword(e.into()),
+            AuthError::UnexpectedError(_) => ChangePasswordError::UnexpectedError(e.into()),
+        })?;
+
+    authentication::change_password(*user_id, form.new_password, pool)
+        .await
+        .context("Failed to change the password")?;
+
+    Ok(())
+}
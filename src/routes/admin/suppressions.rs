@@ -0,0 +1,205 @@
+use anyhow::Context;
+use axum::{
+    http::StatusCode,
+    response::{Html, IntoResponse, Redirect, Response},
+    Extension, Form,
+};
+use axum_macros::debug_handler;
+use sqlx::PgPool;
+
+use crate::{
+    domain::SubscriberEmail,
+    flash_messages::{render_flash_messages, FlashMessage},
+    session_state::SignedCookies,
+    suppressions::{list_suppressions, suppress, unsuppress, ADMIN_SOURCE},
+    utils::error_chain_fmt,
+};
+
+#[derive(serde::Deserialize)]
+pub struct AddSuppressionFormData {
+    email: String,
+    reason: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RemoveSuppressionFormData {
+    email: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum SuppressionError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("Something went wrong")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SuppressionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+#[debug_handler]
+pub async fn suppressions_page(
+    Extension(pool): Extension<PgPool>,
+    cookies: SignedCookies,
+) -> Result<(SignedCookies, Html<String>), StatusCode> {
+    let suppressions = list_suppressions(&pool).await.map_err(|e| {
+        tracing::error!(error.cause_chain = ?e, error.message = %e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let (cookies, flash_messages) = cookies.take_flash();
+    let msg_html = render_flash_messages(&flash_messages);
+
+    let rows: String = suppressions
+        .iter()
+        .map(|s| {
+            format!(
+                r#"<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>
+            <form action="/admin/suppressions/remove" method="post">
+                <input type="hidden" name="email" value="{}">
+                <button type="submit">Remove</button>
+            </form>
+        </td></tr>"#,
+                htmlescape::encode_minimal(&s.email),
+                htmlescape::encode_minimal(&s.reason),
+                htmlescape::encode_minimal(&s.source),
+                s.created_at.format("%Y-%m-%d %H:%M:%S"),
+                htmlescape::encode_attribute(&s.email),
+            )
+        })
+        .collect();
+
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Suppressions</title>
+</head>
+<body>
+    {msg_html}
+    <p>No email is sent to these {} address(es).</p>
+    <form action="/admin/suppressions" method="post">
+        <label>Email
+            <input type="email" placeholder="Enter the address to suppress" name="email">
+        </label>
+        <label>Reason
+            <input type="text" placeholder="E.g. legal request" name="reason">
+        </label>
+        <button type="submit">Suppress</button>
+    </form>
+    <table>
+        <tr><th>Email</th><th>Reason</th><th>Source</th><th>Since</th><th></th></tr>
+        {rows}
+    </table>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        suppressions.len(),
+    );
+
+    Ok((cookies, Html(body)))
+}
+
+#[debug_handler]
+#[tracing::instrument(name = "Add a suppression", skip(form, pool, cookies))]
+pub async fn add_suppression(
+    Extension(pool): Extension<PgPool>,
+    cookies: SignedCookies,
+    Form(form): Form<AddSuppressionFormData>,
+) -> Response {
+    let flash = match try_add_suppression(&pool, form).await {
+        Ok(flash) => flash,
+        Err(e @ SuppressionError::UnexpectedError(_)) => {
+            tracing::error!(error.cause_chain = ?e, error.message = %e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        Err(e) => {
+            tracing::warn!(error.cause_chain = ?e, error.message = %e);
+            FlashMessage::error(e.to_string())
+        }
+    };
+
+    (
+        cookies.with_flash(flash),
+        Redirect::to("/admin/suppressions"),
+    )
+        .into_response()
+}
+
+async fn try_add_suppression(
+    pool: &PgPool,
+    form: AddSuppressionFormData,
+) -> Result<FlashMessage, SuppressionError> {
+    let email = SubscriberEmail::parse(form.email.clone()).map_err(|e| {
+        SuppressionError::ValidationError(format!(
+            "{} is not a valid subscriber email. {}",
+            form.email, e
+        ))
+    })?;
+    let reason = form.reason.trim();
+    if reason.is_empty() {
+        return Err(SuppressionError::ValidationError(
+            "Please give a reason.".into(),
+        ));
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let inserted = suppress(&mut transaction, email.as_ref(), reason, ADMIN_SOURCE)
+        .await
+        .context("Failed to suppress the address")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to suppress an address")?;
+
+    Ok(if inserted {
+        FlashMessage::info(format!("{} has been suppressed.", email.as_ref()))
+    } else {
+        FlashMessage::info(format!("{} was already suppressed.", email.as_ref()))
+    })
+}
+
+#[debug_handler]
+#[tracing::instrument(name = "Remove a suppression", skip(form, pool, cookies))]
+pub async fn remove_suppression(
+    Extension(pool): Extension<PgPool>,
+    cookies: SignedCookies,
+    Form(form): Form<RemoveSuppressionFormData>,
+) -> Response {
+    let flash = match try_remove_suppression(&pool, &form.email).await {
+        Ok(true) => FlashMessage::info(format!("{} is no longer suppressed.", form.email)),
+        Ok(false) => FlashMessage::error(format!("{} was not suppressed.", form.email)),
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e, error.message = %e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    (
+        cookies.with_flash(flash),
+        Redirect::to("/admin/suppressions"),
+    )
+        .into_response()
+}
+
+async fn try_remove_suppression(pool: &PgPool, email: &str) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let deleted = unsuppress(&mut transaction, email)
+        .await
+        .context("Failed to lift the suppression of the address")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to lift a suppression")?;
+
+    Ok(deleted)
+}
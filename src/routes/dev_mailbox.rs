@@ -0,0 +1,71 @@
+use std::path::PathBuf;
+
+use axum::{extract::Path, http::StatusCode, response::Html, Extension};
+use axum_macros::debug_handler;
+use uuid::Uuid;
+
+use crate::email_client::{read_html, read_index};
+
+/// The directory the `file` email provider writes to.
+#[derive(Clone)]
+pub struct MailboxDirectory(pub PathBuf);
+
+/// List the emails written by the `file` provider, newest first.
+#[debug_handler]
+pub async fn dev_mailbox(
+    Extension(MailboxDirectory(directory)): Extension<MailboxDirectory>,
+) -> Result<Html<String>, StatusCode> {
+    let index = read_index(&directory).await.map_err(|e| {
+        tracing::error!(error.cause_chain = ?e, error.message = %e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let rows: String = index
+        .iter()
+        .rev()
+        .map(|entry| {
+            format!(
+                r#"<tr><td>{}</td><td>{}</td><td><a href="/dev/mailbox/{}">{}</a></td></tr>"#,
+                entry.created_at.format("%Y-%m-%d %H:%M:%S"),
+                htmlescape::encode_minimal(&entry.recipient),
+                entry.id,
+                htmlescape::encode_minimal(&entry.subject),
+            )
+        })
+        .collect();
+
+    Ok(Html(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Mailbox</title>
+</head>
+<body>
+    <p>{} email(s) in {}</p>
+    <table>
+        <tr><th>Sent at</th><th>To</th><th>Subject</th></tr>
+        {rows}
+    </table>
+</body>
+</html>"#,
+        index.len(),
+        htmlescape::encode_minimal(&directory.to_string_lossy()),
+    )))
+}
+
+/// Show the HTML body of an email, links included.
+#[debug_handler]
+pub async fn dev_mailbox_message(
+    Extension(MailboxDirectory(directory)): Extension<MailboxDirectory>,
+    Path(id): Path<Uuid>,
+) -> Result<Html<String>, StatusCode> {
+    read_html(&directory, id)
+        .await
+        .map_err(|e| {
+            tracing::error!(error.cause_chain = ?e, error.message = %e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .map(Html)
+        .ok_or(StatusCode::NOT_FOUND)
+}
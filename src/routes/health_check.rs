@@ -1,7 +1,23 @@
-use axum::{http::StatusCode, response::IntoResponse};
+use std::sync::Arc;
+
+use axum::{Extension, Json};
 use axum_macros::debug_handler;
+use serde_json::{json, Value};
+
+use crate::email_client::{CircuitState, EmailSender};
 
+/// Always 200 while the app is up. `status` is `degraded` while emails fail
+/// over to the secondary provider.
 #[debug_handler]
-pub async fn health_check() -> impl IntoResponse {
-    StatusCode::OK
+pub async fn health_check(Extension(email_sender): Extension<Arc<dyn EmailSender>>) -> Json<Value> {
+    match email_sender.circuit_state() {
+        None => Json(json!({ "status": "ok" })),
+        Some(circuit) => {
+            let status = match circuit {
+                CircuitState::Closed => "ok",
+                CircuitState::Open | CircuitState::HalfOpen => "degraded",
+            };
+            Json(json!({ "status": status, "email_circuit": circuit }))
+        }
+    }
 }
@@ -0,0 +1,125 @@
+use axum::{
+    response::{Html, Redirect},
+    Extension, Form,
+};
+use axum_macros::debug_handler;
+use secrecy::Secret;
+use sqlx::PgPool;
+use tracing::{field::display, Span};
+
+use crate::{
+    authentication::{validate_credentials, AuthError, Credentials},
+    flash_messages::{render_flash_messages, FlashMessage},
+    session_state::{create_session, delete_session, SignedCookies},
+    utils::error_chain_fmt,
+};
+
+#[derive(serde::Deserialize)]
+pub struct LoginFormData {
+    username: String,
+    password: Secret<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum LoginError {
+    #[error("Authentication failed")]
+    AuthError(#[source] anyhow::Error),
+    #[error("Something went wrong")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for LoginError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+#[debug_handler]
+pub async fn login_form(cookies: SignedCookies) -> (SignedCookies, Html<String>) {
+    let (cookies, flash_messages) = cookies.take_flash();
+    let error_html = render_flash_messages(&flash_messages);
+
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Login</title>
+</head>
+<body>
+    {error_html}
+    <form action="/login" method="post">
+        <label>Username
+            <input type="text" placeholder="Enter Username" name="username">
+        </label>
+        <label>Password
+            <input type="password" placeholder="Enter Password" name="password">
+        </label>
+        <button type="submit">Login</button>
+    </form>
+    <p><a href="/password-reset">Forgot your password?</a></p>
+</body>
+</html>"#,
+    );
+
+    (cookies, Html(body))
+}
+
+#[debug_handler]
+#[tracing::instrument(
+    skip(form, pool, cookies),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn login(
+    Extension(pool): Extension<PgPool>,
+    cookies: SignedCookies,
+    Form(form): Form<LoginFormData>,
+) -> (SignedCookies, Redirect) {
+    let credentials = Credentials {
+        username: form.username,
+        password: form.password,
+    };
+    Span::current().record("username", display(&credentials.username));
+
+    match start_session(&pool, &cookies, credentials).await {
+        Ok(session_id) => (
+            cookies.with_session_id(session_id),
+            Redirect::to("/admin/dashboard"),
+        ),
+        Err(e) => {
+            match &e {
+                LoginError::AuthError(_) => {
+                    tracing::warn!(error.cause_chain = ?e, error.message = %e)
+                }
+                LoginError::UnexpectedError(_) => {
+                    tracing::error!(error.cause_chain = ?e, error.message = %e)
+                }
+            }
+            (
+                cookies.with_flash(FlashMessage::error(e.to_string())),
+                Redirect::to("/login"),
+            )
+        }
+    }
+}
+
+async fn start_session(
+    pool: &PgPool,
+    cookies: &SignedCookies,
+    credentials: Credentials,
+) -> Result<String, LoginError> {
+    let user_id = validate_credentials(credentials, pool)
+        .await
+        .map_err(|e| match e {
+            AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
+            AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into()),
+        })?;
+    Span::current().record("user_id", display(&user_id));
+
+    // Never reuse a session id that existed before the user authenticated.
+    if let Some(session_id) = cookies.session_id() {
+        delete_session(pool, &session_id).await?;
+    }
+
+    Ok(create_session(pool, user_id).await?)
+}
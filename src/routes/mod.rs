@@ -1,7 +1,21 @@
+pub use admin::*;
+pub use dev_mailbox::*;
 pub use health_check::*;
+pub use login::*;
+pub use newsletters::*;
+pub use password_reset::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_unsubscribe::*;
+pub use webhooks::*;
 
+mod admin;
+mod dev_mailbox;
 mod health_check;
+mod login;
+mod newsletters;
+mod password_reset;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod webhooks;
@@ -0,0 +1,197 @@
+use anyhow::Context;
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use axum_macros::debug_handler;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::{
+    authentication::{basic_authentication, validate_credentials, AuthError},
+    idempotency::{save_response, try_processing, IdempotencyKey, NextAction},
+    utils::error_chain_fmt,
+};
+
+#[derive(serde::Deserialize)]
+pub struct BodyData {
+    title: String,
+    content: Content,
+}
+
+#[derive(serde::Deserialize)]
+pub struct Content {
+    html: String,
+    text: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum PublishError {
+    #[error("Authentication failed.")]
+    AuthError(#[source] anyhow::Error),
+    #[error("{0}")]
+    InvalidIdempotencyKey(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PublishError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for PublishError {
+    fn into_response(self) -> Response {
+        match self {
+            PublishError::AuthError(_) => {
+                tracing::warn!(error.cause_chain = ?self, error.message = %self);
+                let mut response = StatusCode::UNAUTHORIZED.into_response();
+                response.headers_mut().insert(
+                    header::WWW_AUTHENTICATE,
+                    HeaderValue::from_static(r#"Basic realm="publish""#),
+                );
+                response
+            }
+            PublishError::InvalidIdempotencyKey(_) => {
+                tracing::warn!(error.cause_chain = ?self, error.message = %self);
+                StatusCode::BAD_REQUEST.into_response()
+            }
+            PublishError::UnexpectedError(_) => {
+                tracing::error!(error.cause_chain = ?self, error.message = %self);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+#[debug_handler]
+#[tracing::instrument(
+    name = "Publish a newsletter issue",
+    skip(pool, headers, body),
+    fields(
+        newsletter_title = %body.title,
+        username = tracing::field::Empty,
+        user_id = tracing::field::Empty
+    )
+)]
+pub async fn publish_newsletter(
+    Extension(pool): Extension<PgPool>,
+    headers: HeaderMap,
+    Json(body): Json<BodyData>,
+) -> Result<Response, PublishError> {
+    let credentials = basic_authentication(&headers).map_err(PublishError::AuthError)?;
+    Span::current().record("username", display(&credentials.username));
+    let user_id = validate_credentials(credentials, &pool)
+        .await
+        .map_err(|e| match e {
+            AuthError::InvalidCredentials(_) => PublishError::AuthError(e.into()),
+            AuthError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
+        })?;
+    Span::current().record("user_id", display(&user_id));
+
+    let idempotency_key =
+        IdempotencyKey::from_headers(&headers).map_err(PublishError::InvalidIdempotencyKey)?;
+
+    let idempotency_scope = format!("POST /newsletters {}", user_id);
+    let mut transaction = match &idempotency_key {
+        Some(idempotency_key) => {
+            match try_processing(&pool, &idempotency_scope, idempotency_key).await? {
+                NextAction::StartProcessing(transaction) => transaction,
+                NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+            }
+        }
+        None => pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool")?,
+    };
+
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        &body.title,
+        &body.content.text,
+        &body.content.html,
+    )
+    .await
+    .context("Failed to store newsletter issue details")?;
+
+    enqueue_delivery_tasks(&mut transaction, issue_id)
+        .await
+        .context("Failed to enqueue delivery tasks")?;
+
+    let response = StatusCode::ACCEPTED.into_response();
+    match idempotency_key {
+        Some(idempotency_key) => {
+            Ok(save_response(transaction, &idempotency_scope, &idempotency_key, response).await?)
+        }
+        None => {
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit SQL transaction to publish a newsletter issue")?;
+            Ok(response)
+        }
+    }
+}
+
+#[tracing::instrument(name = "Save newsletter issue details", skip_all)]
+async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"INSERT INTO newsletter_issues (
+        newsletter_issue_id,
+        title,
+        text_content,
+        html_content,
+        published_at
+    )
+    VALUES ($1, $2, $3, $4, $5)"#,
+        newsletter_issue_id,
+        title,
+        text_content,
+        html_content,
+        Utc::now(),
+    )
+    .execute(transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    Ok(newsletter_issue_id)
+}
+
+#[tracing::instrument(name = "Enqueue delivery tasks", skip(transaction))]
+async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"INSERT INTO issue_delivery_queue (
+        newsletter_issue_id,
+        subscriber_email
+    )
+    SELECT $1, email
+    FROM subscriptions
+    WHERE status = 'confirmed'"#,
+        newsletter_issue_id,
+    )
+    .execute(transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,310 @@
+use anyhow::Context;
+use axum::{
+    extract::Query,
+    http::StatusCode,
+    response::{Html, IntoResponse, Redirect, Response},
+    Extension, Form,
+};
+use axum_macros::debug_handler;
+use chrono::Utc;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    authentication::{change_password, check_password_policy},
+    domain::SubscriberEmail,
+    flash_messages::{render_flash_messages, FlashMessage},
+    session_state::{delete_user_sessions, SignedCookies},
+    utils::hash_token,
+};
+
+/// How long a reset link stays valid.
+const RESET_TOKEN_TTL_MINUTES: i64 = 60;
+
+#[derive(serde::Deserialize)]
+pub struct PasswordResetRequestFormData {
+    email: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PasswordResetParameters {
+    token: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PasswordResetFormData {
+    token: String,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+#[debug_handler]
+pub async fn password_reset_request_form(cookies: SignedCookies) -> (SignedCookies, Html<String>) {
+    let (cookies, flash_messages) = cookies.take_flash();
+    let msg_html = render_flash_messages(&flash_messages);
+
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Reset your password</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/password-reset" method="post">
+        <label>Email
+            <input type="email" placeholder="Enter the email of your account" name="email">
+        </label>
+        <button type="submit">Send reset link</button>
+    </form>
+    <p><a href="/login">&lt;- Back to login</a></p>
+</body>
+</html>"#,
+    );
+
+    (cookies, Html(body))
+}
+
+/// Email a reset link to the admin owning `email`, if any.
+///
+/// The response is the same whether or not the address matches an account,
+/// so the form cannot be used to find out who the admins are.
+#[debug_handler]
+#[tracing::instrument(name = "Request a password reset", skip(pool, base_url, cookies, form))]
+pub async fn request_password_reset(
+    Extension(pool): Extension<PgPool>,
+    Extension(base_url): Extension<String>,
+    cookies: SignedCookies,
+    Form(form): Form<PasswordResetRequestFormData>,
+) -> Response {
+    if let Ok(email) = SubscriberEmail::parse(form.email) {
+        if let Err(e) = send_reset_link(&pool, &base_url, &email).await {
+            tracing::error!(error.cause_chain = ?e, error.message = %e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    (
+        cookies.with_flash(FlashMessage::info(
+            "If an account uses this email, we have sent it a link to reset the password.",
+        )),
+        Redirect::to("/password-reset"),
+    )
+        .into_response()
+}
+
+#[debug_handler]
+pub async fn password_reset_form(
+    Extension(pool): Extension<PgPool>,
+    Query(parameters): Query<PasswordResetParameters>,
+    cookies: SignedCookies,
+) -> Response {
+    match get_user_id_from_reset_token(&pool, &parameters.token).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return invalid_link(cookies),
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e, error.message = %e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    let (cookies, flash_messages) = cookies.take_flash();
+    let msg_html = render_flash_messages(&flash_messages);
+
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Choose a new password</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/password-reset/confirm" method="post">
+        <input type="hidden" name="token" value="{}">
+        <label>New password
+            <input type="password" placeholder="Enter new password" name="new_password">
+        </label>
+        <br>
+        <label>Confirm new password
+            <input type="password" placeholder="Type the new password again" name="new_password_check">
+        </label>
+        <br>
+        <button type="submit">Reset password</button>
+    </form>
+</body>
+</html>"#,
+        htmlescape::encode_attribute(&parameters.token),
+    );
+
+    (cookies, Html(body)).into_response()
+}
+
+#[debug_handler]
+#[tracing::instrument(name = "Reset a password", skip(pool, cookies, form))]
+pub async fn reset_password(
+    Extension(pool): Extension<PgPool>,
+    cookies: SignedCookies,
+    Form(form): Form<PasswordResetFormData>,
+) -> Response {
+    // We only ever issue alphanumeric tokens; anything else cannot be valid,
+    // and must not end up in the redirect URL below.
+    if !form.token.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return invalid_link(cookies);
+    }
+
+    let validation = if form.new_password.expose_secret() != form.new_password_check.expose_secret()
+    {
+        Err("You entered two different new passwords - the field values must match.".to_string())
+    } else {
+        check_password_policy(&form.new_password)
+    };
+    if let Err(message) = validation {
+        let retry_url = format!("/password-reset/confirm?token={}", form.token);
+        return (
+            cookies.with_flash(FlashMessage::error(message)),
+            Redirect::to(&retry_url),
+        )
+            .into_response();
+    }
+
+    match consume_token_and_reset_password(&pool, &form.token, form.new_password).await {
+        Ok(true) => (
+            cookies.with_flash(FlashMessage::info(
+                "Your password has been reset. You can now log in.",
+            )),
+            Redirect::to("/login"),
+        )
+            .into_response(),
+        Ok(false) => invalid_link(cookies),
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e, error.message = %e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+fn invalid_link(cookies: SignedCookies) -> Response {
+    (
+        cookies.with_flash(FlashMessage::error(
+            "This reset link is invalid or has expired. Please request a new one.",
+        )),
+        Redirect::to("/password-reset"),
+    )
+        .into_response()
+}
+
+/// The link is only stored and queued if an admin owns `email`, but the
+/// work is the same either way: a single statement decides, so the time
+/// the request takes does not tell whether the account exists.
+#[tracing::instrument(name = "Send a password reset link", skip(pool, base_url, email))]
+async fn send_reset_link(
+    pool: &PgPool,
+    base_url: &str,
+    email: &SubscriberEmail,
+) -> Result<(), anyhow::Error> {
+    let token = generate_reset_token();
+    let reset_link = format!("{}/password-reset/confirm?token={}", base_url, token);
+    let now = Utc::now();
+    sqlx::query!(
+        r#"WITH owner AS (
+        SELECT user_id FROM users WHERE lower(email) = lower($2) LIMIT 1
+    ), token AS (
+        INSERT INTO password_reset_tokens (token_hash, user_id, created_at, expires_at)
+        SELECT $1, user_id, $3, $4 FROM owner
+        RETURNING user_id
+    )
+    INSERT INTO email_outbox (id, recipient, subject, html_content, text_content, created_at)
+    SELECT $5, $2, $6, $7, $8, $3 FROM token"#,
+        hash_token(&token),
+        email.as_ref(),
+        now,
+        now + chrono::Duration::minutes(RESET_TOKEN_TTL_MINUTES),
+        Uuid::new_v4(),
+        "Reset your password",
+        format!(
+            "Click <a href=\"{}\">here</a> to choose a new password.<br />\
+            The link expires in {} minutes. If you did not ask for it, ignore this email.",
+            reset_link, RESET_TOKEN_TTL_MINUTES
+        ),
+        format!(
+            "Visit {} to choose a new password.\n\
+            The link expires in {} minutes. If you did not ask for it, ignore this email.",
+            reset_link, RESET_TOKEN_TTL_MINUTES
+        ),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store and queue a password reset link")?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Get user id from a reset token", skip(pool, token))]
+async fn get_user_id_from_reset_token(
+    pool: &PgPool,
+    token: &str,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let record = sqlx::query!(
+        r#"SELECT user_id FROM password_reset_tokens
+    WHERE token_hash = $1 AND used_at IS NULL AND expires_at > now()"#,
+        hash_token(token),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve a password reset token.")?;
+
+    Ok(record.map(|r| r.user_id))
+}
+
+/// Returns `false` if the token is unknown, expired or already used.
+///
+/// Marking the token as used and changing the password happen in the same
+/// transaction, so a link can only ever be redeemed once.
+#[tracing::instrument(name = "Consume a reset token", skip(pool, token, new_password))]
+async fn consume_token_and_reset_password(
+    pool: &PgPool,
+    token: &str,
+    new_password: Secret<String>,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let record = sqlx::query!(
+        r#"UPDATE password_reset_tokens SET used_at = now()
+    WHERE token_hash = $1 AND used_at IS NULL AND expires_at > now()
+    RETURNING user_id"#,
+        hash_token(token),
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to mark a password reset token as used.")?;
+    let user_id = match record {
+        Some(record) => record.user_id,
+        None => return Ok(false),
+    };
+
+    change_password(user_id, new_password, &mut transaction).await?;
+    // Whoever triggered the reset may not be the one holding the sessions.
+    delete_user_sessions(&mut transaction, user_id).await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to reset a password")?;
+
+    Ok(true)
+}
+
+fn generate_reset_token() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(32)
+        .collect()
+}
@@ -1,117 +1,336 @@
-use std::sync::Arc;
-
 use crate::{
     domain::{NewSubscriber, SubscriberEmail, SubscriberName},
-    email_client::EmailClient,
+    email_outbox::enqueue_email,
+    extract::{BodyFormat, JsonOrForm},
+    idempotency::{save_response, try_processing, IdempotencyKey, NextAction},
+    problem_details::{InvalidParam, ProblemDetails},
+    utils::{error_chain_fmt, hash_token},
+};
+use anyhow::Context;
+use axum::{
+    http::{HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Redirect, Response},
+    Extension,
 };
-use axum::{http::StatusCode, response::IntoResponse, Extension, Json};
 use axum_macros::debug_handler;
 use chrono::Utc;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
+/// Subscribers are anonymous: their idempotency keys are only scoped by the
+/// endpoint.
+const IDEMPOTENCY_SCOPE: &str = "POST /subscribe";
+
+/// How long the link of a confirmation email stays valid.
+#[derive(Clone, Copy)]
+pub struct SubscriptionTokenTtl(pub chrono::Duration);
+
+/// Fields are optional so a missing one is reported like any other invalid
+/// field, rather than as a deserialization failure.
 #[derive(serde::Deserialize)]
 pub struct FormData {
-    name: String,
-    email: String,
+    name: Option<String>,
+    email: Option<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum SubscribeError {
+    #[error("The subscriber details are invalid.")]
+    ValidationError(Vec<InvalidParam>),
+    #[error("{0}")]
+    InvalidIdempotencyKey(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl SubscribeError {
+    fn log(&self) {
+        match self {
+            SubscribeError::ValidationError(invalid_params) => {
+                tracing::warn!(invalid_params = ?invalid_params, error.message = %self);
+            }
+            SubscribeError::InvalidIdempotencyKey(_) => {
+                tracing::warn!(error.cause_chain = ?self, error.message = %self);
+            }
+            SubscribeError::UnexpectedError(_) => {
+                tracing::error!(error.cause_chain = ?self, error.message = %self);
+            }
+        }
+    }
+
+    /// Send HTML form submissions to the error page, telling it what went
+    /// wrong in the query string: `?name=name_too_long&email=missing`.
+    fn into_redirect(self, error_url: &str) -> Response {
+        self.log();
+        let query = match &self {
+            SubscribeError::ValidationError(invalid_params) => invalid_params
+                .iter()
+                .map(|p| format!("{}={}", p.name, p.code))
+                .collect::<Vec<_>>()
+                .join("&"),
+            SubscribeError::InvalidIdempotencyKey(_) => "error=bad_request".into(),
+            SubscribeError::UnexpectedError(_) => "error=unexpected".into(),
+        };
+        let separator = if error_url.contains('?') { '&' } else { '?' };
+        Redirect::to(&format!("{}{}{}", error_url, separator, query)).into_response()
+    }
+}
+
+impl IntoResponse for SubscribeError {
+    fn into_response(self) -> Response {
+        self.log();
+        match self {
+            SubscribeError::ValidationError(invalid_params) => {
+                ProblemDetails::validation_error(invalid_params).into_response()
+            }
+            SubscribeError::InvalidIdempotencyKey(_) => StatusCode::BAD_REQUEST.into_response(),
+            SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    }
+}
+
+/// Where HTML form submissions end up, as they cannot make sense of a bare
+/// status code.
+#[derive(Clone)]
+pub struct SubscribeRedirects {
+    pub success_url: String,
+    pub error_url: String,
 }
 
 #[debug_handler]
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(pool, form, email_client),
+    skip(pool, headers, form, redirects),
     fields(
-        subscriber_email = %form.email,
-        subscriber_name = %form.name
+        subscriber_email = ?form.email,
+        subscriber_name = ?form.name
     )
 )]
 pub async fn subscribe(
     Extension(pool): Extension<PgPool>,
-    Extension(email_client): Extension<Arc<EmailClient>>,
     Extension(base_url): Extension<String>,
-    Json(form): Json<FormData>,
-) -> impl IntoResponse {
-    let new_subscriber: NewSubscriber = match form.try_into() {
-        Ok(new_subscriber) => new_subscriber,
-        Err(_) => return StatusCode::UNPROCESSABLE_ENTITY,
+    Extension(SubscriptionTokenTtl(token_ttl)): Extension<SubscriptionTokenTtl>,
+    Extension(redirects): Extension<SubscribeRedirects>,
+    headers: HeaderMap,
+    JsonOrForm(form, format): JsonOrForm<FormData>,
+) -> Response {
+    let success_response = match format {
+        BodyFormat::Json => StatusCode::OK.into_response(),
+        BodyFormat::Form => Redirect::to(&redirects.success_url).into_response(),
     };
 
-    let mut transaction = match pool.begin().await {
-        Ok(transaction) => transaction,
-        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
-    };
+    let outcome = try_subscribe(
+        &pool,
+        &base_url,
+        token_ttl,
+        &headers,
+        form,
+        success_response,
+    )
+    .await;
+
+    match (outcome, format) {
+        (Ok(response), _) => response,
+        (Err(e), BodyFormat::Json) => e.into_response(),
+        (Err(e), BodyFormat::Form) => e.into_redirect(&redirects.error_url),
+    }
+}
+
+/// Returns `success_response`, or the response saved for the same
+/// idempotency key.
+async fn try_subscribe(
+    pool: &PgPool,
+    base_url: &str,
+    token_ttl: chrono::Duration,
+    headers: &HeaderMap,
+    form: FormData,
+    success_response: Response,
+) -> Result<Response, SubscribeError> {
+    let idempotency_key =
+        IdempotencyKey::from_headers(headers).map_err(SubscribeError::InvalidIdempotencyKey)?;
+    let new_subscriber: NewSubscriber = form.try_into().map_err(SubscribeError::ValidationError)?;
 
-    let subscriber_id = match insert_subscriber(&mut transaction, &new_subscriber).await {
-        Ok(subscriber_id) => subscriber_id,
-        Err(_) => {
-            return StatusCode::INTERNAL_SERVER_ERROR;
+    let mut transaction = match &idempotency_key {
+        Some(idempotency_key) => {
+            match try_processing(pool, IDEMPOTENCY_SCOPE, idempotency_key).await? {
+                NextAction::StartProcessing(transaction) => transaction,
+                NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+            }
         }
+        None => pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool")?,
     };
 
-    let subscription_token = generate_subscription_token();
-    if store_token(&mut transaction, subscriber_id, &subscription_token)
+    let subscriber_id = match insert_subscriber(&mut transaction, &new_subscriber)
         .await
-        .is_err()
+        .context("Failed to insert new subscriber in the database")?
     {
-        return StatusCode::INTERNAL_SERVER_ERROR;
-    }
-
-    if transaction.commit().await.is_err() {
-        return StatusCode::INTERNAL_SERVER_ERROR;
-    }
+        Some(subscriber_id) => Some(subscriber_id),
+        None => resubscribe(&mut transaction, &new_subscriber)
+            .await
+            .context("Failed to update an existing subscriber")?,
+    };
 
-    let res =
-        send_confirmation_email(&email_client, new_subscriber, base_url, subscription_token).await;
+    // The response is the same whether or not a confirmation email goes out,
+    // so the endpoint does not tell who is on the list.
+    if let Some(subscriber_id) = subscriber_id {
+        let subscription_token = generate_subscription_token();
+        store_token(
+            &mut transaction,
+            subscriber_id,
+            &subscription_token,
+            token_ttl,
+        )
+        .await
+        .context("Failed to store the confirmation token for a new subscriber")?;
 
-    if res.is_err() {
-        return StatusCode::INTERNAL_SERVER_ERROR;
+        enqueue_confirmation_email(
+            &mut transaction,
+            &new_subscriber.email,
+            base_url,
+            &subscription_token,
+        )
+        .await
+        .context("Failed to queue a confirmation email")?;
     }
 
-    StatusCode::OK
+    let response = success_response;
+    match idempotency_key {
+        Some(idempotency_key) => {
+            Ok(save_response(transaction, IDEMPOTENCY_SCOPE, &idempotency_key, response).await?)
+        }
+        None => {
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit SQL transaction to store a new subscriber")?;
+            Ok(response)
+        }
+    }
 }
 
 #[tracing::instrument(
-    name = "Send a confirmation email to a new subscriber",
-    skip(email_client, new_subscriber, subscription_token)
+    name = "Queue a confirmation email for a new subscriber",
+    skip(transaction, subscriber_email, subscription_token)
 )]
-async fn send_confirmation_email(
-    email_client: &EmailClient,
-    new_subscriber: NewSubscriber,
-    base_url: String,
-    subscription_token: String,
-) -> Result<(), reqwest::Error> {
+pub(crate) async fn enqueue_confirmation_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_email: &SubscriberEmail,
+    base_url: &str,
+    subscription_token: &str,
+) -> Result<(), sqlx::Error> {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, subscription_token,
     );
 
-    email_client
-        .send_email(
-            new_subscriber.email,
-            "Welcome!",
-            &format!(
-                "Welcome to our newsletter!<br />\
-                Click <a href=\"{}\">here</a> to confirm your subscription.",
-                confirmation_link
-            ),
-            &format!(
-                "Welcome to our newsletter!\nVisit {} to confirm your subscription.",
-                confirmation_link
-            ),
-        )
-        .await
+    enqueue_email(
+        transaction,
+        subscriber_email,
+        "Welcome!",
+        &format!(
+            "Welcome to our newsletter!<br />\
+            Click <a href=\"{}\">here</a> to confirm your subscription.",
+            confirmation_link
+        ),
+        &format!(
+            "Welcome to our newsletter!\nVisit {} to confirm your subscription.",
+            confirmation_link
+        ),
+    )
+    .await?;
+
+    Ok(())
 }
 
+/// Every field is validated, so clients learn about all their mistakes at
+/// once.
 impl TryFrom<FormData> for NewSubscriber {
-    type Error = String;
+    type Error = Vec<InvalidParam>;
     fn try_from(form: FormData) -> Result<Self, Self::Error> {
-        let name = SubscriberName::parse(form.name)?;
-        let email = SubscriberEmail::parse(form.email)?;
-        Ok(NewSubscriber { email, name })
+        let mut invalid_params = Vec::new();
+
+        let name = match form.name {
+            Some(name) => SubscriberName::parse(name)
+                .map_err(|e| {
+                    invalid_params.push(InvalidParam {
+                        name: "name",
+                        code: e.code(),
+                        reason: e.to_string(),
+                    })
+                })
+                .ok(),
+            None => {
+                invalid_params.push(InvalidParam::missing("name"));
+                None
+            }
+        };
+        let email = match form.email {
+            Some(email) => SubscriberEmail::parse(email)
+                .map_err(|e| {
+                    invalid_params.push(InvalidParam {
+                        name: "email",
+                        code: e.code(),
+                        reason: e.to_string(),
+                    })
+                })
+                .ok(),
+            None => {
+                invalid_params.push(InvalidParam::missing("email"));
+                None
+            }
+        };
+
+        match (name, email) {
+            (Some(name), Some(email)) => Ok(NewSubscriber { email, name }),
+            _ => Err(invalid_params),
+        }
     }
 }
 
+#[debug_handler]
+pub async fn subscribe_thank_you() -> Html<&'static str> {
+    Html(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Thank you</title>
+</head>
+<body>
+    <p>Thanks for subscribing! Please check your inbox to confirm your subscription.</p>
+</body>
+</html>"#,
+    )
+}
+
+#[debug_handler]
+pub async fn subscribe_error() -> Html<&'static str> {
+    Html(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscription failed</title>
+</head>
+<body>
+    <p>We could not subscribe you. Please check your name and email, then try again.</p>
+</body>
+</html>"#,
+    )
+}
+
+/// Returns `None` if the email is already in `subscriptions`, whatever its
+/// case.
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
     skip(new_subscriber, transaction)
@@ -119,25 +338,66 @@ impl TryFrom<FormData> for NewSubscriber {
 pub async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
-    let subscriber_id = Uuid::new_v4();
-    sqlx::query!(
-        "INSERT INTO subscriptions (id, email, name, subscribed_at, status) VALUES ($1, $2, $3, $4, 'pending_confirmation')",
-        subscriber_id,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let record = sqlx::query!(
+        r#"INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+    VALUES ($1, $2, $3, $4, 'pending_confirmation')
+    ON CONFLICT ((lower(email))) DO NOTHING
+    RETURNING id"#,
+        Uuid::new_v4(),
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
         Utc::now(),
     )
-    .execute(transaction)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
+    .fetch_optional(transaction)
+    .await?;
+
+    Ok(record.map(|r| r.id))
+}
+
+/// Handle a subscription request for an email we already know.
+///
+/// Returns the subscriber to send a confirmation email to, if any:
+/// - `pending_confirmation`: the same subscriber, who gets a new link;
+/// - `confirmed`: nobody, there is nothing left to do;
+/// - `unsubscribed`: the same subscriber, back to a fresh double opt-in;
+/// - `suppressed`: nobody, the address bounced or complained.
+#[tracing::instrument(
+    name = "Handle a subscription request for a known email",
+    skip(new_subscriber, transaction)
+)]
+async fn resubscribe(
+    transaction: &mut Transaction<'_, Postgres>,
+    new_subscriber: &NewSubscriber,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let existing = sqlx::query!(
+        r#"SELECT id, status FROM subscriptions WHERE lower(email) = lower($1) FOR UPDATE"#,
+        new_subscriber.email.as_ref(),
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
 
-    Ok(subscriber_id)
+    match existing.status.as_str() {
+        "confirmed" | "suppressed" => Ok(None),
+        "unsubscribed" => {
+            sqlx::query!(
+                r#"UPDATE subscriptions
+    SET status = 'pending_confirmation', name = $2, subscribed_at = $3, unsubscribed_at = NULL
+    WHERE id = $1"#,
+                existing.id,
+                new_subscriber.name.as_ref(),
+                Utc::now(),
+            )
+            .execute(transaction)
+            .await?;
+            Ok(Some(existing.id))
+        }
+        _ => Ok(Some(existing.id)),
+    }
 }
 
+/// Store a new token for the subscriber, revoking any previous one: only the
+/// link of the latest confirmation email works.
 #[tracing::instrument(
     name = "Store subscription token in the database",
     skip(subscription_token, transaction)
@@ -146,23 +406,29 @@ pub async fn store_token(
     transaction: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
     subscription_token: &str,
+    ttl: chrono::Duration,
 ) -> Result<(), sqlx::Error> {
+    let now = Utc::now();
     sqlx::query!(
-        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id)
-    VALUES ($1, $2)"#,
-        subscription_token,
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
         subscriber_id
     )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"INSERT INTO subscription_tokens (subscription_token_hash, subscriber_id, created_at, expires_at)
+    VALUES ($1, $2, $3, $4)"#,
+        hash_token(subscription_token),
+        subscriber_id,
+        now,
+        now + ttl,
+    )
     .execute(transaction)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
+    .await?;
     Ok(())
 }
 
-fn generate_subscription_token() -> String {
+pub(crate) fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
@@ -1,68 +1,222 @@
-use axum::{extract::Query, response::IntoResponse, Extension};
+use anyhow::Context;
+use axum::{
+    extract::Query,
+    response::{Html, IntoResponse, Response},
+    Extension, Form,
+};
 use axum_macros::debug_handler;
+use chrono::{DateTime, Utc};
 use hyper::StatusCode;
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
+use crate::{
+    domain::SubscriberEmail,
+    routes::{
+        enqueue_confirmation_email, generate_subscription_token, store_token, SubscriptionTokenTtl,
+    },
+    utils::{error_chain_fmt, hash_token},
+};
+
 #[derive(serde::Deserialize)]
 pub struct Parameters {
     subscription_token: String,
 }
 
+struct StoredToken {
+    subscriber_id: Uuid,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(thiserror::Error)]
+pub enum ConfirmError {
+    #[error("The subscription token is unknown.")]
+    UnknownToken,
+    #[error("The subscription token has expired.")]
+    ExpiredToken(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ConfirmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for ConfirmError {
+    fn into_response(self) -> Response {
+        match self {
+            ConfirmError::UnknownToken => {
+                tracing::warn!(error.cause_chain = ?self, error.message = %self);
+                StatusCode::UNAUTHORIZED.into_response()
+            }
+            ConfirmError::ExpiredToken(ref subscription_token) => {
+                tracing::warn!(error.cause_chain = ?self, error.message = %self);
+                expired_link(subscription_token)
+            }
+            ConfirmError::UnexpectedError(_) => {
+                tracing::error!(error.cause_chain = ?self, error.message = %self);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
 #[debug_handler]
 #[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters, pool))]
 pub async fn confirm(
     Query(parameters): Query<Parameters>,
     Extension(pool): Extension<PgPool>,
-) -> impl IntoResponse {
-    let id = match get_subscriber_id_from_token(&pool, parameters.subscription_token).await {
-        Ok(id) => id,
-        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
-    };
-
-    match id {
-        None => return StatusCode::UNAUTHORIZED,
-        Some(id) => {
-            if confirm_subscriber(&pool, id).await.is_err() {
-                return StatusCode::INTERNAL_SERVER_ERROR;
-            }
-
-            StatusCode::OK
-        }
+) -> Result<StatusCode, ConfirmError> {
+    let token = get_token(&pool, &parameters.subscription_token)
+        .await
+        .context("Failed to retrieve the subscriber associated with the provided token")?
+        .ok_or(ConfirmError::UnknownToken)?;
+    if token.expires_at <= Utc::now() {
+        return Err(ConfirmError::ExpiredToken(parameters.subscription_token));
     }
+
+    confirm_subscriber(&pool, token.subscriber_id)
+        .await
+        .context("Failed to mark the subscriber as confirmed")?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Send a fresh confirmation email to the owner of an expired link.
+///
+/// Holding the old link proves the request comes from the subscriber, so
+/// the new email goes to the address it was issued for.
+#[debug_handler]
+#[tracing::instrument(
+    name = "Resend a confirmation email",
+    skip(form, pool, base_url, token_ttl)
+)]
+pub async fn resend_confirmation(
+    Extension(pool): Extension<PgPool>,
+    Extension(base_url): Extension<String>,
+    Extension(SubscriptionTokenTtl(token_ttl)): Extension<SubscriptionTokenTtl>,
+    Form(form): Form<Parameters>,
+) -> Result<Html<&'static str>, ConfirmError> {
+    let token = get_token(&pool, &form.subscription_token)
+        .await
+        .context("Failed to retrieve the subscriber associated with the provided token")?
+        .ok_or(ConfirmError::UnknownToken)?;
+
+    send_new_link(&pool, token.subscriber_id, &base_url, token_ttl)
+        .await
+        .context("Failed to resend a confirmation email")?;
+
+    Ok(Html(
+        "<p>We have sent you a new confirmation link. Please check your inbox.</p>",
+    ))
+}
+
+fn expired_link(subscription_token: &str) -> Response {
+    (
+        StatusCode::GONE,
+        Html(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Link expired</title>
+</head>
+<body>
+    <p>This confirmation link has expired.</p>
+    <form action="/subscriptions/confirm/resend" method="post">
+        <input type="hidden" name="subscription_token" value="{}">
+        <button type="submit">Send me a new link</button>
+    </form>
+</body>
+</html>"#,
+            htmlescape::encode_attribute(subscription_token),
+        )),
+    )
+        .into_response()
 }
 
+/// Confirm the subscriber and consume their token, so the link cannot be
+/// replayed.
+///
+/// Only pending subscribers are confirmed: a link left over from before an
+/// unsubscription or a suppression must not opt them back in.
 #[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, pool))]
 async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
     sqlx::query!(
-        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1;"#,
+        r#"UPDATE subscriptions SET status = 'confirmed'
+    WHERE id = $1 AND status = 'pending_confirmation';"#,
         subscriber_id
     )
-    .execute(pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
+    .execute(&mut transaction)
+    .await?;
+    delete_tokens(&mut transaction, subscriber_id).await?;
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Delete the tokens of a subscriber", skip(transaction))]
+async fn delete_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscriber_id = $1;",
+        subscriber_id
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Send a new confirmation link", skip(pool, base_url, ttl))]
+async fn send_new_link(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    base_url: &str,
+    ttl: chrono::Duration,
+) -> Result<(), anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let subscriber = sqlx::query!(
+        "SELECT email, status FROM subscriptions WHERE id = $1 FOR UPDATE;",
+        subscriber_id
+    )
+    .fetch_one(&mut transaction)
+    .await?;
+    // Subscribers who are no longer pending have no use for a link.
+    if subscriber.status != "pending_confirmation" {
+        return Ok(());
+    }
+    let email = SubscriberEmail::parse(subscriber.email).map_err(anyhow::Error::msg)?;
+
+    let subscription_token = generate_subscription_token();
+    store_token(&mut transaction, subscriber_id, &subscription_token, ttl).await?;
+    enqueue_confirmation_email(&mut transaction, &email, base_url, &subscription_token).await?;
+    transaction.commit().await?;
 
     Ok(())
 }
 
+/// Expired tokens are returned too: they are told apart from unknown ones.
+///
+/// Only digests are stored: the time the lookup by digest takes gives away
+/// nothing about the token itself.
 #[tracing::instrument(name = "Get subscriber_id from token", skip(subscription_token, pool))]
-async fn get_subscriber_id_from_token(
+async fn get_token(
     pool: &PgPool,
-    subscription_token: String,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let record = sqlx::query!(
-        "SELECT subscriber_id FROM subscription_tokens WHERE subscription_token = $1;",
-        subscription_token
+    subscription_token: &str,
+) -> Result<Option<StoredToken>, sqlx::Error> {
+    sqlx::query_as!(
+        StoredToken,
+        r#"SELECT subscriber_id, expires_at
+    FROM subscription_tokens
+    WHERE subscription_token_hash = $1;"#,
+        hash_token(subscription_token)
     )
     .fetch_optional(pool)
     .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
-
-    Ok(record.map(|r| r.subscriber_id))
 }
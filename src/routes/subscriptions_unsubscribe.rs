@@ -0,0 +1,142 @@
+use axum::{
+    extract::Query,
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+    Extension, Form,
+};
+use axum_macros::debug_handler;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::unsubscribe::UnsubscribeLinks;
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeParameters {
+    token: String,
+}
+
+/// Ask for confirmation first: mail scanners follow every link of an email,
+/// and must not unsubscribe people by doing so.
+#[debug_handler]
+pub async fn unsubscribe_form(
+    Query(parameters): Query<UnsubscribeParameters>,
+    Extension(unsubscribe_links): Extension<UnsubscribeLinks>,
+) -> Response {
+    if unsubscribe_links.verify(&parameters.token).is_none() {
+        return invalid_link();
+    }
+
+    Html(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribe</title>
+</head>
+<body>
+    <p>Do you want to stop receiving our newsletter?</p>
+    <form action="/subscriptions/unsubscribe" method="post">
+        <input type="hidden" name="token" value="{}">
+        <button type="submit">Unsubscribe</button>
+    </form>
+</body>
+</html>"#,
+        htmlescape::encode_attribute(&parameters.token),
+    ))
+    .into_response()
+}
+
+#[debug_handler]
+#[tracing::instrument(
+    name = "Unsubscribe a subscriber",
+    skip(form, pool, unsubscribe_links),
+    fields(subscriber_id = tracing::field::Empty)
+)]
+pub async fn unsubscribe(
+    Extension(pool): Extension<PgPool>,
+    Extension(unsubscribe_links): Extension<UnsubscribeLinks>,
+    Form(form): Form<UnsubscribeParameters>,
+) -> Response {
+    let subscriber_id = match unsubscribe_links.verify(&form.token) {
+        Some(subscriber_id) => subscriber_id,
+        None => return invalid_link(),
+    };
+    tracing::Span::current().record("subscriber_id", tracing::field::display(subscriber_id));
+
+    if let Err(e) = mark_subscriber_as_unsubscribed(&pool, subscriber_id).await {
+        tracing::error!(error.cause_chain = ?e, "Failed to unsubscribe a subscriber");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    Html(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribed</title>
+</head>
+<body>
+    <p>You have been unsubscribed. You will not receive any more issues.</p>
+</body>
+</html>"#,
+    )
+    .into_response()
+}
+
+/// RFC 8058 one-click unsubscribe, triggered by mailbox providers.
+///
+/// The token travels in the URL and the body is always
+/// `List-Unsubscribe=One-Click`, so there is nothing else to read: no cookie,
+/// no confirmation page.
+#[debug_handler]
+#[tracing::instrument(
+    name = "One-click unsubscribe",
+    skip(parameters, pool, unsubscribe_links),
+    fields(subscriber_id = tracing::field::Empty)
+)]
+pub async fn unsubscribe_one_click(
+    Query(parameters): Query<UnsubscribeParameters>,
+    Extension(pool): Extension<PgPool>,
+    Extension(unsubscribe_links): Extension<UnsubscribeLinks>,
+) -> StatusCode {
+    let subscriber_id = match unsubscribe_links.verify(&parameters.token) {
+        Some(subscriber_id) => subscriber_id,
+        None => return StatusCode::BAD_REQUEST,
+    };
+    tracing::Span::current().record("subscriber_id", tracing::field::display(subscriber_id));
+
+    match mark_subscriber_as_unsubscribed(&pool, subscriber_id).await {
+        Ok(()) => StatusCode::OK,
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e, "Failed to unsubscribe a subscriber");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+fn invalid_link() -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Html("<p>This unsubscribe link is invalid.</p>"),
+    )
+        .into_response()
+}
+
+/// Unsubscribing twice is not an error: the link stays valid forever, and
+/// the second click has nothing left to do. A suppressed subscriber stays
+/// suppressed: their address must not be written to, whatever they ask.
+#[tracing::instrument(name = "Mark subscriber as unsubscribed", skip(pool))]
+pub(crate) async fn mark_subscriber_as_unsubscribed(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE subscriptions SET status = 'unsubscribed', unsubscribed_at = now()
+    WHERE id = $1 AND status NOT IN ('unsubscribed', 'suppressed')"#,
+        subscriber_id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
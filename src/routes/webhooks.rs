@@ -0,0 +1,118 @@
+use anyhow::Context;
+use axum::{
+    body::Bytes,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use axum_macros::debug_handler;
+use sqlx::PgPool;
+
+use crate::{
+    configurations::EmailProvider,
+    email_events::{record_events, store_events, MailgunWebhook, PostmarkWebhook, SendGridWebhook},
+    utils::error_chain_fmt,
+};
+
+#[derive(thiserror::Error)]
+pub enum WebhookError {
+    #[error("The webhook call is not authentic.")]
+    InvalidSignature(#[source] anyhow::Error),
+    #[error("The webhook payload is invalid.")]
+    InvalidPayload(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for WebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for WebhookError {
+    fn into_response(self) -> Response {
+        match self {
+            WebhookError::InvalidSignature(_) => {
+                tracing::warn!(error.cause_chain = ?self, error.message = %self);
+                StatusCode::UNAUTHORIZED.into_response()
+            }
+            WebhookError::InvalidPayload(_) => {
+                tracing::warn!(error.cause_chain = ?self, error.message = %self);
+                StatusCode::BAD_REQUEST.into_response()
+            }
+            // Providers retry failed calls, so the events are not lost.
+            WebhookError::UnexpectedError(_) => {
+                tracing::error!(error.cause_chain = ?self, error.message = %self);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+#[debug_handler]
+#[tracing::instrument(name = "Receive SendGrid events", skip_all)]
+pub async fn sendgrid_webhook(
+    Extension(pool): Extension<PgPool>,
+    Extension(webhook): Extension<SendGridWebhook>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<StatusCode, WebhookError> {
+    webhook
+        .verify(&headers, &body)
+        .map_err(WebhookError::InvalidSignature)?;
+    let events = SendGridWebhook::parse(&body).map_err(WebhookError::InvalidPayload)?;
+    record_events(&pool, EmailProvider::SendGrid, &events).await?;
+    Ok(StatusCode::OK)
+}
+
+#[debug_handler]
+#[tracing::instrument(name = "Receive Mailgun events", skip_all)]
+pub async fn mailgun_webhook(
+    Extension(pool): Extension<PgPool>,
+    Extension(webhook): Extension<MailgunWebhook>,
+    body: Bytes,
+) -> Result<StatusCode, WebhookError> {
+    let token = webhook
+        .verify(&body)
+        .map_err(WebhookError::InvalidSignature)?;
+    let events = MailgunWebhook::parse(&body).map_err(WebhookError::InvalidPayload)?;
+
+    // The token is only used up along with the events: a call that failed
+    // can be retried.
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    if !token
+        .claim(&mut transaction)
+        .await
+        .context("Failed to claim the token of the call")?
+    {
+        return Err(WebhookError::InvalidSignature(anyhow::anyhow!(
+            "The call was already received"
+        )));
+    }
+    store_events(&mut transaction, EmailProvider::Mailgun, &events).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store email events")?;
+    Ok(StatusCode::OK)
+}
+
+#[debug_handler]
+#[tracing::instrument(name = "Receive Postmark events", skip_all)]
+pub async fn postmark_webhook(
+    Extension(pool): Extension<PgPool>,
+    Extension(webhook): Extension<PostmarkWebhook>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<StatusCode, WebhookError> {
+    webhook
+        .verify(&headers)
+        .map_err(WebhookError::InvalidSignature)?;
+    let events = PostmarkWebhook::parse(&body).map_err(WebhookError::InvalidPayload)?;
+    record_events(&pool, EmailProvider::Postmark, &events).await?;
+    Ok(StatusCode::OK)
+}
@@ -0,0 +1,175 @@
+use std::convert::Infallible;
+
+use anyhow::Context;
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderMap, HeaderValue},
+    response::{IntoResponseParts, ResponseParts},
+    Extension,
+};
+use chrono::Utc;
+use cookie::{Cookie, CookieJar, Key, SameSite};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+const SESSION_COOKIE_NAME: &str = "session_id";
+const SESSION_TTL_HOURS: i64 = 12;
+
+#[derive(Clone)]
+pub struct CookieSettings {
+    /// Signs every cookie we set, so clients cannot tamper with them.
+    pub key: Key,
+    /// Only send cookies over HTTPS.
+    pub secure: bool,
+}
+
+/// The cookies of the current request, as an extractor.
+///
+/// Reads only return cookies whose signature is valid; cookies added or
+/// removed are written back when `SignedCookies` is part of the response.
+pub struct SignedCookies {
+    jar: CookieJar,
+    settings: CookieSettings,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for SignedCookies
+where
+    S: Send + Sync,
+{
+    type Rejection = <Extension<CookieSettings> as FromRequestParts<S>>::Rejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(settings) =
+            Extension::<CookieSettings>::from_request_parts(parts, state).await?;
+        Ok(Self::from_headers(&parts.headers, settings))
+    }
+}
+
+impl SignedCookies {
+    pub fn from_headers(headers: &HeaderMap, settings: CookieSettings) -> Self {
+        let mut jar = CookieJar::new();
+        headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .filter_map(|cookie| Cookie::parse_encoded(cookie.trim().to_owned()).ok())
+            .for_each(|cookie| jar.add_original(cookie));
+
+        Self { jar, settings }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Cookie<'static>> {
+        self.jar.signed(&self.settings.key).get(name)
+    }
+
+    pub fn with_cookie(mut self, mut cookie: Cookie<'static>) -> Self {
+        cookie.set_path("/");
+        cookie.set_http_only(true);
+        cookie.set_same_site(SameSite::Lax);
+        cookie.set_secure(self.settings.secure);
+        self.jar.signed_mut(&self.settings.key).add(cookie);
+        self
+    }
+
+    pub fn without_cookie(mut self, name: &'static str) -> Self {
+        self.jar.remove(Cookie::build(name, "").path("/").finish());
+        self
+    }
+
+    pub fn session_id(&self) -> Option<String> {
+        self.get(SESSION_COOKIE_NAME)
+            .map(|cookie| cookie.value().to_owned())
+    }
+
+    pub fn with_session_id(self, session_id: String) -> Self {
+        self.with_cookie(Cookie::new(SESSION_COOKIE_NAME, session_id))
+    }
+
+    pub fn without_session_id(self) -> Self {
+        self.without_cookie(SESSION_COOKIE_NAME)
+    }
+}
+
+impl IntoResponseParts for SignedCookies {
+    type Error = Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        for cookie in self.jar.delta() {
+            if let Ok(value) = HeaderValue::from_str(&cookie.encoded().to_string()) {
+                res.headers_mut().append(header::SET_COOKIE, value);
+            }
+        }
+        Ok(res)
+    }
+}
+
+#[tracing::instrument(name = "Create a session", skip(pool))]
+pub async fn create_session(pool: &PgPool, user_id: Uuid) -> Result<String, anyhow::Error> {
+    let session_id = generate_session_id();
+    let now = Utc::now();
+    sqlx::query!(
+        r#"INSERT INTO sessions (session_id, user_id, created_at, expires_at)
+    VALUES ($1, $2, $3, $4)"#,
+        session_id,
+        user_id,
+        now,
+        now + chrono::Duration::hours(SESSION_TTL_HOURS),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store a new session.")?;
+
+    Ok(session_id)
+}
+
+#[tracing::instrument(name = "Get the user of a session", skip(pool, session_id))]
+pub async fn get_session_user_id(
+    pool: &PgPool,
+    session_id: &str,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let record = sqlx::query!(
+        r#"SELECT user_id FROM sessions WHERE session_id = $1 AND expires_at > now()"#,
+        session_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve a session.")?;
+
+    Ok(record.map(|r| r.user_id))
+}
+
+#[tracing::instrument(name = "Delete a session", skip(pool, session_id))]
+pub async fn delete_session(pool: &PgPool, session_id: &str) -> Result<(), anyhow::Error> {
+    sqlx::query!(r#"DELETE FROM sessions WHERE session_id = $1"#, session_id)
+        .execute(pool)
+        .await
+        .context("Failed to delete a session.")?;
+
+    Ok(())
+}
+
+/// Log a user out everywhere, e.g. after their password was reset.
+#[tracing::instrument(name = "Delete all sessions of a user", skip(executor))]
+pub async fn delete_user_sessions<'c>(
+    executor: impl PgExecutor<'c>,
+    user_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(r#"DELETE FROM sessions WHERE user_id = $1"#, user_id)
+        .execute(executor)
+        .await
+        .context("Failed to delete the sessions of a user.")?;
+
+    Ok(())
+}
+
+fn generate_session_id() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(64)
+        .collect()
+}
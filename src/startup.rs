@@ -1,22 +1,126 @@
 use std::sync::Arc;
 
 use axum::{
+    middleware,
     routing::{get, post},
     Extension, Router,
 };
-use sqlx::PgPool;
+use cookie::Key;
+use secrecy::ExposeSecret;
+use sqlx::{postgres::PgPoolOptions, PgPool};
 use tower_http::trace::TraceLayer;
 
-use crate::{email_client::EmailClient, routes};
+use crate::{
+    authentication::reject_anonymous_users,
+    configurations::{DatabaseSettings, Settings},
+    email_client::EmailSender,
+    routes,
+    session_state::CookieSettings,
+    unsubscribe::UnsubscribeLinks,
+};
+
+pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
+    PgPoolOptions::new()
+        .acquire_timeout(std::time::Duration::from_secs(2))
+        .connect_lazy_with(configuration.with_db())
+}
 
-pub fn get_app(pool: PgPool, email_client: EmailClient, base_url: String) -> Router {
-    let email_client = Arc::new(email_client);
-    Router::new()
+pub fn get_app(
+    pool: PgPool,
+    configuration: &Settings,
+    email_sender: Arc<dyn EmailSender>,
+) -> Router {
+    let settings = &configuration.application;
+    let base_url = settings.base_url.clone();
+    let cookie_settings = CookieSettings {
+        key: Key::try_from(settings.hmac_secret.expose_secret().as_bytes())
+            .expect("The HMAC secret must be at least 64 bytes long"),
+        secure: base_url.starts_with("https://"),
+    };
+    let unsubscribe_links = UnsubscribeLinks::new(base_url.clone(), settings.hmac_secret.clone());
+    let subscribe_redirects = routes::SubscribeRedirects {
+        success_url: settings.subscribe_success_url.clone(),
+        error_url: settings.subscribe_error_url.clone(),
+    };
+
+    let admin_routes = Router::new()
+        .route("/dashboard", get(routes::admin_dashboard))
+        .route("/logout", post(routes::log_out))
+        .route(
+            "/password",
+            get(routes::change_password_form).post(routes::change_password),
+        )
+        .route(
+            "/suppressions",
+            get(routes::suppressions_page).post(routes::add_suppression),
+        )
+        .route("/suppressions/remove", post(routes::remove_suppression))
+        .route_layer(middleware::from_fn(reject_anonymous_users));
+
+    let mut app = Router::new()
         .route("/health_check", get(routes::health_check))
         .route("/subscribe", post(routes::subscribe))
+        .route("/subscribe/thank-you", get(routes::subscribe_thank_you))
+        .route("/subscribe/error", get(routes::subscribe_error))
         .route("/subscriptions/confirm", get(routes::confirm))
-        .layer(Extension(pool))
-        .layer(Extension(email_client))
+        .route(
+            "/subscriptions/confirm/resend",
+            post(routes::resend_confirmation),
+        )
+        .route(
+            "/subscriptions/unsubscribe",
+            get(routes::unsubscribe_form).post(routes::unsubscribe),
+        )
+        // People who paste the header's URL in a browser get the usual page.
+        .route(
+            "/subscriptions/unsubscribe/one-click",
+            get(routes::unsubscribe_form).post(routes::unsubscribe_one_click),
+        )
+        .route("/newsletters", post(routes::publish_newsletter))
+        .route("/login", get(routes::login_form).post(routes::login))
+        .route(
+            "/password-reset",
+            get(routes::password_reset_request_form).post(routes::request_password_reset),
+        )
+        .route(
+            "/password-reset/confirm",
+            get(routes::password_reset_form).post(routes::reset_password),
+        )
+        .nest("/admin", admin_routes);
+    if let Some(webhook) = configuration.webhooks.sendgrid() {
+        app = app.route(
+            "/webhooks/sendgrid",
+            post(routes::sendgrid_webhook).layer(Extension(webhook)),
+        );
+    }
+    if let Some(webhook) = configuration.webhooks.mailgun() {
+        app = app.route(
+            "/webhooks/mailgun",
+            post(routes::mailgun_webhook).layer(Extension(webhook)),
+        );
+    }
+    if let Some(webhook) = configuration.webhooks.postmark() {
+        app = app.route(
+            "/webhooks/postmark",
+            post(routes::postmark_webhook).layer(Extension(webhook)),
+        );
+    }
+    if let Some(directory) = configuration.email_client.dev_mailbox() {
+        tracing::warn!("Serving the emails of {:?} at /dev/mailbox", directory);
+        app = app
+            .route("/dev/mailbox", get(routes::dev_mailbox))
+            .route("/dev/mailbox/:id", get(routes::dev_mailbox_message))
+            .layer(Extension(routes::MailboxDirectory(directory)));
+    }
+
+    app.layer(Extension(pool))
+        .layer(Extension(email_sender))
+        .layer(Extension(cookie_settings))
+        .layer(Extension(unsubscribe_links))
+        .layer(Extension(routes::SubscriptionTokenTtl(
+            settings.subscription_token_ttl(),
+        )))
+        .layer(Extension(subscribe_redirects))
         .layer(Extension(base_url))
         .layer(TraceLayer::new_for_http())
 }
@@ -0,0 +1,116 @@
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
+
+/// Where manual entries come from. Other entries name the provider that
+/// reported the address.
+pub const ADMIN_SOURCE: &str = "admin";
+
+/// An address no email is ever sent to.
+pub struct Suppression {
+    pub email: String,
+    /// E.g. `hard_bounce`, or the note of an admin.
+    pub reason: String,
+    pub source: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Add `email` to the suppression list, keeping the first entry if it is
+/// already there, and suppress the matching subscriber. Their pending
+/// confirmation links stop working.
+///
+/// Returns whether the address was not suppressed yet.
+#[tracing::instrument(name = "Suppress an address", skip(transaction, email))]
+pub async fn suppress(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+    reason: &str,
+    source: &str,
+) -> Result<bool, sqlx::Error> {
+    let inserted = sqlx::query!(
+        r#"INSERT INTO suppressions (email, reason, source, created_at)
+    VALUES ($1, $2, $3, now())
+    ON CONFLICT ((lower(email))) DO NOTHING"#,
+        email,
+        reason,
+        source,
+    )
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected()
+        == 1;
+    sqlx::query!(
+        r#"UPDATE subscriptions SET status = 'suppressed', suppressed_at = now()
+    WHERE lower(email) = lower($1) AND status <> 'suppressed'"#,
+        email,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens
+    WHERE subscriber_id IN (SELECT id FROM subscriptions WHERE lower(email) = lower($1))"#,
+        email,
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(inserted)
+}
+
+/// Remove `email` from the suppression list. Its subscriber, if any, is
+/// left unsubscribed: they have to opt in again.
+///
+/// Returns whether the address was suppressed.
+#[tracing::instrument(name = "Lift the suppression of an address", skip(transaction, email))]
+pub async fn unsuppress(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+) -> Result<bool, sqlx::Error> {
+    let deleted = sqlx::query!(
+        r#"DELETE FROM suppressions WHERE lower(email) = lower($1)"#,
+        email
+    )
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected()
+        == 1;
+    sqlx::query!(
+        r#"UPDATE subscriptions SET status = 'unsubscribed', suppressed_at = NULL
+    WHERE lower(email) = lower($1) AND status = 'suppressed'"#,
+        email,
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(deleted)
+}
+
+/// The suppression list, newest first.
+pub async fn list_suppressions(pool: &PgPool) -> Result<Vec<Suppression>, sqlx::Error> {
+    sqlx::query_as!(
+        Suppression,
+        r#"SELECT email, reason, source, created_at FROM suppressions ORDER BY created_at DESC"#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// The indices of the suppressed addresses among `emails`, in ascending order.
+///
+/// Addresses are compared with Postgres' `lower()`, as the unique index on
+/// the suppression list is.
+pub async fn suppressed_among(
+    executor: impl PgExecutor<'_>,
+    emails: &[String],
+) -> Result<Vec<usize>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"SELECT e.position AS "position!"
+    FROM unnest($1::TEXT[]) WITH ORDINALITY AS e (email, position)
+    WHERE EXISTS (SELECT 1 FROM suppressions s WHERE lower(s.email) = lower(e.email))
+    ORDER BY e.position"#,
+        emails
+    )
+    .fetch_all(executor)
+    .await?;
+
+    Ok(rows.into_iter().map(|r| r.position as usize - 1).collect())
+}
@@ -0,0 +1,119 @@
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Keeps the signatures of unsubscribe tokens apart from anything else signed
+/// with the same secret.
+const SIGNATURE_CONTEXT: &[u8] = b"unsubscribe:";
+
+/// Builds and checks the unsubscribe links of newsletter emails.
+///
+/// A token is the subscriber id followed by an HMAC of it, so links need no
+/// storage and cannot be forged to unsubscribe somebody else.
+#[derive(Clone)]
+pub struct UnsubscribeLinks {
+    base_url: String,
+    hmac_secret: Secret<String>,
+}
+
+impl UnsubscribeLinks {
+    pub fn new(base_url: String, hmac_secret: Secret<String>) -> Self {
+        Self {
+            base_url,
+            hmac_secret,
+        }
+    }
+
+    pub fn link_for(&self, subscriber_id: Uuid) -> String {
+        format!(
+            "{}/subscriptions/unsubscribe?token={}",
+            self.base_url,
+            self.token_for(subscriber_id)
+        )
+    }
+
+    /// The target of the `List-Unsubscribe` header: a `POST` to it
+    /// unsubscribes right away, without a confirmation page.
+    pub fn one_click_link_for(&self, subscriber_id: Uuid) -> String {
+        format!(
+            "{}/subscriptions/unsubscribe/one-click?token={}",
+            self.base_url,
+            self.token_for(subscriber_id)
+        )
+    }
+
+    pub fn token_for(&self, subscriber_id: Uuid) -> String {
+        let signature = self.mac(subscriber_id).finalize().into_bytes();
+        format!(
+            "{}.{}",
+            subscriber_id.simple(),
+            base64::encode_config(signature, base64::URL_SAFE_NO_PAD)
+        )
+    }
+
+    /// Returns the subscriber a token was issued for, if its signature is
+    /// valid.
+    pub fn verify(&self, token: &str) -> Option<Uuid> {
+        let (subscriber_id, signature) = token.split_once('.')?;
+        let subscriber_id = Uuid::try_parse(subscriber_id).ok()?;
+        let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD).ok()?;
+        self.mac(subscriber_id)
+            .verify_slice(&signature)
+            .ok()
+            .map(|_| subscriber_id)
+    }
+
+    fn mac(&self, subscriber_id: Uuid) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(self.hmac_secret.expose_secret().as_bytes())
+            .expect("HMAC can take a key of any size");
+        mac.update(SIGNATURE_CONTEXT);
+        mac.update(subscriber_id.as_bytes());
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    use super::UnsubscribeLinks;
+
+    fn links(secret: &str) -> UnsubscribeLinks {
+        UnsubscribeLinks::new("http://127.0.0.1".into(), Secret::new(secret.into()))
+    }
+
+    #[test]
+    fn a_token_is_verified_as_its_subscriber() {
+        let links = links("secret");
+        let subscriber_id = Uuid::new_v4();
+        let token = links.token_for(subscriber_id);
+        assert_eq!(links.verify(&token), Some(subscriber_id));
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let token = links("another secret").token_for(Uuid::new_v4());
+        assert_eq!(links("secret").verify(&token), None);
+    }
+
+    #[test]
+    fn a_token_for_another_subscriber_is_rejected() {
+        let links = links("secret");
+        let token = links.token_for(Uuid::new_v4());
+        let (_, signature) = token.split_once('.').unwrap();
+        let forged = format!("{}.{}", Uuid::new_v4().simple(), signature);
+        assert_eq!(links.verify(&forged), None);
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        let links = links("secret");
+        for token in ["", ".", "not-a-token", "1234.abcd"] {
+            assert_eq!(links.verify(token), None);
+        }
+    }
+}
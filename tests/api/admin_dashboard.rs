@@ -0,0 +1,51 @@
+use sqlx::PgPool;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+#[sqlx::test]
+async fn you_must_be_logged_in_to_access_the_admin_dashboard(pool: PgPool) {
+    let app = spawn_app(pool).await;
+
+    let response = app.get_admin_dashboard().await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[sqlx::test]
+async fn logout_clears_session_state(pool: PgPool) {
+    let app = spawn_app(pool).await;
+
+    // Login
+    app.login_as_test_user().await;
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+
+    // Logout
+    let response = app.post_logout().await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Follow the redirect
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>You have successfully logged out.</i></p>"));
+
+    // Attempt to load the admin panel
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[sqlx::test]
+async fn a_tampered_session_cookie_is_rejected(pool: PgPool) {
+    let app = spawn_app(pool).await;
+
+    let response = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .get(app.url_for("/admin/dashboard"))
+        .header("Cookie", "session_id=forged-session-id")
+        .send()
+        .await
+        .unwrap();
+
+    assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,81 @@
+use axum_zero2prod::canonical_emails::canonicalize_stored_emails;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+async fn store_subscriber(pool: &PgPool, email: &str) -> sqlx::Result<()> {
+    sqlx::query!(
+        "INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, $2, 'le guin', now(), 'confirmed')",
+        Uuid::new_v4(),
+        email,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn subscriber_emails(pool: &PgPool) -> sqlx::Result<Vec<String>> {
+    Ok(
+        sqlx::query!("SELECT email FROM subscriptions ORDER BY email COLLATE \"C\"")
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .map(|r| r.email)
+            .collect(),
+    )
+}
+
+#[sqlx::test]
+async fn emails_are_rewritten_as_the_application_writes_them(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    store_subscriber(&pool, "ursula@ＥＸＡＭＰＬＥ.com").await?;
+    store_subscriber(&pool, "bob@Bücher.de").await?;
+    store_subscriber(&pool, "ann@example.com").await?;
+    sqlx::query!(
+        "INSERT INTO suppressions (email, reason, source, created_at)
+        VALUES ('spam@Bücher.DE', 'Spam trap', 'admin', now())"
+    )
+    .execute(&pool)
+    .await?;
+
+    // Act
+    let report = canonicalize_stored_emails(&pool).await.unwrap();
+
+    // Assert
+    assert_eq!(report.n_rewritten, 3);
+    assert_eq!(
+        subscriber_emails(&pool).await?,
+        [
+            "ann@example.com",
+            "bob@xn--bcher-kva.de",
+            "ursula@example.com"
+        ]
+    );
+    let suppressed = sqlx::query!("SELECT email FROM suppressions")
+        .fetch_one(&pool)
+        .await?;
+    assert_eq!(suppressed.email, "spam@xn--bcher-kva.de");
+    Ok(())
+}
+
+#[sqlx::test]
+async fn subscribers_that_would_share_an_email_are_reported_and_left_as_is(
+    pool: PgPool,
+) -> sqlx::Result<()> {
+    // Arrange
+    store_subscriber(&pool, "bob@Bücher.de").await?;
+    store_subscriber(&pool, "Bob@xn--bcher-kva.de").await?;
+
+    // Act
+    let report = canonicalize_stored_emails(&pool).await.unwrap();
+
+    // Assert
+    assert_eq!(report.n_rewritten, 0);
+    assert_eq!(report.subscriber_collisions.len(), 1);
+    assert_eq!(report.subscriber_collisions[0].len(), 2);
+    assert_eq!(
+        subscriber_emails(&pool).await?,
+        ["Bob@xn--bcher-kva.de", "bob@Bücher.de"]
+    );
+    Ok(())
+}
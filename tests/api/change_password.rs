@@ -0,0 +1,131 @@
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+#[sqlx::test]
+async fn you_must_be_logged_in_to_see_the_change_password_form(pool: PgPool) {
+    let app = spawn_app(pool).await;
+
+    let response = app
+        .client
+        .get(app.url_for("/admin/password"))
+        .send()
+        .await
+        .unwrap();
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[sqlx::test]
+async fn you_must_be_logged_in_to_change_your_password(pool: PgPool) {
+    let app = spawn_app(pool).await;
+    let new_password = Uuid::new_v4().to_string();
+
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": Uuid::new_v4().to_string(),
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[sqlx::test]
+async fn new_password_fields_must_match(pool: PgPool) {
+    let app = spawn_app(pool).await;
+    app.login_as_test_user().await;
+
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": Uuid::new_v4().to_string(),
+            "new_password_check": Uuid::new_v4().to_string(),
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains(
+        "<p><i>You entered two different new passwords - the field values must match.</i></p>"
+    ));
+}
+
+#[sqlx::test]
+async fn current_password_must_be_valid(pool: PgPool) {
+    let app = spawn_app(pool).await;
+    app.login_as_test_user().await;
+    let new_password = Uuid::new_v4().to_string();
+
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": Uuid::new_v4().to_string(),
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains("<p><i>The current password is incorrect.</i></p>"));
+}
+
+#[sqlx::test]
+async fn new_password_must_follow_the_length_policy(pool: PgPool) {
+    let app = spawn_app(pool).await;
+    app.login_as_test_user().await;
+
+    let test_cases = vec![
+        ("a".repeat(11), "at least 12 characters"),
+        ("a".repeat(129), "at most 128 characters"),
+    ];
+    for (new_password, error_message) in test_cases {
+        let response = app
+            .post_change_password(&serde_json::json!({
+                "current_password": &app.test_user.password,
+                "new_password": &new_password,
+                "new_password_check": &new_password,
+            }))
+            .await;
+        assert_is_redirect_to(&response, "/admin/password");
+
+        let html_page = app.get_change_password_html().await;
+        assert!(
+            html_page.contains(error_message),
+            "The page did not mention `{}`.",
+            error_message
+        );
+    }
+}
+
+#[sqlx::test]
+async fn changing_password_works(pool: PgPool) {
+    let app = spawn_app(pool).await;
+    let new_password = Uuid::new_v4().to_string();
+
+    // Login and change password
+    app.login_as_test_user().await;
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains("<p><i>Your password has been changed.</i></p>"));
+
+    // Logout, then login with the new password
+    app.post_logout().await;
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
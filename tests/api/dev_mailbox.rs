@@ -0,0 +1,112 @@
+use std::path::PathBuf;
+
+use axum_zero2prod::configurations::{EmailProvider, FileTransportSettings};
+use serde_json::json;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::helpers::{fake_email, fake_name, spawn_app, spawn_app_with, TestApp};
+
+async fn spawn_app_with_mailbox(pool: PgPool, serve_mailbox: bool) -> (TestApp, PathBuf) {
+    let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+    let app = spawn_app_with(pool, |c| {
+        c.email_client.provider = EmailProvider::File;
+        c.email_client.file = Some(FileTransportSettings {
+            directory: directory.clone(),
+            serve_mailbox,
+        });
+    })
+    .await;
+
+    (app, directory)
+}
+
+#[sqlx::test]
+async fn the_file_provider_writes_emails_to_the_mailbox(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let (app, directory) = spawn_app_with_mailbox(pool, true).await;
+    let email = fake_email();
+
+    // Act - Part 1 - Subscribe
+    app.post_subscriptions(
+        json!({"name": fake_name(), "email": email})
+            .to_string()
+            .into(),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Act - Part 2 - Browse the mailbox
+    let mailbox = app
+        .client
+        .get(app.url_for("/dev/mailbox"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(mailbox.status().as_u16(), 200);
+    let mailbox = mailbox.text().await.unwrap();
+    assert!(mailbox.contains(&email));
+    assert!(mailbox.contains("Welcome!"));
+
+    // Act - Part 3 - Open the email
+    let message_path = mailbox
+        .split('"')
+        .find(|s| s.starts_with("/dev/mailbox/"))
+        .unwrap();
+    let message = app
+        .client
+        .get(app.url_for(message_path))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(message.contains("/subscriptions/confirm?subscription_token="));
+    let emls = std::fs::read_dir(&directory)
+        .unwrap()
+        .filter(|e| e.as_ref().unwrap().path().extension() == Some("eml".as_ref()))
+        .count();
+    assert_eq!(emls, 1);
+
+    std::fs::remove_dir_all(directory).unwrap();
+    Ok(())
+}
+
+#[sqlx::test]
+async fn the_mailbox_is_not_served_unless_enabled(pool: PgPool) -> sqlx::Result<()> {
+    for app in [
+        spawn_app(pool.clone()).await,
+        spawn_app_with_mailbox(pool, false).await.0,
+    ] {
+        let response = app
+            .client
+            .get(app.url_for("/dev/mailbox"))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status().as_u16(), 404);
+    }
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn unknown_emails_are_not_found(pool: PgPool) -> sqlx::Result<()> {
+    let (app, _) = spawn_app_with_mailbox(pool, true).await;
+
+    let response = app
+        .client
+        .get(app.url_for(&format!("/dev/mailbox/{}", Uuid::new_v4())))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 404);
+    Ok(())
+}
@@ -1,12 +1,15 @@
 use axum::{body::Body, http::StatusCode};
+use axum_zero2prod::configurations::{EmailClientSettings, EmailProvider};
+use serde_json::{json, Value};
 use sqlx::PgPool;
+use wiremock::{
+    matchers::{method, path},
+    Mock, MockServer, ResponseTemplate,
+};
 
-use crate::helpers::spawn_app;
+use crate::helpers::{fake_email, fake_name, spawn_app, spawn_app_with, TestApp};
 
-#[sqlx::test]
-async fn health_check_works(pool: PgPool) -> sqlx::Result<()> {
-    let test_app = spawn_app(pool).await;
-    dbg!(test_app.url_for("/health_check"));
+async fn get_health_check(test_app: &TestApp) -> Value {
     let response = test_app
         .client
         .get(test_app.url_for("/health_check"))
@@ -16,9 +19,78 @@ async fn health_check_works(pool: PgPool) -> sqlx::Result<()> {
         .expect("Failed to execute request.");
 
     assert_eq!(response.status(), StatusCode::OK);
+    response.json().await.unwrap()
+}
+
+#[sqlx::test]
+async fn health_check_works(pool: PgPool) -> sqlx::Result<()> {
+    let test_app = spawn_app(pool).await;
+
+    assert_eq!(get_health_check(&test_app).await, json!({"status": "ok"}));
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn emails_fail_over_to_the_secondary_provider(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let secondary_server = MockServer::start().await;
+    let secondary_uri = secondary_server.uri();
+    let test_app = spawn_app_with(pool, |c| {
+        c.email_client.circuit_breaker.failure_threshold = 2;
+        c.email_client.secondary = Some(Box::new(EmailClientSettings {
+            provider: EmailProvider::Postmark,
+            base_url: secondary_uri,
+            sender_email: c.email_client.sender_email.clone(),
+            authorization_token: c.email_client.authorization_token.clone(),
+            timeout_milliseconds: 200,
+            retry: Default::default(),
+            smtp: None,
+            file: None,
+            secondary: None,
+            circuit_breaker: Default::default(),
+            smtputf8: false,
+        }));
+    })
+    .await;
+    assert_eq!(
+        get_health_check(&test_app).await,
+        json!({"status": "ok", "email_circuit": "closed"})
+    );
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(3)
+        .mount(&secondary_server)
+        .await;
+    // Once the breaker is open, the primary provider is left alone.
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(2)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    for _ in 0..3 {
+        test_app
+            .post_subscriptions(
+                json!({"name": fake_name(), "email": fake_email()})
+                    .to_string()
+                    .into(),
+            )
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+    test_app.dispatch_all_pending_emails().await;
 
-    let content_length_header = response.headers().get("content-length");
-    assert_eq!("0", content_length_header.unwrap());
+    // Assert
+    assert_eq!(
+        get_health_check(&test_app).await,
+        json!({"status": "degraded", "email_circuit": "open"})
+    );
 
     Ok(())
 }
@@ -1,19 +1,31 @@
-use std::net::{SocketAddr, TcpListener};
+use std::{
+    net::{SocketAddr, TcpListener},
+    sync::Arc,
+};
 
 use axum::http;
 use axum_zero2prod::{
-    configurations::get_configuration,
-    email_client::EmailClient,
+    authentication::compute_password_hash,
+    configurations::{get_configuration, EmailProvider, Settings},
+    email_client::{EmailSender, SuppressionCheck},
+    email_outbox::try_dispatch_email,
+    issue_delivery_worker::{try_execute_task, ExecutionOutcome},
     startup::get_app,
     telemetry::{get_subscriber, init_subscriber},
+    unsubscribe::UnsubscribeLinks,
 };
 use fake::{faker::internet::raw::SafeEmail, locales};
 use fake::{faker::name::raw::*, Fake};
-use hyper::Body;
 use once_cell::sync::Lazy;
 use reqwest::Url;
+use secrecy::{ExposeSecret, Secret};
+use serde_json::json;
 use sqlx::PgPool;
-use wiremock::MockServer;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, MockServer, ResponseTemplate,
+};
 
 static TRACING: Lazy<()> = Lazy::new(|| {
     if std::env::var("TEST_LOG").is_ok() {
@@ -25,8 +37,44 @@ static TRACING: Lazy<()> = Lazy::new(|| {
     }
 });
 
+pub struct TestUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub email: String,
+    pub password: String,
+}
+
+impl TestUser {
+    pub fn generate() -> Self {
+        Self {
+            user_id: Uuid::new_v4(),
+            username: Uuid::new_v4().to_string(),
+            email: fake_email(),
+            password: Uuid::new_v4().to_string(),
+        }
+    }
+
+    async fn store(&self, pool: &PgPool) {
+        let password_hash = compute_password_hash(Secret::new(self.password.clone())).unwrap();
+        sqlx::query!(
+            "INSERT INTO users (user_id, username, email, password_hash) VALUES ($1, $2, $3, $4)",
+            self.user_id,
+            self.username,
+            self.email,
+            password_hash.expose_secret(),
+        )
+        .execute(pool)
+        .await
+        .expect("Failed to store test user.");
+    }
+}
+
 pub struct TestApp {
     pub client: reqwest::Client,
+    pub db_pool: PgPool,
+    pub email_sender: Arc<dyn EmailSender>,
+    pub unsubscribe_links: UnsubscribeLinks,
+    pub test_user: TestUser,
     pub email_server: MockServer,
     pub addr: SocketAddr,
     pub port: u16,
@@ -42,12 +90,244 @@ impl TestApp {
         self.client
             .post(self.url_for("/subscribe"))
             .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
-            .body(Body::from(body))
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_subscriptions_form<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.client
+            .post(self.url_for("/subscribe"))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_subscriptions_with_idempotency_key(
+        &self,
+        body: String,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        self.client
+            .post(self.url_for("/subscribe"))
+            .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .header("Idempotency-Key", idempotency_key)
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_newsletters_with_idempotency_key(
+        &self,
+        body: serde_json::Value,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        self.client
+            .post(self.url_for("/newsletters"))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Idempotency-Key", idempotency_key)
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
+        self.client
+            .post(self.url_for("/newsletters"))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.client
+            .post(self.url_for("/login"))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_login_html(&self) -> String {
+        self.client
+            .get(self.url_for("/login"))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_admin_dashboard(&self) -> reqwest::Response {
+        self.client
+            .get(self.url_for("/admin/dashboard"))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_dashboard_html(&self) -> String {
+        self.get_admin_dashboard().await.text().await.unwrap()
+    }
+
+    pub async fn post_logout(&self) -> reqwest::Response {
+        self.client
+            .post(self.url_for("/admin/logout"))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_change_password_html(&self) -> String {
+        self.client
+            .get(self.url_for("/admin/password"))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_change_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.client
+            .post(self.url_for("/admin/password"))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_suppressions(&self) -> reqwest::Response {
+        self.client
+            .get(self.url_for("/admin/suppressions"))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_suppressions_html(&self) -> String {
+        self.get_suppressions().await.text().await.unwrap()
+    }
+
+    pub async fn post_suppression<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.client
+            .post(self.url_for("/admin/suppressions"))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_remove_suppression(&self, email: &str) -> reqwest::Response {
+        self.client
+            .post(self.url_for("/admin/suppressions/remove"))
+            .form(&[("email", email)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_password_reset_html(&self) -> String {
+        self.client
+            .get(self.url_for("/password-reset"))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_password_reset_request(&self, email: &str) -> reqwest::Response {
+        self.client
+            .post(self.url_for("/password-reset"))
+            .form(&serde_json::json!({ "email": email }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_password_reset_form(&self, reset_link: Url) -> reqwest::Response {
+        self.client
+            .get(reset_link)
             .send()
             .await
             .expect("Failed to execute request.")
     }
 
+    pub async fn post_password_reset<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.client
+            .post(self.url_for("/password-reset/confirm"))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Extract the link of an email, pointing it at the test server so it
+    /// shares the cookies of the other requests.
+    pub fn get_link(&self, email_request: &wiremock::Request) -> Url {
+        let link = Url::parse(&self.get_confirmation_links(email_request).plain_text).unwrap();
+        let mut url = self.url_for(link.path());
+        url.set_query(link.query());
+        url
+    }
+
+    pub async fn login_as_test_user(&self) {
+        self.post_login(&serde_json::json!({
+            "username": &self.test_user.username,
+            "password": &self.test_user.password,
+        }))
+        .await;
+    }
+
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                try_dispatch_email(&self.db_pool, self.email_sender.as_ref())
+                    .await
+                    .unwrap()
+            {
+                break;
+            }
+        }
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.db_pool,
+                self.email_sender.as_ref(),
+                &self.unsubscribe_links,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
     pub fn url_for(&self, path: &str) -> Url {
         let mut url = Url::parse(&format!("http://{}", self.addr)).unwrap();
         url.set_path(path);
@@ -66,8 +346,8 @@ impl TestApp {
             links[0].as_str().to_owned()
         };
 
-        let html_link = get_link(&body["content"][0]["value"].as_str().unwrap());
-        let text_link = get_link(&body["content"][1]["value"].as_str().unwrap());
+        let html_link = get_link(body["content"][0]["value"].as_str().unwrap());
+        let text_link = get_link(body["content"][1]["value"].as_str().unwrap());
         ConfirmationLinks {
             html: html_link,
             plain_text: text_link,
@@ -76,6 +356,11 @@ impl TestApp {
 }
 
 pub async fn spawn_app(pool: PgPool) -> TestApp {
+    spawn_app_with(pool, |_| {}).await
+}
+
+/// Spawn the application after `customize` has tweaked its configuration.
+pub async fn spawn_app_with(pool: PgPool, customize: impl FnOnce(&mut Settings)) -> TestApp {
     Lazy::force(&TRACING);
 
     let email_server = MockServer::start().await;
@@ -83,22 +368,21 @@ pub async fn spawn_app(pool: PgPool) -> TestApp {
     let configuration = {
         let mut c = get_configuration().expect("Failed to read configuration.");
 
+        c.email_client.provider = EmailProvider::SendGrid;
         c.email_client.base_url = email_server.uri();
+        c.email_client.timeout_milliseconds = 200;
+        // The outbox and the delivery queue retry on their own; tests count
+        // one call per attempt.
+        c.email_client.retry.max_attempts = 1;
+        customize(&mut c);
 
         c
     };
-    let sender = configuration
-        .email_client
-        .sender()
-        .expect("Invalid sender email address");
-    let email_client = EmailClient::new(
-        configuration.email_client.base_url,
-        sender,
-        configuration.email_client.authorization_token,
-        std::time::Duration::from_millis(200),
-    );
-
-    let app = get_app(pool, email_client, configuration.application.base_url);
+    let email_sender: Arc<dyn EmailSender> = Arc::new(SuppressionCheck::new(
+        configuration.email_client.client(),
+        pool.clone(),
+    ));
+    let app = get_app(pool.clone(), &configuration, email_sender.clone());
 
     let listener = TcpListener::bind("0.0.0.0:0".parse::<SocketAddr>().unwrap()).unwrap();
     let addr = listener.local_addr().unwrap();
@@ -111,14 +395,73 @@ pub async fn spawn_app(pool: PgPool) -> TestApp {
             .unwrap();
     });
 
+    let test_user = TestUser::generate();
+    test_user.store(&pool).await;
+
     TestApp {
+        db_pool: pool,
+        test_user,
+        email_sender,
+        unsubscribe_links: UnsubscribeLinks::new(
+            configuration.application.base_url.clone(),
+            configuration.application.hmac_secret.clone(),
+        ),
         email_server,
         addr,
         port: addr.port(),
-        client: reqwest::Client::new(),
+        client: reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .cookie_store(true)
+            .build()
+            .unwrap(),
     }
 }
 
+/// Use the public API of the application under test to create
+/// an unconfirmed subscriber.
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    let body = json!({"name": fake_name(), "email": fake_email()}).to_string();
+
+    let _mock_guard = Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_links(email_request)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    let confirmation_link = create_unconfirmed_subscriber(app).await.html;
+    let mut confirmation_link = Url::parse(&confirmation_link).unwrap();
+    confirmation_link.set_port(Some(app.port)).unwrap();
+
+    reqwest::get(confirmation_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(response.headers().get("Location").unwrap(), location);
+}
+
 pub fn fake_name() -> String {
     Name(locales::EN).fake()
 }
@@ -0,0 +1,53 @@
+use sqlx::PgPool;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+#[sqlx::test]
+async fn an_error_flash_message_is_set_on_failure(pool: PgPool) {
+    let app = spawn_app(pool).await;
+
+    let login_body = serde_json::json!({
+        "username": "random-username",
+        "password": "random-password"
+    });
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Follow the redirect
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Authentication failed</i></p>"));
+
+    // Reload the login page
+    let html_page = app.get_login_html().await;
+    assert!(!html_page.contains("Authentication failed"));
+}
+
+#[sqlx::test]
+async fn redirect_to_admin_dashboard_after_login_success(pool: PgPool) {
+    let app = spawn_app(pool).await;
+
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[sqlx::test]
+async fn logging_in_again_replaces_the_previous_session(pool: PgPool) {
+    let app = spawn_app(pool).await;
+
+    app.login_as_test_user().await;
+    app.login_as_test_user().await;
+
+    let n_sessions = sqlx::query!("SELECT COUNT(*) AS n FROM sessions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_sessions, Some(1));
+}
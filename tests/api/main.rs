@@ -1,4 +1,14 @@
+mod admin_dashboard;
+mod canonical_emails;
+mod change_password;
+mod dev_mailbox;
 mod health_check;
 mod helpers;
+mod login;
+mod newsletters;
+mod password_reset;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod suppressions;
+mod webhooks;
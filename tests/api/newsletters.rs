@@ -0,0 +1,668 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Mutex,
+};
+
+use axum_zero2prod::{
+    domain::SubscriberEmail,
+    email_client::{EmailError, EmailSender},
+    issue_delivery_worker::try_execute_task,
+};
+use serde_json::json;
+use sqlx::PgPool;
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{
+    create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app, TestUser,
+};
+
+#[sqlx::test]
+async fn newsletters_are_not_delivered_to_unconfirmed_subscribers(
+    pool: PgPool,
+) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool).await;
+    create_unconfirmed_subscriber(&test_app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        // We assert that no request is fired at the email server
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    let response = test_app.post_newsletters(newsletter_request_body).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    // Mock verifies on Drop that we haven't sent the newsletter email
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn an_issue_reaches_all_confirmed_subscribers_in_a_single_call(
+    pool: PgPool,
+) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool.clone()).await;
+    for _ in 0..3 {
+        create_confirmed_subscriber(&test_app).await;
+    }
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    test_app.post_newsletters(newsletter_request_body).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let personalizations = body["personalizations"].as_array().unwrap();
+    assert_eq!(personalizations.len(), 3);
+    let links: std::collections::HashSet<_> = personalizations
+        .iter()
+        .map(|p| p["substitutions"]["{{unsubscribe_link}}"].as_str().unwrap())
+        .collect();
+    assert_eq!(links.len(), 3);
+
+    Ok(())
+}
+
+/// A provider without a bulk API, as Postmark or SMTP.
+#[derive(Default)]
+struct OneByOneSender {
+    calls: AtomicUsize,
+}
+
+#[async_trait::async_trait]
+impl EmailSender for OneByOneSender {
+    async fn send_email(
+        &self,
+        _recipient: &SubscriberEmail,
+        _subject: &str,
+        _html_content: &str,
+        _text_content: &str,
+        _headers: &[(&str, &str)],
+    ) -> Result<(), EmailError> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+#[sqlx::test]
+async fn the_worker_claims_no_more_tasks_than_one_call_of_the_provider_reaches(
+    pool: PgPool,
+) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool.clone()).await;
+    for _ in 0..3 {
+        create_confirmed_subscriber(&test_app).await;
+    }
+    test_app
+        .post_newsletters(json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+    let sender = OneByOneSender::default();
+
+    // Act
+    try_execute_task(&pool, &sender, &test_app.unsubscribe_links)
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(sender.calls.load(Ordering::SeqCst), 1);
+    let queued = sqlx::query!("SELECT count(*) AS \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&pool)
+        .await?;
+    assert_eq!(queued.count, 2);
+
+    Ok(())
+}
+
+/// A provider with a bulk API, keeping the subject of each email it sent.
+#[derive(Default)]
+struct SubjectRecordingSender {
+    subjects: Mutex<Vec<String>>,
+}
+
+#[async_trait::async_trait]
+impl EmailSender for SubjectRecordingSender {
+    async fn send_email(
+        &self,
+        _recipient: &SubscriberEmail,
+        subject: &str,
+        _html_content: &str,
+        _text_content: &str,
+        _headers: &[(&str, &str)],
+    ) -> Result<(), EmailError> {
+        self.subjects.lock().unwrap().push(subject.to_owned());
+        Ok(())
+    }
+
+    fn max_batch_size(&self) -> usize {
+        100
+    }
+}
+
+#[sqlx::test]
+async fn the_worker_claims_the_tasks_of_one_issue_at_a_time(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool.clone()).await;
+    create_confirmed_subscriber(&test_app).await;
+    for title in ["First issue", "Second issue"] {
+        test_app
+            .post_newsletters(json!({
+                "title": title,
+                "content": {
+                    "text": "Newsletter body as plain text",
+                    "html": "<p>Newsletter body as HTML</p>",
+                }
+            }))
+            .await;
+    }
+    let sender = SubjectRecordingSender::default();
+
+    // Act
+    try_execute_task(&pool, &sender, &test_app.unsubscribe_links)
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(sender.subjects.lock().unwrap().len(), 1);
+    let queued = sqlx::query!("SELECT count(*) AS \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&pool)
+        .await?;
+    assert_eq!(queued.count, 1);
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn newsletters_are_delivered_to_confirmed_subscribers(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool).await;
+    create_confirmed_subscriber(&test_app).await;
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    let response = test_app.post_newsletters(newsletter_request_body).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn newsletters_skip_confirmed_subscribers_with_invalid_emails(
+    pool: PgPool,
+) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool.clone()).await;
+    create_confirmed_subscriber(&test_app).await;
+    sqlx::query!(
+        "INSERT INTO subscriptions (id, email, name, subscribed_at, status) \
+        VALUES (gen_random_uuid(), 'not-an-email', 'Broken', now(), 'confirmed')"
+    )
+    .execute(&pool)
+    .await?;
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    let response = test_app.post_newsletters(newsletter_request_body).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn newsletters_returns_422_for_invalid_data(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool).await;
+    let test_cases = vec![
+        (
+            json!({
+                "content": {
+                    "text": "Newsletter body as plain text",
+                    "html": "<p>Newsletter body as HTML</p>",
+                }
+            }),
+            "missing title",
+        ),
+        (json!({"title": "Newsletter!"}), "missing content"),
+    ];
+
+    for (invalid_body, error_message) in test_cases {
+        // Act
+        let response = test_app.post_newsletters(invalid_body).await;
+
+        // Assert
+        assert_eq!(
+            422,
+            response.status().as_u16(),
+            "The API did not fail with 422 Unprocessable Entity when the payload was {}.",
+            error_message
+        );
+    }
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn publishing_enqueues_one_delivery_task_per_confirmed_subscriber(
+    pool: PgPool,
+) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool.clone()).await;
+    create_confirmed_subscriber(&test_app).await;
+    create_confirmed_subscriber(&test_app).await;
+    create_unconfirmed_subscriber(&test_app).await;
+
+    // Act
+    let newsletter_request_body = json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    let response = test_app.post_newsletters(newsletter_request_body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    let queued = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&pool)
+        .await?;
+    assert_eq!(queued.len(), 2);
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn failed_deliveries_are_kept_in_the_queue_and_retried_later(
+    pool: PgPool,
+) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool.clone()).await;
+    create_confirmed_subscriber(&test_app).await;
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    test_app.post_newsletters(newsletter_request_body).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let task = sqlx::query!("SELECT n_retries, execute_after FROM issue_delivery_queue")
+        .fetch_one(&pool)
+        .await?;
+    assert_eq!(task.n_retries, 1);
+    assert!(task.execute_after > chrono::Utc::now());
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn newsletter_creation_is_idempotent(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool.clone()).await;
+    create_confirmed_subscriber(&test_app).await;
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    let newsletter_request_body = json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+
+    // Act - Part 1 - Publish the newsletter
+    let response = test_app
+        .post_newsletters_with_idempotency_key(newsletter_request_body.clone(), &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    // Act - Part 2 - Publish it again with the same key
+    let response = test_app
+        .post_newsletters_with_idempotency_key(newsletter_request_body, &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let issues = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_all(&pool)
+        .await?;
+    assert_eq!(issues.len(), 1);
+    // Mock verifies on Drop that we have sent the newsletter email **once**
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn concurrent_newsletter_submissions_are_handled_gracefully(
+    pool: PgPool,
+) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool.clone()).await;
+    create_confirmed_subscriber(&test_app).await;
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    let newsletter_request_body = json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+
+    // Act - Submit two newsletter forms concurrently
+    let response1 = test_app
+        .post_newsletters_with_idempotency_key(newsletter_request_body.clone(), &idempotency_key);
+    let response2 =
+        test_app.post_newsletters_with_idempotency_key(newsletter_request_body, &idempotency_key);
+    let (response1, response2) = tokio::join!(response1, response2);
+
+    // Assert
+    assert_eq!(response1.status(), response2.status());
+    assert_eq!(
+        response1.text().await.unwrap(),
+        response2.text().await.unwrap()
+    );
+    test_app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we have sent the newsletter email **once**
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn an_idempotency_key_used_on_another_endpoint_is_not_replayed(
+    pool: PgPool,
+) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool.clone()).await;
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    let body = json!({"name": "le guin", "email": "ursula_le_guin@gmail.com"});
+    let response = test_app
+        .post_subscriptions_with_idempotency_key(body.to_string(), &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act
+    let response = test_app
+        .post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key)
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    let issues = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_all(&pool)
+        .await?;
+    assert_eq!(issues.len(), 1);
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn an_expired_idempotency_key_can_be_used_again(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool.clone()).await;
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    test_app
+        .post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key)
+        .await;
+    sqlx::query!("UPDATE idempotency SET created_at = now() - interval '2 days'")
+        .execute(&pool)
+        .await?;
+
+    // Act
+    let response = test_app
+        .post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key)
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    let issues = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_all(&pool)
+        .await?;
+    assert_eq!(issues.len(), 2);
+
+    Ok(())
+}
+
+fn newsletter_request_body() -> serde_json::Value {
+    json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+#[sqlx::test]
+async fn requests_missing_authorization_are_rejected(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool).await;
+
+    // Act
+    let response = test_app
+        .client
+        .post(test_app.url_for("/newsletters"))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn non_existing_user_is_rejected(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool).await;
+    // Random credentials
+    let username = uuid::Uuid::new_v4().to_string();
+    let password = uuid::Uuid::new_v4().to_string();
+
+    // Act
+    let response = test_app
+        .client
+        .post(test_app.url_for("/newsletters"))
+        .basic_auth(username, Some(password))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn invalid_password_is_rejected(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool).await;
+    let username = &test_app.test_user.username;
+    // Random password
+    let password = uuid::Uuid::new_v4().to_string();
+    assert_ne!(test_app.test_user.password, password);
+
+    // Act
+    let response = test_app
+        .client
+        .post(test_app.url_for("/newsletters"))
+        .basic_auth(username, Some(password))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn outdated_password_hashes_are_upgraded_on_successful_login(
+    pool: PgPool,
+) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool.clone()).await;
+    let user = TestUser::generate();
+    let salt = argon2::password_hash::SaltString::generate(&mut rand::thread_rng());
+    // Weaker parameters than the ones the application uses
+    let outdated_hash = argon2::Argon2::new(
+        argon2::Algorithm::Argon2id,
+        argon2::Version::V0x13,
+        argon2::Params::new(4096, 1, 1, None).unwrap(),
+    );
+    let outdated_hash =
+        argon2::PasswordHasher::hash_password(&outdated_hash, user.password.as_bytes(), &salt)
+            .unwrap()
+            .to_string();
+    sqlx::query!(
+        "INSERT INTO users (user_id, username, password_hash) VALUES ($1, $2, $3)",
+        user.user_id,
+        user.username,
+        outdated_hash,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Act
+    let response = test_app
+        .client
+        .post(test_app.url_for("/newsletters"))
+        .basic_auth(&user.username, Some(&user.password))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(202, response.status().as_u16());
+    let saved = sqlx::query!(
+        "SELECT password_hash FROM users WHERE user_id = $1",
+        user.user_id
+    )
+    .fetch_one(&pool)
+    .await?;
+    assert_ne!(saved.password_hash, outdated_hash);
+    assert!(saved.password_hash.contains("m=15000,t=2,p=1"));
+
+    Ok(())
+}
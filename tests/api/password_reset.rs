@@ -0,0 +1,178 @@
+use sqlx::PgPool;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{assert_is_redirect_to, fake_email, spawn_app, TestApp};
+
+async fn request_reset_link(app: &TestApp) -> reqwest::Url {
+    let _mock_guard = Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_password_reset_request(&app.test_user.email).await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    app.get_link(email_request)
+}
+
+fn token_of(reset_link: &reqwest::Url) -> String {
+    reset_link
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .map(|(_, value)| value.into_owned())
+        .unwrap()
+}
+
+#[sqlx::test]
+async fn requesting_a_reset_sends_a_link_to_the_admin(pool: PgPool) {
+    let app = spawn_app(pool).await;
+
+    let reset_link = request_reset_link(&app).await;
+
+    let response = app.get_password_reset_form(reset_link).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("new_password"));
+}
+
+#[sqlx::test]
+async fn the_email_of_the_admin_is_matched_whatever_its_case(pool: PgPool) {
+    let app = spawn_app(pool).await;
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_password_reset_request(&app.test_user.email.to_uppercase())
+        .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[sqlx::test]
+async fn the_response_does_not_reveal_whether_an_account_exists(pool: PgPool) {
+    let app = spawn_app(pool).await;
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_password_reset_request(&fake_email()).await;
+    assert_is_redirect_to(&response, "/password-reset");
+    app.dispatch_all_pending_emails().await;
+
+    let html_page = app.get_password_reset_html().await;
+    assert!(html_page.contains("If an account uses this email"));
+}
+
+#[sqlx::test]
+async fn a_reset_link_changes_the_password_and_logs_out_everywhere(pool: PgPool) {
+    let app = spawn_app(pool).await;
+    app.login_as_test_user().await;
+    let new_password = Uuid::new_v4().to_string();
+
+    let reset_link = request_reset_link(&app).await;
+    let response = app
+        .post_password_reset(&serde_json::json!({
+            "token": token_of(&reset_link),
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+
+    // The session opened before the reset is gone
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[sqlx::test]
+async fn a_reset_link_can_only_be_used_once(pool: PgPool) {
+    let app = spawn_app(pool).await;
+    let new_password = Uuid::new_v4().to_string();
+    let reset_link = request_reset_link(&app).await;
+    let body = serde_json::json!({
+        "token": token_of(&reset_link),
+        "new_password": &new_password,
+        "new_password_check": &new_password,
+    });
+
+    let response = app.post_password_reset(&body).await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app.post_password_reset(&body).await;
+    assert_is_redirect_to(&response, "/password-reset");
+    let response = app.get_password_reset_form(reset_link).await;
+    assert_is_redirect_to(&response, "/password-reset");
+}
+
+#[sqlx::test]
+async fn an_expired_reset_link_is_rejected(pool: PgPool) {
+    let app = spawn_app(pool).await;
+    let new_password = Uuid::new_v4().to_string();
+    let reset_link = request_reset_link(&app).await;
+
+    sqlx::query!("UPDATE password_reset_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app
+        .post_password_reset(&serde_json::json!({
+            "token": token_of(&reset_link),
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/password-reset");
+
+    let html_page = app.get_password_reset_html().await;
+    assert!(html_page.contains("This reset link is invalid or has expired."));
+}
+
+#[sqlx::test]
+async fn the_new_password_must_follow_the_length_policy(pool: PgPool) {
+    let app = spawn_app(pool).await;
+    let reset_link = request_reset_link(&app).await;
+    let token = token_of(&reset_link);
+
+    let response = app
+        .post_password_reset(&serde_json::json!({
+            "token": &token,
+            "new_password": "short",
+            "new_password_check": "short",
+        }))
+        .await;
+    assert_is_redirect_to(
+        &response,
+        &format!("/password-reset/confirm?token={}", token),
+    );
+
+    // The token was not consumed
+    let response = app.get_password_reset_form(reset_link).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("at least 12 characters"));
+}
@@ -3,11 +3,13 @@ use axum::body::Body;
 use serde_json::json;
 use sqlx::PgPool;
 use wiremock::{
-    matchers::{method, path},
+    matchers::{any, method, path},
     Mock, ResponseTemplate,
 };
 
-use crate::helpers::{fake_email, fake_name, spawn_app, ConfirmationLinks};
+use crate::helpers::{
+    assert_is_redirect_to, fake_email, fake_name, spawn_app, spawn_app_with, ConfirmationLinks,
+};
 
 #[sqlx::test]
 async fn subscribe_returns_a_200_for_valid_form_data(pool: PgPool) -> sqlx::Result<()> {
@@ -26,6 +28,7 @@ async fn subscribe_returns_a_200_for_valid_form_data(pool: PgPool) -> sqlx::Resu
 
     // Act
     let response = test_app.post_subscriptions(body.into()).await;
+    test_app.dispatch_all_pending_emails().await;
 
     // Assert
     assert_eq!(200, response.status().as_u16());
@@ -63,6 +66,34 @@ async fn subscribe_persists_the_new_subscriber(pool: PgPool) -> sqlx::Result<()>
     Ok(())
 }
 
+#[sqlx::test]
+async fn subscribe_dedupes_emails_whatever_their_case(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool.clone()).await;
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    for email in ["  Ursula@Example.COM ", "ursula@example.com"] {
+        let body = json!({"name": fake_name(), "email": email}).to_string();
+        let response = test_app.post_subscriptions(body.into()).await;
+        assert_eq!(200, response.status().as_u16());
+    }
+
+    // Assert
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_all(&pool)
+        .await?;
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].email, "Ursula@example.com");
+
+    Ok(())
+}
+
 #[sqlx::test]
 async fn subscribe_returns_a_422_when_data_is_missing(pool: PgPool) -> sqlx::Result<()> {
     // Arrange
@@ -128,6 +159,72 @@ async fn subscribe_returns_a_422_when_fields_are_present_but_invalid(
     Ok(())
 }
 
+#[sqlx::test]
+async fn subscribe_reports_every_invalid_field_as_problem_json(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool).await;
+    let body = json!({"name": "a".repeat(257), "email": "not-an-email"}).to_string();
+
+    // Act
+    let response = test_app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(422, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
+    );
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["status"], 422);
+    let codes: Vec<_> = problem["invalid-params"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| (p["name"].as_str().unwrap(), p["code"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        codes,
+        vec![("name", "name_too_long"), ("email", "missing_at_sign")]
+    );
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn subscribe_reports_missing_and_forbidden_fields(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool).await;
+    let test_cases = vec![
+        (json!({"email": fake_email()}), "name", "missing"),
+        (json!({"name": fake_name()}), "email", "missing"),
+        (
+            json!({"name": "<script>", "email": fake_email()}),
+            "name",
+            "forbidden_character",
+        ),
+        (
+            json!({"name": " ", "email": fake_email()}),
+            "name",
+            "name_empty",
+        ),
+    ];
+
+    for (body, field, code) in test_cases {
+        // Act
+        let response = test_app.post_subscriptions(body.to_string().into()).await;
+
+        // Assert
+        assert_eq!(422, response.status().as_u16());
+        let problem: serde_json::Value = response.json().await.unwrap();
+        let invalid_params = problem["invalid-params"].as_array().unwrap();
+        assert_eq!(invalid_params.len(), 1, "Unexpected problem: {}", problem);
+        assert_eq!(invalid_params[0]["name"], field);
+        assert_eq!(invalid_params[0]["code"], code);
+    }
+
+    Ok(())
+}
+
 #[sqlx::test]
 async fn subscribe_sends_a_confirmation_email_for_valid_data(pool: PgPool) -> sqlx::Result<()> {
     let test_app = spawn_app(pool).await;
@@ -140,7 +237,8 @@ async fn subscribe_sends_a_confirmation_email_for_valid_data(pool: PgPool) -> sq
         .mount(&test_app.email_server)
         .await;
     // Act
-    test_app.post_subscriptions(body.into()).await;
+    test_app.post_subscriptions(body).await;
+    test_app.dispatch_all_pending_emails().await;
 
     Ok(())
 }
@@ -157,14 +255,493 @@ async fn subscribe_sends_a_confirmation_email_with_a_link(pool: PgPool) -> sqlx:
         .await;
 
     // Act
-    test_app.post_subscriptions(body.into()).await;
+    test_app.post_subscriptions(body).await;
+    test_app.dispatch_all_pending_emails().await;
 
     // Assert
     let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
 
-    let ConfirmationLinks { html, plain_text } = test_app.get_confirmation_links(&email_request);
+    let ConfirmationLinks { html, plain_text } = test_app.get_confirmation_links(email_request);
 
     assert_eq!(html, plain_text);
 
     Ok(())
 }
+
+#[sqlx::test]
+async fn subscribe_stores_the_confirmation_email_in_the_outbox(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool.clone()).await;
+    let email = fake_email();
+    let body = json!({"name": fake_name(), "email": email}).to_string();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT recipient FROM email_outbox")
+        .fetch_one(&pool)
+        .await
+        .expect("Failed to fetch the outbox.");
+    assert_eq!(saved.recipient, email);
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn subscribe_succeeds_even_if_the_email_provider_is_down(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool.clone()).await;
+    let body = json!({"name": fake_name(), "email": fake_email()}).to_string();
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app.post_subscriptions(body.into()).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT n_retries, execute_after FROM email_outbox")
+        .fetch_one(&pool)
+        .await
+        .expect("Failed to fetch the outbox.");
+    assert_eq!(saved.n_retries, 1);
+    assert!(saved.execute_after > chrono::Utc::now());
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn emails_the_provider_rejects_are_not_sent_again(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool.clone()).await;
+    let body = json!({"name": fake_name(), "email": fake_email()}).to_string();
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(400))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    test_app.post_subscriptions(body.into()).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let pending = sqlx::query!("SELECT count(*) AS \"count!\" FROM email_outbox")
+        .fetch_one(&pool)
+        .await?;
+    assert_eq!(pending.count, 0);
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn subscribe_is_idempotent(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool.clone()).await;
+    let body = json!({"name": fake_name(), "email": fake_email()}).to_string();
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+
+    // Act - Part 1 - Subscribe
+    let response = test_app
+        .post_subscriptions_with_idempotency_key(body.clone(), &idempotency_key)
+        .await;
+    assert_eq!(200, response.status().as_u16());
+
+    // Act - Part 2 - Retry the same request
+    let response = test_app
+        .post_subscriptions_with_idempotency_key(body, &idempotency_key)
+        .await;
+    assert_eq!(200, response.status().as_u16());
+
+    // Assert
+    let subscriptions = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_all(&pool)
+        .await?;
+    assert_eq!(subscriptions.len(), 1);
+    let outbox = sqlx::query!("SELECT id FROM email_outbox")
+        .fetch_all(&pool)
+        .await?;
+    assert_eq!(outbox.len(), 1);
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn concurrent_subscribe_requests_are_handled_gracefully(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool.clone()).await;
+    let body = json!({"name": fake_name(), "email": fake_email()}).to_string();
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+
+    // Act - Submit two requests concurrently
+    let response1 =
+        test_app.post_subscriptions_with_idempotency_key(body.clone(), &idempotency_key);
+    let response2 = test_app.post_subscriptions_with_idempotency_key(body, &idempotency_key);
+    let (response1, response2) = tokio::join!(response1, response2);
+
+    // Assert
+    assert_eq!(response1.status(), response2.status());
+    assert_eq!(
+        response1.text().await.unwrap(),
+        response2.text().await.unwrap()
+    );
+    let subscriptions = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_all(&pool)
+        .await?;
+    assert_eq!(subscriptions.len(), 1);
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn subscribe_returns_a_400_for_an_invalid_idempotency_key(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool).await;
+    let body = json!({"name": fake_name(), "email": fake_email()}).to_string();
+
+    // Act
+    let response = test_app
+        .post_subscriptions_with_idempotency_key(body, &"a".repeat(100))
+        .await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn subscribing_again_while_pending_sends_a_new_link(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool.clone()).await;
+    let body = json!({"name": fake_name(), "email": fake_email()}).to_string();
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    for _ in 0..2 {
+        let response = test_app.post_subscriptions(body.clone().into()).await;
+        assert_eq!(200, response.status().as_u16());
+    }
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_requests = test_app.email_server.received_requests().await.unwrap();
+    let first_link = test_app.get_link(&email_requests[0]);
+    let second_link = test_app.get_link(&email_requests[1]);
+    assert_ne!(first_link, second_link);
+
+    // Only the latest link works
+    let response = reqwest::get(first_link).await.unwrap();
+    assert_eq!(401, response.status().as_u16());
+    let response = reqwest::get(second_link).await.unwrap();
+    assert_eq!(200, response.status().as_u16());
+
+    let subscriptions = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_all(&pool)
+        .await?;
+    assert_eq!(subscriptions.len(), 1);
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn subscribing_again_once_confirmed_succeeds_without_an_email(
+    pool: PgPool,
+) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool.clone()).await;
+    let body = json!({"name": fake_name(), "email": fake_email()}).to_string();
+    test_app.post_subscriptions(body.clone().into()).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'confirmed'")
+        .execute(&pool)
+        .await?;
+    sqlx::query!("DELETE FROM email_outbox")
+        .execute(&pool)
+        .await?;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app.post_subscriptions(body.into()).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&pool)
+        .await?;
+    assert_eq!(saved.status, "confirmed");
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn subscribing_again_after_unsubscribing_restarts_the_opt_in(
+    pool: PgPool,
+) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool.clone()).await;
+    let email = fake_email();
+    let body = json!({"name": fake_name(), "email": email}).to_string();
+    test_app.post_subscriptions(body.into()).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed', unsubscribed_at = now()")
+        .execute(&pool)
+        .await?;
+    sqlx::query!("DELETE FROM email_outbox")
+        .execute(&pool)
+        .await?;
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let new_name = fake_name();
+    let body = json!({"name": new_name, "email": email}).to_string();
+    let response = test_app.post_subscriptions(body.into()).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT name, status, unsubscribed_at FROM subscriptions")
+        .fetch_one(&pool)
+        .await?;
+    assert_eq!(saved.name, new_name);
+    assert_eq!(saved.status, "pending_confirmation");
+    assert!(saved.unsubscribed_at.is_none());
+
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let response = reqwest::get(test_app.get_link(email_request))
+        .await
+        .unwrap();
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&pool)
+        .await?;
+    assert_eq!(saved.status, "confirmed");
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn subscription_tokens_are_not_stored_in_plaintext(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool.clone()).await;
+    let body = json!({"name": fake_name(), "email": fake_email()}).to_string();
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    test_app.post_subscriptions(body.into()).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let token = test_app
+        .get_link(email_request)
+        .query_pairs()
+        .find(|(key, _)| key == "subscription_token")
+        .map(|(_, value)| value.into_owned())
+        .unwrap();
+    let stored = sqlx::query!("SELECT subscription_token_hash FROM subscription_tokens")
+        .fetch_one(&pool)
+        .await?;
+    assert_ne!(stored.subscription_token_hash, token);
+    assert!(!stored.subscription_token_hash.contains(&token));
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn subscribe_fails_if_there_is_a_fatal_database_error(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool.clone()).await;
+    let body = json!({"name": fake_name(), "email": fake_email()}).to_string();
+    // Sabotage the database
+    sqlx::query!("ALTER TABLE subscription_tokens DROP COLUMN subscription_token_hash;")
+        .execute(&pool)
+        .await?;
+
+    // Act
+    let response = test_app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(500, response.status().as_u16());
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn html_form_submissions_are_redirected_to_the_thank_you_page(
+    pool: PgPool,
+) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool.clone()).await;
+    let name = fake_name();
+    let email = fake_email();
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app
+        .post_subscriptions_form(&json!({"name": name, "email": email}))
+        .await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/subscribe/thank-you");
+    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions")
+        .fetch_one(&pool)
+        .await
+        .expect("Failed to fetch saved subscriptions");
+    assert_eq!(saved.email, email);
+    assert_eq!(saved.name, name);
+    assert_eq!(saved.status, "pending_confirmation");
+
+    let response = test_app
+        .client
+        .get(test_app.url_for("/subscribe/thank-you"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn invalid_html_form_submissions_are_redirected_to_the_error_page(
+    pool: PgPool,
+) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool.clone()).await;
+    let test_cases = vec![
+        (
+            json!({"name": "", "email": fake_email()}),
+            "/subscribe/error?name=name_empty",
+        ),
+        (
+            json!({"name": fake_name(), "email": "not-an-email"}),
+            "/subscribe/error?email=missing_at_sign",
+        ),
+        (json!({}), "/subscribe/error?name=missing&email=missing"),
+    ];
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    for (body, location) in test_cases {
+        // Act
+        let response = test_app.post_subscriptions_form(&body).await;
+
+        // Assert
+        assert_is_redirect_to(&response, location);
+    }
+    test_app.dispatch_all_pending_emails().await;
+
+    let saved = sqlx::query!("SELECT COUNT(*) AS count FROM subscriptions")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, Some(0));
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn utf8_mailboxes_are_not_sent_to_providers_without_smtputf8(
+    pool: PgPool,
+) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool.clone()).await;
+    let body = json!({"name": fake_name(), "email": "абонент@пример.рф"}).to_string();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app.post_subscriptions(body.into()).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    // Sending again would not help: the email is dropped.
+    let pending = sqlx::query!("SELECT count(*) AS \"count!\" FROM email_outbox")
+        .fetch_one(&pool)
+        .await?;
+    assert_eq!(pending.count, 0);
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn utf8_mailboxes_are_sent_to_providers_with_smtputf8(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app_with(pool, |c| c.email_client.smtputf8 = true).await;
+    let body = json!({"name": fake_name(), "email": "абонент@пример.рф"}).to_string();
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    test_app.post_subscriptions(body.into()).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(
+        body["personalizations"][0]["to"][0]["email"],
+        "абонент@xn--e1afmkfd.xn--p1ai"
+    );
+
+    Ok(())
+}
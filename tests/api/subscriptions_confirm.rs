@@ -40,10 +40,11 @@ async fn the_link_returned_by_subscribe_returns_a_200_if_called(pool: PgPool) ->
         .mount(&test_app.email_server)
         .await;
 
-    test_app.post_subscriptions(body.into()).await;
+    test_app.post_subscriptions(body).await;
+    test_app.dispatch_all_pending_emails().await;
     let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
 
-    let links = test_app.get_confirmation_links(&email_request);
+    let links = test_app.get_confirmation_links(email_request);
     let raw_confirmation_link = links.html;
     let mut confirmation_link = Url::parse(&raw_confirmation_link).unwrap();
     assert_eq!(confirmation_link.host_str().unwrap(), "127.0.0.1");
@@ -72,10 +73,11 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber(pool: PgPool) -
         .mount(&test_app.email_server)
         .await;
 
-    test_app.post_subscriptions(body.into()).await;
+    test_app.post_subscriptions(body).await;
+    test_app.dispatch_all_pending_emails().await;
     let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
 
-    let raw_confirmation_link = test_app.get_confirmation_links(&email_request).html;
+    let raw_confirmation_link = test_app.get_confirmation_links(email_request).html;
     let mut confirmation_link = Url::parse(&raw_confirmation_link).unwrap();
     confirmation_link.set_port(Some(test_app.port)).unwrap();
 
@@ -96,3 +98,200 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber(pool: PgPool) -
 
     Ok(())
 }
+
+#[sqlx::test]
+async fn a_confirmation_link_can_only_be_used_once(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool.clone()).await;
+    let body = Body::from(json!({"name": fake_name(), "email": fake_email()}).to_string());
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    test_app.post_subscriptions(body).await;
+    test_app.dispatch_all_pending_emails().await;
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_link = test_app.get_link(email_request);
+
+    // Act
+    let first = reqwest::get(confirmation_link.clone()).await.unwrap();
+    let second = reqwest::get(confirmation_link).await.unwrap();
+
+    // Assert
+    assert_eq!(200, first.status().as_u16());
+    assert_eq!(401, second.status().as_u16());
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn a_confirmation_link_does_not_opt_back_in_a_subscriber_who_left(
+    pool: PgPool,
+) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool.clone()).await;
+    let body = Body::from(json!({"name": fake_name(), "email": fake_email()}).to_string());
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    test_app.post_subscriptions(body).await;
+    test_app.dispatch_all_pending_emails().await;
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_link = test_app.get_link(email_request);
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&pool)
+        .await?;
+
+    // Act
+    reqwest::get(confirmation_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&pool)
+        .await?;
+    assert_eq!(saved.status, "unsubscribed");
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn expired_confirmation_links_are_rejected_with_a_410(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool.clone()).await;
+    let body = Body::from(json!({"name": fake_name(), "email": fake_email()}).to_string());
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    test_app.post_subscriptions(body).await;
+    test_app.dispatch_all_pending_emails().await;
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_link = test_app.get_link(email_request);
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&pool)
+        .await?;
+
+    // Act
+    let response = reqwest::get(confirmation_link).await.unwrap();
+
+    // Assert
+    assert_eq!(410, response.status().as_u16());
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains(r#"action="/subscriptions/confirm/resend""#));
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&pool)
+        .await?;
+    assert_eq!(saved.status, "pending_confirmation");
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn an_expired_link_can_be_exchanged_for_a_new_one(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool.clone()).await;
+    let body = Body::from(json!({"name": fake_name(), "email": fake_email()}).to_string());
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&test_app.email_server)
+        .await;
+
+    test_app.post_subscriptions(body).await;
+    test_app.dispatch_all_pending_emails().await;
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let expired_link = test_app.get_link(email_request);
+    let expired_token = expired_link
+        .query_pairs()
+        .find(|(key, _)| key == "subscription_token")
+        .map(|(_, value)| value.into_owned())
+        .unwrap();
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&pool)
+        .await?;
+
+    // Act
+    let response = test_app
+        .client
+        .post(test_app.url_for("/subscriptions/confirm/resend"))
+        .form(&json!({ "subscription_token": expired_token }))
+        .send()
+        .await
+        .unwrap();
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[1];
+    let new_link = test_app.get_link(email_request);
+    assert_ne!(new_link, expired_link);
+
+    let response = reqwest::get(new_link).await.unwrap();
+    assert_eq!(200, response.status().as_u16());
+    let response = reqwest::get(expired_link).await.unwrap();
+    assert_eq!(401, response.status().as_u16());
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn resending_with_an_unknown_token_is_rejected(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool).await;
+
+    // Act
+    let response = test_app
+        .client
+        .post(test_app.url_for("/subscriptions/confirm/resend"))
+        .form(&json!({ "subscription_token": "unknown-token" }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn confirm_fails_if_there_is_a_fatal_database_error(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let test_app = spawn_app(pool.clone()).await;
+    sqlx::query!("ALTER TABLE subscription_tokens DROP COLUMN subscription_token_hash;")
+        .execute(&pool)
+        .await?;
+
+    // Act
+    let response = test_app
+        .client
+        .get(test_app.url_for("/subscriptions/confirm"))
+        .query(&[("subscription_token", "a-token")])
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(500, response.status().as_u16());
+
+    Ok(())
+}
@@ -0,0 +1,315 @@
+use serde_json::json;
+use sqlx::PgPool;
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+
+/// Publish an issue and return the body of the email it sent.
+async fn publish_and_get_email(app: &TestApp) -> serde_json::Value {
+    let _mock_guard = Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_newsletters(json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    serde_json::from_slice(&email_request.body).unwrap()
+}
+
+/// Publish an issue and return the unsubscribe link of the email it sent.
+///
+/// Issues are sent in batches: the link is substituted for each subscriber.
+async fn get_unsubscribe_link(app: &TestApp) -> reqwest::Url {
+    let body = publish_and_get_email(app).await;
+    let text = body["content"][0]["value"].as_str().unwrap();
+    let placeholder = "{{unsubscribe_link}}";
+    assert!(text.contains(placeholder));
+    let link = body["personalizations"][0]["substitutions"][placeholder]
+        .as_str()
+        .expect("The newsletter has no unsubscribe link");
+    assert!(link.contains("/subscriptions/unsubscribe"));
+
+    to_test_server(app, link)
+}
+
+fn to_test_server(app: &TestApp, link: &str) -> reqwest::Url {
+    let link = reqwest::Url::parse(link).unwrap();
+    let mut url = app.url_for(link.path());
+    url.set_query(link.query());
+    url
+}
+
+fn token_of(link: &reqwest::Url) -> String {
+    link.query_pairs()
+        .find(|(key, _)| key == "token")
+        .map(|(_, value)| value.into_owned())
+        .unwrap()
+}
+
+async fn subscriber_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+#[sqlx::test]
+async fn newsletter_emails_carry_an_unsubscribe_link(pool: PgPool) {
+    let app = spawn_app(pool).await;
+    create_confirmed_subscriber(&app).await;
+
+    let link = get_unsubscribe_link(&app).await;
+
+    let response = app.client.get(link).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("Unsubscribe"));
+    // Opening the link alone does not unsubscribe anybody
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+}
+
+#[sqlx::test]
+async fn confirming_unsubscribes_the_subscriber(pool: PgPool) {
+    let app = spawn_app(pool).await;
+    create_confirmed_subscriber(&app).await;
+    let link = get_unsubscribe_link(&app).await;
+
+    let response = app
+        .client
+        .post(app.url_for("/subscriptions/unsubscribe"))
+        .form(&json!({ "token": token_of(&link) }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "unsubscribed");
+}
+
+#[sqlx::test]
+async fn unsubscribing_twice_is_fine(pool: PgPool) {
+    let app = spawn_app(pool).await;
+    create_confirmed_subscriber(&app).await;
+    let link = get_unsubscribe_link(&app).await;
+
+    for _ in 0..2 {
+        let response = app
+            .client
+            .post(app.url_for("/subscriptions/unsubscribe"))
+            .form(&json!({ "token": token_of(&link) }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    assert_eq!(subscriber_status(&app).await, "unsubscribed");
+}
+
+#[sqlx::test]
+async fn unsubscribing_leaves_a_suppressed_subscriber_suppressed(pool: PgPool) {
+    let app = spawn_app(pool).await;
+    create_confirmed_subscriber(&app).await;
+    let link = get_unsubscribe_link(&app).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'suppressed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app
+        .client
+        .post(app.url_for("/subscriptions/unsubscribe"))
+        .form(&json!({ "token": token_of(&link) }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "suppressed");
+}
+
+#[sqlx::test]
+async fn unsubscribed_subscribers_do_not_receive_newsletters(pool: PgPool) {
+    let app = spawn_app(pool).await;
+    create_confirmed_subscriber(&app).await;
+    let link = get_unsubscribe_link(&app).await;
+    app.client
+        .post(app.url_for("/subscriptions/unsubscribe"))
+        .form(&json!({ "token": token_of(&link) }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+}
+
+#[sqlx::test]
+async fn pending_deliveries_are_dropped_after_unsubscribing(pool: PgPool) {
+    let app = spawn_app(pool).await;
+    create_confirmed_subscriber(&app).await;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+
+    // Publish, but unsubscribe before the worker gets to the task
+    app.post_newsletters(json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.client
+        .post(app.url_for("/subscriptions/unsubscribe"))
+        .form(&json!({ "token": app.unsubscribe_links.token_for(subscriber_id) }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let n_tasks = sqlx::query!("SELECT COUNT(*) AS n FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_tasks, Some(0));
+}
+
+#[sqlx::test]
+async fn forged_tokens_are_rejected(pool: PgPool) {
+    let app = spawn_app(pool).await;
+    create_confirmed_subscriber(&app).await;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+    let forged_token = format!("{}.not-a-signature", subscriber_id.simple());
+
+    let response = app
+        .client
+        .get(app.url_for("/subscriptions/unsubscribe"))
+        .query(&[("token", &forged_token)])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = app
+        .client
+        .post(app.url_for("/subscriptions/unsubscribe"))
+        .form(&json!({ "token": &forged_token }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+}
+
+#[sqlx::test]
+async fn newsletter_emails_carry_one_click_unsubscribe_headers(pool: PgPool) {
+    let app = spawn_app(pool).await;
+    create_confirmed_subscriber(&app).await;
+
+    let body = publish_and_get_email(&app).await;
+
+    let headers = &body["personalizations"][0]["headers"];
+    let list_unsubscribe = headers["List-Unsubscribe"].as_str().unwrap();
+    assert!(list_unsubscribe.starts_with('<') && list_unsubscribe.ends_with('>'));
+    assert!(list_unsubscribe.contains("/subscriptions/unsubscribe/one-click?token="));
+    assert_eq!(
+        headers["List-Unsubscribe-Post"],
+        "List-Unsubscribe=One-Click"
+    );
+}
+
+#[sqlx::test]
+async fn one_click_unsubscribe_works_without_cookies_or_confirmation(pool: PgPool) {
+    let app = spawn_app(pool).await;
+    create_confirmed_subscriber(&app).await;
+    let body = publish_and_get_email(&app).await;
+    let list_unsubscribe = body["personalizations"][0]["headers"]["List-Unsubscribe"]
+        .as_str()
+        .unwrap();
+    let one_click_link = to_test_server(&app, list_unsubscribe.trim_matches(&['<', '>'][..]));
+
+    // What a mailbox provider sends: a bare POST, nothing else
+    let response = reqwest::Client::new()
+        .post(one_click_link)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "unsubscribed");
+}
+
+#[sqlx::test]
+async fn one_click_unsubscribe_rejects_forged_tokens(pool: PgPool) {
+    let app = spawn_app(pool).await;
+    create_confirmed_subscriber(&app).await;
+
+    let response = reqwest::Client::new()
+        .post(app.url_for("/subscriptions/unsubscribe/one-click"))
+        .query(&[("token", "forged.token")])
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+}
@@ -0,0 +1,266 @@
+use serde_json::json;
+use sqlx::PgPool;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, fake_email, fake_name, spawn_app, TestApp,
+};
+
+async fn subscriber_emails(app: &TestApp) -> Vec<String> {
+    sqlx::query!("SELECT email FROM subscriptions ORDER BY subscribed_at")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.email)
+        .collect()
+}
+
+async fn subscribe(app: &TestApp, email: &str) {
+    app.post_subscriptions(
+        json!({"name": fake_name(), "email": email})
+            .to_string()
+            .into(),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+}
+
+async fn publish_newsletter(app: &TestApp) {
+    app.post_newsletters(json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+/// Who the newsletter was sent to, sorted.
+async fn newsletter_recipients(app: &TestApp) -> Vec<String> {
+    let mut recipients: Vec<String> = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| serde_json::from_slice::<serde_json::Value>(&request.body).unwrap())
+        .filter(|body| body["subject"] == "Newsletter title")
+        .flat_map(|body| {
+            body["personalizations"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|p| p["to"][0]["email"].as_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    recipients.sort();
+    recipients
+}
+
+#[sqlx::test]
+async fn you_must_be_logged_in_to_manage_suppressions(pool: PgPool) -> sqlx::Result<()> {
+    let app = spawn_app(pool).await;
+
+    assert_is_redirect_to(&app.get_suppressions().await, "/login");
+    let response = app
+        .post_suppression(&json!({"email": fake_email(), "reason": "Legal request"}))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+    assert_is_redirect_to(&app.post_remove_suppression(&fake_email()).await, "/login");
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn suppressed_addresses_are_listed(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.login_as_test_user().await;
+    let email = fake_email();
+
+    // Act - Part 1 - Suppress
+    let response = app
+        .post_suppression(&json!({"email": email, "reason": "Spam trap"}))
+        .await;
+    assert_is_redirect_to(&response, "/admin/suppressions");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_suppressions_html().await;
+    assert!(html_page.contains(&format!("<p><i>{} has been suppressed.</i></p>", email)));
+    assert!(html_page.contains(&format!(
+        "<td>{}</td><td>Spam trap</td><td>admin</td>",
+        email
+    )));
+
+    // Act - Part 3 - Remove
+    let response = app.post_remove_suppression(&email).await;
+    assert_is_redirect_to(&response, "/admin/suppressions");
+
+    // Act - Part 4 - Follow the redirect
+    let html_page = app.get_suppressions_html().await;
+    assert!(html_page.contains(&format!("<p><i>{} is no longer suppressed.</i></p>", email)));
+    assert!(!html_page.contains(&format!("<td>{}</td>", email)));
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn invalid_suppressions_are_rejected(pool: PgPool) -> sqlx::Result<()> {
+    let app = spawn_app(pool).await;
+    app.login_as_test_user().await;
+
+    for (body, message) in [
+        (
+            json!({"email": "not-an-email", "reason": "Legal request"}),
+            "not-an-email is not a valid subscriber email. The email must contain an `@`.",
+        ),
+        (
+            json!({"email": fake_email(), "reason": "  "}),
+            "Please give a reason.",
+        ),
+    ] {
+        let response = app.post_suppression(&body).await;
+        assert_is_redirect_to(&response, "/admin/suppressions");
+
+        let html_page = app.get_suppressions_html().await;
+        assert!(html_page.contains(&format!("<p><i>{}</i></p>", message)));
+        assert!(html_page.contains("No email is sent to these 0 address(es)."));
+    }
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn a_suppressed_address_is_not_sent_a_confirmation_email(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.login_as_test_user().await;
+    let email = fake_email();
+    app.post_suppression(&json!({"email": email, "reason": "Legal request"}))
+        .await;
+
+    Mock::given(path("/mail/send"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    subscribe(&app, &email).await;
+
+    // Assert
+    let pending = sqlx::query!("SELECT count(*) AS \"count!\" FROM email_outbox")
+        .fetch_one(&app.db_pool)
+        .await?;
+    assert_eq!(pending.count, 0);
+    Ok(())
+}
+
+#[sqlx::test]
+async fn a_subscriber_can_opt_in_again_once_the_suppression_is_removed(
+    pool: PgPool,
+) -> sqlx::Result<()> {
+    // Arrange
+    let app = spawn_app(pool).await;
+    app.login_as_test_user().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_emails(&app).await.pop().unwrap();
+    app.post_suppression(&json!({"email": email, "reason": "Legal request"}))
+        .await;
+    app.post_remove_suppression(&email).await;
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    subscribe(&app, &email).await;
+
+    // Assert
+    let status = sqlx::query!("SELECT status FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&app.db_pool)
+        .await?
+        .status;
+    assert_eq!(status, "pending_confirmation");
+    Ok(())
+}
+
+#[sqlx::test]
+async fn newsletters_skip_suppressed_subscribers(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let app = spawn_app(pool).await;
+    for _ in 0..3 {
+        create_confirmed_subscriber(&app).await;
+    }
+    let emails = subscriber_emails(&app).await;
+    // Listed without going through the admin pages, which would also change
+    // the status of the subscriber: only the check at send time stops it.
+    sqlx::query!(
+        "INSERT INTO suppressions (email, reason, source, created_at) VALUES ($1, 'Spam trap', 'admin', now())",
+        emails[1],
+    )
+    .execute(&app.db_pool)
+    .await?;
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.login_as_test_user().await;
+    publish_newsletter(&app).await;
+
+    // Assert
+    let mut expected = vec![emails[0].clone(), emails[2].clone()];
+    expected.sort();
+    assert_eq!(newsletter_recipients(&app).await, expected);
+    Ok(())
+}
+
+#[sqlx::test]
+async fn suppressions_match_whatever_the_case(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let app = spawn_app(pool).await;
+    for _ in 0..4 {
+        create_confirmed_subscriber(&app).await;
+    }
+    let emails = subscriber_emails(&app).await;
+    for email in [emails[0].to_uppercase(), emails[2].to_uppercase()] {
+        sqlx::query!(
+            "INSERT INTO suppressions (email, reason, source, created_at) VALUES ($1, 'Spam trap', 'admin', now())",
+            email,
+        )
+        .execute(&app.db_pool)
+        .await?;
+    }
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.login_as_test_user().await;
+    publish_newsletter(&app).await;
+
+    // Assert
+    let mut expected = vec![emails[1].clone(), emails[3].clone()];
+    expected.sort();
+    assert_eq!(newsletter_recipients(&app).await, expected);
+    Ok(())
+}
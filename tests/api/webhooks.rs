@@ -0,0 +1,387 @@
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use p256::{
+    ecdsa::{signature::Signer, Signature, SigningKey},
+    pkcs8::EncodePublicKey,
+};
+use secrecy::Secret;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use sqlx::PgPool;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{fake_email, fake_name, spawn_app, spawn_app_with, TestApp};
+
+const MAILGUN_SIGNING_KEY: &str = "mailgun-signing-key";
+
+fn sendgrid_signing_key() -> SigningKey {
+    SigningKey::from_bytes(&[7u8; 32].into()).unwrap()
+}
+
+async fn spawn_app_with_webhooks(pool: PgPool) -> TestApp {
+    let der = sendgrid_signing_key()
+        .verifying_key()
+        .to_public_key_der()
+        .unwrap();
+    spawn_app_with(pool, |c| {
+        c.webhooks.sendgrid_verification_key = Some(base64::encode(der.as_bytes()));
+        c.webhooks.mailgun_signing_key = Some(Secret::new(MAILGUN_SIGNING_KEY.into()));
+        c.webhooks.postmark_username = Some("postmark".into());
+        c.webhooks.postmark_password = Some(Secret::new("webhook-password".into()));
+    })
+    .await
+}
+
+async fn subscribe(app: &TestApp) -> String {
+    let email = fake_email();
+    let _mock_guard = Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(
+        json!({"name": fake_name(), "email": email})
+            .to_string()
+            .into(),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    email
+}
+
+async fn subscriber_status(app: &TestApp, email: &str) -> String {
+    sqlx::query!("SELECT status FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+async fn stored_event_types(app: &TestApp) -> Vec<String> {
+    sqlx::query!("SELECT event_type FROM email_events ORDER BY occurred_at")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.event_type)
+        .collect()
+}
+
+async fn post_sendgrid_events(app: &TestApp, events: Value, signing_key: &SigningKey) -> u16 {
+    let body = events.to_string();
+    let timestamp = Utc::now().timestamp().to_string();
+    let signature: Signature = signing_key.sign(format!("{}{}", timestamp, body).as_bytes());
+    app.client
+        .post(app.url_for("/webhooks/sendgrid"))
+        .header("X-Twilio-Email-Event-Webhook-Timestamp", timestamp)
+        .header(
+            "X-Twilio-Email-Event-Webhook-Signature",
+            base64::encode(signature.to_der()),
+        )
+        .body(body)
+        .send()
+        .await
+        .unwrap()
+        .status()
+        .as_u16()
+}
+
+async fn post_mailgun_event(app: &TestApp, event_data: Value) -> u16 {
+    post_mailgun_body(app, &signed_mailgun_body(event_data)).await
+}
+
+/// Mailgun signs every call with a new random token.
+fn signed_mailgun_body(event_data: Value) -> Value {
+    let timestamp = Utc::now().timestamp().to_string();
+    let token = Uuid::new_v4().simple().to_string();
+    let mut mac = Hmac::<Sha256>::new_from_slice(MAILGUN_SIGNING_KEY.as_bytes()).unwrap();
+    mac.update(timestamp.as_bytes());
+    mac.update(token.as_bytes());
+    let signature: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+
+    json!({
+        "signature": {"timestamp": timestamp, "token": token, "signature": signature},
+        "event-data": event_data,
+    })
+}
+
+async fn post_mailgun_body(app: &TestApp, body: &Value) -> u16 {
+    app.client
+        .post(app.url_for("/webhooks/mailgun"))
+        .json(body)
+        .send()
+        .await
+        .unwrap()
+        .status()
+        .as_u16()
+}
+
+async fn post_postmark_event(app: &TestApp, password: &str, event: Value) -> u16 {
+    app.client
+        .post(app.url_for("/webhooks/postmark"))
+        .basic_auth("postmark", Some(password))
+        .json(&event)
+        .send()
+        .await
+        .unwrap()
+        .status()
+        .as_u16()
+}
+
+#[sqlx::test]
+async fn a_sendgrid_hard_bounce_suppresses_the_subscriber(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let app = spawn_app_with_webhooks(pool).await;
+    let bounced = subscribe(&app).await;
+    let delivered = subscribe(&app).await;
+    let event = |email: &str, event: &str, bounce_type: Option<&str>, timestamp: i64| {
+        json!({
+            "email": email,
+            "timestamp": timestamp,
+            "event": event,
+            "type": bounce_type,
+            "sg_event_id": format!("{}-{}", email, event),
+        })
+    };
+
+    // Act
+    let status = post_sendgrid_events(
+        &app,
+        json!([
+            event(&delivered, "processed", None, 1670832000),
+            event(&delivered, "delivered", None, 1670832001),
+            event(&bounced, "bounce", Some("bounce"), 1670832002),
+        ]),
+        &sendgrid_signing_key(),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(status, 200);
+    assert_eq!(stored_event_types(&app).await, ["delivered", "hard_bounce"]);
+    assert_eq!(subscriber_status(&app, &bounced).await, "suppressed");
+    assert_eq!(
+        subscriber_status(&app, &delivered).await,
+        "pending_confirmation"
+    );
+    Ok(())
+}
+
+#[sqlx::test]
+async fn a_sendgrid_call_signed_with_another_key_is_rejected(pool: PgPool) -> sqlx::Result<()> {
+    let app = spawn_app_with_webhooks(pool).await;
+    let email = subscribe(&app).await;
+
+    let status = post_sendgrid_events(
+        &app,
+        json!([{
+            "email": email,
+            "timestamp": 1670832000,
+            "event": "spamreport",
+            "sg_event_id": "spamreport",
+        }]),
+        &SigningKey::from_bytes(&[8u8; 32].into()).unwrap(),
+    )
+    .await;
+
+    assert_eq!(status, 401);
+    assert!(stored_event_types(&app).await.is_empty());
+    assert_eq!(
+        subscriber_status(&app, &email).await,
+        "pending_confirmation"
+    );
+    Ok(())
+}
+
+#[sqlx::test]
+async fn a_mailgun_complaint_suppresses_the_subscriber_once(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let app = spawn_app_with_webhooks(pool).await;
+    let email = subscribe(&app).await;
+    let event_data = json!({
+        "id": "CPgfbmQMTCKtHW6uIWtuVe",
+        "event": "complained",
+        "recipient": email,
+        "timestamp": 1670832000.5,
+    });
+
+    // Act - Mailgun delivers events at least once
+    for _ in 0..2 {
+        assert_eq!(post_mailgun_event(&app, event_data.clone()).await, 200);
+    }
+
+    // Assert
+    assert_eq!(stored_event_types(&app).await, ["spam_report"]);
+    assert_eq!(subscriber_status(&app, &email).await, "suppressed");
+    Ok(())
+}
+
+#[sqlx::test]
+async fn a_replayed_mailgun_call_is_rejected(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let app = spawn_app_with_webhooks(pool).await;
+    let body = signed_mailgun_body(json!({
+        "id": "CPgfbmQMTCKtHW6uIWtuVe",
+        "event": "delivered",
+        "recipient": fake_email(),
+        "timestamp": 1670832000.5,
+    }));
+
+    // Act
+    let first = post_mailgun_body(&app, &body).await;
+    let replayed = post_mailgun_body(&app, &body).await;
+
+    // Assert
+    assert_eq!(first, 200);
+    assert_eq!(replayed, 401);
+    Ok(())
+}
+
+#[sqlx::test]
+async fn a_postmark_soft_bounce_does_not_suppress_the_subscriber(pool: PgPool) -> sqlx::Result<()> {
+    let app = spawn_app_with_webhooks(pool).await;
+    let email = subscribe(&app).await;
+
+    let status = post_postmark_event(
+        &app,
+        "webhook-password",
+        json!({
+            "RecordType": "Bounce",
+            "ID": 42,
+            "Type": "SoftBounce",
+            "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+            "Email": email,
+            "BouncedAt": "2022-12-12T08:00:00Z",
+        }),
+    )
+    .await;
+
+    assert_eq!(status, 200);
+    assert_eq!(stored_event_types(&app).await, ["soft_bounce"]);
+    assert_eq!(
+        subscriber_status(&app, &email).await,
+        "pending_confirmation"
+    );
+    Ok(())
+}
+
+#[sqlx::test]
+async fn postmark_calls_without_the_credentials_are_rejected(pool: PgPool) -> sqlx::Result<()> {
+    let app = spawn_app_with_webhooks(pool).await;
+
+    let status = post_postmark_event(
+        &app,
+        "wrong-password",
+        json!({
+            "RecordType": "Delivery",
+            "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+            "Recipient": fake_email(),
+            "DeliveredAt": "2022-12-12T08:00:00Z",
+        }),
+    )
+    .await;
+
+    assert_eq!(status, 401);
+    Ok(())
+}
+
+#[sqlx::test]
+async fn webhooks_are_not_served_unless_configured(pool: PgPool) -> sqlx::Result<()> {
+    let app = spawn_app(pool).await;
+
+    for provider in ["sendgrid", "mailgun", "postmark"] {
+        let response = app
+            .client
+            .post(app.url_for(&format!("/webhooks/{}", provider)))
+            .json(&json!([]))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status().as_u16(), 404);
+    }
+    Ok(())
+}
+
+#[sqlx::test]
+async fn a_suppressed_subscriber_is_not_sent_a_new_confirmation(pool: PgPool) -> sqlx::Result<()> {
+    // Arrange
+    let app = spawn_app_with_webhooks(pool).await;
+    let email = subscribe(&app).await;
+    post_postmark_event(
+        &app,
+        "webhook-password",
+        json!({
+            "RecordType": "Bounce",
+            "ID": 42,
+            "Type": "HardBounce",
+            "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+            "Email": email,
+            "BouncedAt": "2022-12-12T08:00:00Z",
+        }),
+    )
+    .await;
+    Mock::given(path("/mail/send"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions(
+        json!({"name": fake_name(), "email": email})
+            .to_string()
+            .into(),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(subscriber_status(&app, &email).await, "suppressed");
+    Ok(())
+}
+
+#[sqlx::test]
+async fn a_suppressed_subscriber_cannot_confirm_with_an_earlier_link(
+    pool: PgPool,
+) -> sqlx::Result<()> {
+    // Arrange
+    let app = spawn_app_with_webhooks(pool).await;
+    let email = subscribe(&app).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_link = app.get_link(email_request);
+    post_postmark_event(
+        &app,
+        "webhook-password",
+        json!({
+            "RecordType": "SpamComplaint",
+            "ID": 42,
+            "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+            "Email": email,
+            "BouncedAt": "2022-12-12T08:00:00Z",
+        }),
+    )
+    .await;
+
+    // Act
+    let response = reqwest::get(confirmation_link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(subscriber_status(&app, &email).await, "suppressed");
+    Ok(())
+}